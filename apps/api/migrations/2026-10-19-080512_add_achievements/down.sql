@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "results" DROP COLUMN "placement";
+
+DROP TABLE IF EXISTS "user_achievements";
+DROP TABLE IF EXISTS "achievements";
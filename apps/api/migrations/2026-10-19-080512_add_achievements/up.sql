@@ -0,0 +1,33 @@
+-- Your SQL goes here
+
+CREATE TABLE "achievements"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"code" VARCHAR(64) NOT NULL UNIQUE,
+	"name" VARCHAR NOT NULL,
+	"description" TEXT NOT NULL,
+	"rule" JSONB NOT NULL,
+	"created_at" TIMESTAMP NOT NULL
+);
+
+CREATE TABLE "user_achievements"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"user_id" UUID NOT NULL,
+	"achievement_id" UUID NOT NULL,
+	"result_id" UUID,
+	"unlocked_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("user_id") REFERENCES "users"("id"),
+	FOREIGN KEY ("achievement_id") REFERENCES "achievements"("id"),
+	FOREIGN KEY ("result_id") REFERENCES "results"("id") ON DELETE SET NULL,
+	UNIQUE ("user_id", "achievement_id")
+);
+
+-- Place in the room, filled when the result is stored. Old results have no placement.
+ALTER TABLE "results" ADD COLUMN "placement" INT2;
+
+INSERT INTO "achievements" ("id", "code", "name", "description", "rule", "created_at") VALUES
+	(gen_random_uuid(), 'first_race', 'First steps', 'Finish your first race', '{"type": "races", "count": 1}', NOW()),
+	(gen_random_uuid(), 'races_100', 'Regular', 'Finish 100 races', '{"type": "races", "count": 100}', NOW()),
+	(gen_random_uuid(), 'wpm_100', 'Centurion', 'Reach 100 WPM in a race', '{"type": "min_wpm", "wpm": 100}', NOW()),
+	(gen_random_uuid(), 'races_in_day_10', 'Marathon', 'Finish 10 races in a day', '{"type": "races_in_day", "count": 10}', NOW()),
+	(gen_random_uuid(), 'perfect_300', 'Flawless', 'Finish a text of 300+ characters without mistakes', '{"type": "perfect_accuracy", "min_length": 300}', NOW()),
+	(gen_random_uuid(), 'wins_5', 'Champion', 'Win 5 multiplayer races', '{"type": "multiplayer_wins", "count": 5}', NOW());
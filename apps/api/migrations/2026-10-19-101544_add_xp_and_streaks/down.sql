@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "xp_ledger";
+
+ALTER TABLE "users" DROP COLUMN "xp";
+ALTER TABLE "users" DROP COLUMN "level";
+ALTER TABLE "users" DROP COLUMN "streak_days";
+ALTER TABLE "users" DROP COLUMN "best_streak_days";
+ALTER TABLE "users" DROP COLUMN "last_active_on";
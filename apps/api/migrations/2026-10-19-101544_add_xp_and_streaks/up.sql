@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+ALTER TABLE "users" ADD COLUMN "xp" INT8 NOT NULL DEFAULT 0;
+ALTER TABLE "users" ADD COLUMN "level" INT4 NOT NULL DEFAULT 1;
+ALTER TABLE "users" ADD COLUMN "streak_days" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "users" ADD COLUMN "best_streak_days" INT4 NOT NULL DEFAULT 0;
+ALTER TABLE "users" ADD COLUMN "last_active_on" DATE;
+
+CREATE TABLE "xp_ledger"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"user_id" UUID NOT NULL,
+	"result_id" UUID,
+	"amount" INT4 NOT NULL,
+	"breakdown" JSONB NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("user_id") REFERENCES "users"("id"),
+	FOREIGN KEY ("result_id") REFERENCES "results"("id") ON DELETE SET NULL
+);
+
+CREATE INDEX "xp_ledger_user_id_idx" ON "xp_ledger"("user_id");
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "daily_challenge_attempts";
+DROP TABLE IF EXISTS "daily_challenges";
+
+ALTER TABLE "rooms" DROP COLUMN "kind";
+
+DROP TYPE room_kinds;
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+
+CREATE TYPE room_kinds AS ENUM ('regular', 'daily');
+
+ALTER TABLE "rooms" ADD COLUMN "kind" room_kinds NOT NULL DEFAULT 'regular';
+
+CREATE TABLE "daily_challenges"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"challenge_date" DATE NOT NULL UNIQUE,
+	"text_id" UUID NOT NULL,
+	"chosen_by" UUID,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("text_id") REFERENCES "texts"("id"),
+	FOREIGN KEY ("chosen_by") REFERENCES "users"("id")
+);
+
+CREATE TABLE "daily_challenge_attempts"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"challenge_id" UUID NOT NULL,
+	"user_id" UUID NOT NULL,
+	"result_id" UUID NOT NULL,
+	"ranked" BOOLEAN NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("challenge_id") REFERENCES "daily_challenges"("id"),
+	FOREIGN KEY ("user_id") REFERENCES "users"("id"),
+	FOREIGN KEY ("result_id") REFERENCES "results"("id") ON DELETE CASCADE
+);
+
+-- Only one ranked attempt per user, retries are stored unranked
+CREATE UNIQUE INDEX "daily_challenge_attempts_ranked_idx"
+	ON "daily_challenge_attempts"("challenge_id", "user_id") WHERE "ranked";
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS "tournament_matches";
+DROP TABLE IF EXISTS "tournament_participants";
+DROP TABLE IF EXISTS "tournaments";
+
+DROP TYPE match_statuses;
+DROP TYPE tournament_statuses;
+DROP TYPE tournament_formats;
+
+-- Enum values can't be dropped, recreate the type without 'tournament'
+UPDATE "rooms" SET "kind" = 'regular' WHERE "kind" = 'tournament';
+ALTER TABLE "rooms" ALTER COLUMN "kind" DROP DEFAULT;
+ALTER TYPE room_kinds RENAME TO room_kinds_old;
+CREATE TYPE room_kinds AS ENUM ('regular', 'daily');
+ALTER TABLE "rooms" ALTER COLUMN "kind" TYPE room_kinds USING "kind"::text::room_kinds;
+ALTER TABLE "rooms" ALTER COLUMN "kind" SET DEFAULT 'regular';
+DROP TYPE room_kinds_old;
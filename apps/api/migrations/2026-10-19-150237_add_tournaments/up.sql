@@ -0,0 +1,60 @@
+-- Your SQL goes here
+
+ALTER TYPE room_kinds ADD VALUE 'tournament';
+
+CREATE TYPE tournament_formats AS ENUM ('single_elimination', 'swiss');
+
+CREATE TYPE tournament_statuses AS ENUM ('registration', 'running', 'finished');
+
+CREATE TYPE match_statuses AS ENUM ('pending', 'running', 'finished');
+
+CREATE TABLE "tournaments"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"name" VARCHAR NOT NULL,
+	"format" tournament_formats NOT NULL,
+	"status" tournament_statuses NOT NULL,
+	"dictionary_id" UUID NOT NULL,
+	"rounds" INT4 NOT NULL,
+	"current_round" INT4 NOT NULL,
+	"created_by" UUID NOT NULL,
+	"winner_id" UUID,
+	"created_at" TIMESTAMP NOT NULL,
+	"started_at" TIMESTAMP,
+	"ended_at" TIMESTAMP,
+	FOREIGN KEY ("dictionary_id") REFERENCES "dictionaries"("id"),
+	FOREIGN KEY ("created_by") REFERENCES "users"("id"),
+	FOREIGN KEY ("winner_id") REFERENCES "users"("id")
+);
+
+CREATE TABLE "tournament_participants"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"tournament_id" UUID NOT NULL,
+	"user_id" UUID NOT NULL,
+	"seed" INT4,
+	"score" INT4 NOT NULL,
+	"eliminated" BOOLEAN NOT NULL,
+	"registered_at" TIMESTAMP NOT NULL,
+	FOREIGN KEY ("tournament_id") REFERENCES "tournaments"("id") ON DELETE CASCADE,
+	FOREIGN KEY ("user_id") REFERENCES "users"("id"),
+	UNIQUE ("tournament_id", "user_id")
+);
+
+CREATE TABLE "tournament_matches"(
+	"id" UUID NOT NULL PRIMARY KEY,
+	"tournament_id" UUID NOT NULL,
+	"round" INT4 NOT NULL,
+	"position" INT4 NOT NULL,
+	"player_one_id" UUID NOT NULL,
+	-- Empty for a bye
+	"player_two_id" UUID,
+	"room_id" UUID,
+	"winner_id" UUID,
+	"status" match_statuses NOT NULL,
+	"created_at" TIMESTAMP NOT NULL,
+	"finished_at" TIMESTAMP,
+	FOREIGN KEY ("tournament_id") REFERENCES "tournaments"("id") ON DELETE CASCADE,
+	FOREIGN KEY ("player_one_id") REFERENCES "users"("id"),
+	FOREIGN KEY ("player_two_id") REFERENCES "users"("id"),
+	FOREIGN KEY ("room_id") REFERENCES "rooms"("id"),
+	FOREIGN KEY ("winner_id") REFERENCES "users"("id")
+);
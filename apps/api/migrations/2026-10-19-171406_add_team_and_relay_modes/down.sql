@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "room_users" DROP COLUMN "leg";
+ALTER TABLE "room_users" DROP COLUMN "team";
+
+ALTER TABLE "rooms" DROP COLUMN "mode";
+
+DROP TYPE room_modes;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+
+CREATE TYPE room_modes AS ENUM ('solo', 'team', 'relay');
+
+ALTER TABLE "rooms" ADD COLUMN "mode" room_modes NOT NULL DEFAULT 'solo';
+
+-- Team makeup of team and relay rooms, leg is the relay segment typed by the user
+ALTER TABLE "room_users" ADD COLUMN "team" INT2;
+ALTER TABLE "room_users" ADD COLUMN "leg" INT2;
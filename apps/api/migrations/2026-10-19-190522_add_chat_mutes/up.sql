@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Set by moderators, blocks room chat and reactions in every room
+ALTER TABLE "users" ADD COLUMN "chat_muted_until" TIMESTAMP;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX IF EXISTS "rooms_series_id_idx";
+
+ALTER TABLE "rooms" DROP COLUMN "previous_room_id";
+ALTER TABLE "rooms" DROP COLUMN "series_id";
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Rematches chain rooms into a series, named after its first room
+ALTER TABLE "rooms" ADD COLUMN "series_id" UUID;
+UPDATE "rooms" SET "series_id" = "id";
+ALTER TABLE "rooms" ALTER COLUMN "series_id" SET NOT NULL;
+
+ALTER TABLE "rooms" ADD COLUMN "previous_room_id" UUID REFERENCES "rooms"("id") ON DELETE SET NULL;
+
+CREATE INDEX "rooms_series_id_idx" ON "rooms"("series_id");
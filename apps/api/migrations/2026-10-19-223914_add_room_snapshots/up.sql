@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Latest checkpoint of a live room, removed once the room is closed
+CREATE TABLE "room_snapshots" (
+    "room_id" UUID PRIMARY KEY REFERENCES "rooms"("id") ON DELETE CASCADE,
+    "state" JSONB NOT NULL,
+    "saved_at" TIMESTAMP NOT NULL
+);
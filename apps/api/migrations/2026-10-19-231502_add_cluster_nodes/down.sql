@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE "cluster_mailbox";
+
+ALTER TABLE "rooms" DROP COLUMN "node_id";
+
+DROP TABLE "cluster_nodes";
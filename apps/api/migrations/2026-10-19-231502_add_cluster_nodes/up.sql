@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- API instances, a node is alive while it keeps its heartbeat fresh
+CREATE TABLE "cluster_nodes" (
+    "id" VARCHAR(64) PRIMARY KEY,
+    "heartbeat_at" TIMESTAMP NOT NULL
+);
+
+-- Node running the room, NULL for rooms from before clustering
+ALTER TABLE "rooms" ADD COLUMN "node_id" VARCHAR(64);
+
+-- Messages between nodes that don't fit in a NOTIFY payload
+CREATE TABLE "cluster_mailbox" (
+    "id" UUID PRIMARY KEY,
+    "payload" TEXT NOT NULL,
+    "created_at" TIMESTAMP NOT NULL
+);
@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+
+UPDATE "room_users" SET "left_at" = "joined_at" WHERE "left_at" IS NULL;
+ALTER TABLE "room_users" ALTER COLUMN "left_at" SET NOT NULL;
+ALTER TABLE "room_users" DROP COLUMN "leave_reason";
+
+UPDATE "rooms" SET "started_at" = "created_at" WHERE "started_at" IS NULL;
+UPDATE "rooms" SET "ended_at" = "created_at" WHERE "ended_at" IS NULL;
+ALTER TABLE "rooms" ALTER COLUMN "started_at" SET NOT NULL;
+ALTER TABLE "rooms" ALTER COLUMN "ended_at" SET NOT NULL;
+ALTER TABLE "rooms" DROP COLUMN "status";
+
+DROP TYPE leave_reasons;
+DROP TYPE room_statuses;
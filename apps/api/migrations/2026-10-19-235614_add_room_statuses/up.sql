@@ -0,0 +1,38 @@
+-- Your SQL goes here
+
+CREATE TYPE room_statuses AS ENUM ('created', 'countdown', 'running', 'finished', 'aborted');
+
+CREATE TYPE leave_reasons AS ENUM ('finished', 'left', 'dropped');
+
+ALTER TABLE "rooms" ADD COLUMN "status" room_statuses NOT NULL DEFAULT 'created';
+ALTER TABLE "rooms" ALTER COLUMN "started_at" DROP NOT NULL;
+ALTER TABLE "rooms" ALTER COLUMN "ended_at" DROP NOT NULL;
+
+ALTER TABLE "room_users" ADD COLUMN "leave_reason" leave_reasons;
+ALTER TABLE "room_users" ALTER COLUMN "left_at" DROP NOT NULL;
+
+-- Placeholders were written within the same instant as the row itself
+UPDATE "rooms" SET "started_at" = NULL WHERE "started_at" - "created_at" < INTERVAL '1 second';
+UPDATE "rooms" SET "ended_at" = NULL WHERE "ended_at" - "created_at" < INTERVAL '1 second';
+UPDATE "room_users" SET "left_at" = NULL WHERE "left_at" - "joined_at" < INTERVAL '1 second';
+
+UPDATE "room_users" SET "leave_reason" = 'finished'
+WHERE "id" IN (SELECT "room_user_id" FROM "results");
+
+UPDATE "rooms" SET "status" = CASE
+    WHEN "id" IN (SELECT "room_id" FROM "room_snapshots") THEN
+        CASE WHEN "started_at" IS NULL THEN 'created' ELSE 'running' END::room_statuses
+    WHEN "id" IN (SELECT "ru"."room_id" FROM "room_users" "ru" WHERE "ru"."leave_reason" = 'finished')
+        THEN 'finished'::room_statuses
+    ELSE 'aborted'::room_statuses
+END;
+
+-- Whoever didn't finish a closed room dropped out of it
+UPDATE "room_users" SET "leave_reason" = 'dropped', "left_at" = "rooms"."ended_at"
+FROM "rooms"
+WHERE "room_users"."room_id" = "rooms"."id"
+    AND "rooms"."status" IN ('finished', 'aborted')
+    AND "room_users"."leave_reason" IS NULL;
+
+UPDATE "rooms" SET "ended_at" = COALESCE("started_at", "created_at")
+WHERE "status" IN ('finished', 'aborted') AND "ended_at" IS NULL;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM "sessions";
+
+ALTER TABLE "sessions" RENAME COLUMN "refresh_token_hash" TO "token";
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Sessions from before refresh tokens have nothing to refresh with
+DELETE FROM "sessions";
+
+-- SHA-256 of the current refresh token secret, rotated on every refresh
+ALTER TABLE "sessions" RENAME COLUMN "token" TO "refresh_token_hash";
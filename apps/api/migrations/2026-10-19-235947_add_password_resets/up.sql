@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+-- Single use password reset links, only the SHA-256 of the token is stored
+CREATE TABLE "password_resets" (
+    "id" UUID PRIMARY KEY,
+    "user_id" UUID NOT NULL REFERENCES "users"("id") ON DELETE CASCADE,
+    "token_hash" VARCHAR NOT NULL UNIQUE,
+    "expires_at" TIMESTAMP NOT NULL,
+    "used_at" TIMESTAMP,
+    "created_at" TIMESTAMP NOT NULL
+);
+
+CREATE INDEX "password_resets_user_id_idx" ON "password_resets" ("user_id");
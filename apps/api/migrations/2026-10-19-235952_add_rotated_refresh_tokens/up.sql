@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Refresh tokens a session already rotated away from, one coming back means it was copied
+CREATE TABLE "rotated_refresh_tokens" (
+    "token_hash" VARCHAR PRIMARY KEY,
+    "session_id" UUID NOT NULL REFERENCES "sessions"("id") ON DELETE CASCADE,
+    "rotated_at" TIMESTAMP NOT NULL
+);
+
+CREATE INDEX "rotated_refresh_tokens_session_id_idx" ON "rotated_refresh_tokens" ("session_id");
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DELETE FROM "daily_challenge_attempts" WHERE "result_id" IS NULL;
+
+ALTER TABLE "daily_challenge_attempts" ALTER COLUMN "result_id" SET NOT NULL;
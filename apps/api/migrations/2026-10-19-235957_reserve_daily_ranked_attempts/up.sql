@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- The ranked attempt is reserved when the run starts and gets its result once it finishes,
+-- an abandoned ranked run keeps the slot without a result
+ALTER TABLE "daily_challenge_attempts" ALTER COLUMN "result_id" DROP NOT NULL;
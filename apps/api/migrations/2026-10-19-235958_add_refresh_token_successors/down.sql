@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE "rotated_refresh_tokens" DROP COLUMN "replaced_by";
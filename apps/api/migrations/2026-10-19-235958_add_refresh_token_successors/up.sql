@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Refresh token that replaced this one, handed out again to a concurrent refresh within the
+-- grace window and cleared after it
+ALTER TABLE "rotated_refresh_tokens" ADD COLUMN "replaced_by" VARCHAR;
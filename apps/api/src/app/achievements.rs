@@ -0,0 +1,96 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::models::{
+        achievement::{Achievement, AchievementRule, UserAchievement},
+        result::Results,
+    },
+};
+
+/// Everything about a just stored result that rules can look at.
+pub struct AchievementContext {
+    pub user_id: Uuid,
+    pub result_id: Uuid,
+    pub wpm: f32,
+    pub mistakes: i16,
+    pub text_length: usize,
+}
+
+/// Aggregates over the whole user history, loaded only if some locked rule needs them.
+#[derive(Default)]
+struct HistoryStats {
+    races: i64,
+    races_in_day: i64,
+    multiplayer_wins: i64,
+}
+
+impl AchievementRule {
+    fn is_satisfied(&self, ctx: &AchievementContext, history: &HistoryStats) -> bool {
+        match *self {
+            AchievementRule::Races { count } => history.races >= count,
+            AchievementRule::MinWpm { wpm } => ctx.wpm >= wpm,
+            AchievementRule::RacesInDay { count } => history.races_in_day >= count,
+            AchievementRule::PerfectAccuracy { min_length } => {
+                ctx.mistakes == 0 && ctx.text_length >= min_length
+            },
+            AchievementRule::MultiplayerWins { count } => history.multiplayer_wins >= count,
+        }
+    }
+}
+
+/// Unlocks every achievement the user satisfies after the result in `ctx` was inserted.
+/// Returns only the newly unlocked ones.
+pub async fn evaluate_achievements(
+    conn: &mut DbConn,
+    ctx: &AchievementContext,
+) -> MyResult<Vec<Achievement>> {
+    let locked = Achievement::get_locked_achievements_by_user_id(conn, ctx.user_id).await?;
+
+    if locked.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let needs = |f: fn(&AchievementRule) -> bool| locked.iter().any(|a| f(&a.rule));
+    let mut history = HistoryStats::default();
+
+    if needs(|r| matches!(r, AchievementRule::Races { .. })) {
+        history.races = Results::get_results_count_by_user_id(conn, ctx.user_id).await?;
+    }
+
+    if needs(|r| matches!(r, AchievementRule::RacesInDay { .. })) {
+        let since = (Utc::now() - Duration::hours(24)).naive_utc();
+        history.races_in_day =
+            Results::get_results_count_since_by_user_id(conn, ctx.user_id, since).await?;
+    }
+
+    if needs(|r| matches!(r, AchievementRule::MultiplayerWins { .. })) {
+        history.multiplayer_wins =
+            Results::get_multiplayer_wins_count_by_user_id(conn, ctx.user_id).await?;
+    }
+
+    let unlocked_at = Utc::now().naive_utc();
+    let mut unlocked = vec![];
+
+    for achievement in locked {
+        if !achievement.rule.is_satisfied(ctx, &history) {
+            continue;
+        }
+
+        let user_achievement = UserAchievement {
+            id: Uuid::new_v4(),
+            user_id: ctx.user_id,
+            achievement_id: achievement.id,
+            result_id: Some(ctx.result_id),
+            unlocked_at,
+        };
+
+        // 0 rows - already unlocked by a concurrent evaluation
+        if user_achievement.insert_user_achievement(conn).await? > 0 {
+            unlocked.push(achievement);
+        }
+    }
+
+    Ok(unlocked)
+}
@@ -1,4 +1,4 @@
-use std::sync::LazyLock;
+use std::{collections::HashMap, sync::LazyLock};
 
 use axum::{
     RequestPartsExt,
@@ -10,47 +10,149 @@ use axum_extra::{
     extract::CookieJar,
     headers::{Authorization, authorization::Bearer},
 };
-use jsonwebtoken::{DecodingKey, EncodingKey, Validation, decode};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use chrono::{NaiveDateTime, Utc};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::db::models::session::Session;
+use crate::{db::models::session::Session, utils};
 
 use super::{
     error::{AuthError, MyError},
     state::AppState,
+    types::{DbPool, MyResult},
 };
 
 pub const COMPANY_NAME: &str = env!("CARGO_PKG_NAME");
 pub const COOKIE_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "_token");
+pub const REFRESH_COOKIE_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "_refresh_token");
+/// Only sent along to the endpoints that rotate or end the session
+pub const REFRESH_COOKIE_PATH: &str = "/api/v1/user";
+
+/// Access tokens are short lived, the refresh token keeps the session going
+pub const ACCESS_TOKEN_TTL: chrono::Duration = chrono::Duration::minutes(15);
+/// Sessions expire after this long, or this long after their last refresh with sliding
+/// expiration
+pub const SESSION_TTL: chrono::Duration = chrono::Duration::days(7);
+/// A refresh racing another one with the same token, from a second tab or a retry, gets the
+/// tokens the first one got if it comes this soon
+pub const REFRESH_GRACE: chrono::Duration = chrono::Duration::seconds(10);
+/// Sliding sessions still end this long after the login
+pub const SESSION_MAX_AGE: chrono::Duration = chrono::Duration::days(30);
+const SESSION_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
 
 pub static KEYS: LazyLock<Keys> = LazyLock::new(|| {
-    let secret = crate::app::config::load_config().jwt_secret;
-    Keys::new(secret.as_bytes())
+    let config = crate::app::config::load_config();
+    Keys::new(&config.jwt_signing_key, &config.jwt_verification_keys)
 });
 
+/// Ed25519 keys, tokens name the one that signed them in the `kid` header.
 pub struct Keys {
-    pub encoding: EncodingKey,
-    pub decoding: DecodingKey,
+    kid: String,
+    encoding: EncodingKey,
+    /// The signing key and the retired ones, tokens signed before a rotation stay valid
+    decoding: HashMap<String, DecodingKey>,
+    /// Public part of `decoding`, for services verifying our tokens
+    pub jwks: JwkSet,
 }
 
 impl Keys {
-    fn new(secret: &[u8]) -> Self {
-        Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
+    /// `signing_key` is a base64 PKCS#8 document, `verification_keys` comma separated JWK `x`
+    /// values of retired keys
+    fn new(signing_key: &str, verification_keys: &str) -> Self {
+        assert!(!signing_key.trim().is_empty(), "JWT_SIGNING_KEY is not set");
+        let pkcs8 = STANDARD.decode(signing_key.trim()).expect("JWT_SIGNING_KEY is not base64");
+
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8)
+            .expect("JWT_SIGNING_KEY is not an Ed25519 PKCS#8 key");
+        let signing_x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+        let retired = verification_keys.split(',').map(str::trim).filter(|x| !x.is_empty());
+
+        let mut decoding = HashMap::new();
+        let mut jwks = JwkSet { keys: vec![] };
+
+        for x in std::iter::once(signing_x.as_str()).chain(retired) {
+            let kid = key_id(x);
+            if decoding.contains_key(&kid) {
+                continue;
+            }
+
+            let key = DecodingKey::from_ed_components(x)
+                .expect("JWT_VERIFICATION_KEYS holds an invalid key");
+            decoding.insert(kid.clone(), key);
+
+            jwks.keys.push(Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(KeyAlgorithm::EdDSA),
+                    key_id: Some(kid),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: x.to_string(),
+                }),
+            });
         }
+
+        Self { kid: key_id(&signing_x), encoding: EncodingKey::from_ed_der(&pkcs8), decoding, jwks }
+    }
+
+    fn encode(&self, claims: &Claims) -> jsonwebtoken::errors::Result<String> {
+        let header = Header { kid: Some(self.kid.clone()), ..Header::new(Algorithm::EdDSA) };
+
+        encode(&header, claims, &self.encoding)
+    }
+
+    fn decode(&self, token: &str) -> Option<Claims> {
+        let kid = decode_header(token).ok()?.kid?;
+        let key = self.decoding.get(&kid)?;
+
+        decode(token, key, &Validation::new(Algorithm::EdDSA)).ok().map(|data| data.claims)
     }
 }
 
+/// Derived from the public key, so keys never need names in the config
+fn key_id(x: &str) -> String {
+    utils::hash_token(x)[..16].to_string()
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
     pub company: String,
+    pub iat: usize,
     pub exp: usize,
     pub sid: Uuid,
 }
 
+impl Claims {
+    pub fn access_token(user_id: Uuid, session_id: Uuid, now: NaiveDateTime) -> MyResult<String> {
+        let claims = Claims {
+            sub: user_id,
+            company: COMPANY_NAME.to_string(),
+            iat: now.and_utc().timestamp() as usize,
+            exp: (now + ACCESS_TOKEN_TTL).and_utc().timestamp() as usize,
+            sid: session_id,
+        };
+
+        KEYS.encode(&claims).map_err(|_| MyError::Auth(AuthError::TokenCreation))
+    }
+}
+
 impl<S> FromRequestParts<S> for Claims
 where
     S: Send + Sync,
@@ -70,18 +172,71 @@ where
             .or_else(|| bearer_header.as_ref().map(|header| header.token()))
             .ok_or(AuthError::InvalidToken)?;
 
-        let token_data = decode(token, &KEYS.decoding, &Validation::default())?;
-
-        let claims: Claims = token_data.claims;
+        // Expired access tokens are a 401, the client refreshes and retries
+        let claims = KEYS.decode(token).ok_or(AuthError::InvalidToken)?;
 
+        // Logout, revocation and password changes end the session, its access tokens with it
         let app_state = AppState::from_ref(state);
         let mut conn = app_state.db().await?;
-        let maybe_session = Session::get_session(&mut conn, claims.sid).await?;
+        let now = Utc::now().naive_utc();
 
-        if maybe_session.is_none() {
+        let session = Session::get_session(&mut conn, claims.sid).await?;
+        if session.is_none_or(|session| session.expires_at <= now) {
             return Err(MyError::Auth(AuthError::InvalidToken));
         }
 
         Ok(claims)
     }
 }
+
+/// Opaque `<session id>.<secret>` token, only the hash of the secret is stored
+pub struct RefreshToken {
+    pub session_id: Uuid,
+    pub secret_hash: String,
+}
+
+impl RefreshToken {
+    /// New token for the session and the hash to store
+    pub fn generate(session_id: Uuid) -> (String, String) {
+        let secret = utils::generate_token();
+        let token = format!("{}.{}", session_id.simple(), secret);
+
+        (token, utils::hash_token(&secret))
+    }
+
+    pub fn parse(token: &str) -> Option<Self> {
+        let (session_id, secret) = token.split_once('.')?;
+
+        Some(Self { session_id: session_id.parse().ok()?, secret_hash: utils::hash_token(secret) })
+    }
+}
+
+/// Expired sessions are rejected anyway, this only keeps the table from growing forever
+pub fn spawn_session_cleanup(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let Ok(mut conn) = pool.get().await else {
+                log::error!("Session cleanup skipped, no connection");
+                continue;
+            };
+
+            let now = Utc::now().naive_utc();
+
+            match Session::delete_expired_sessions(&mut conn, now).await {
+                Ok(0) => {},
+                Ok(deleted) => log::info!("Deleted {} expired sessions", deleted),
+                Err(err) => log::error!("Session cleanup failed: {:?}", err),
+            }
+
+            // Rotations normally clear them, except the last one of each session
+            let before = now - REFRESH_GRACE;
+            if let Err(err) = Session::forget_refresh_token_successors(&mut conn, before).await {
+                log::error!("Refresh token cleanup failed: {:?}", err);
+            }
+        }
+    });
+}
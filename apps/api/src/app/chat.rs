@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const CHAT_MAX_LENGTH: usize = 200;
+
+/// At most this many chat messages and reactions per player in `CHAT_RATE_WINDOW`.
+const CHAT_RATE_LIMIT: usize = 5;
+const CHAT_RATE_WINDOW: Duration = Duration::seconds(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Reaction {
+    ThumbsUp,
+    Clap,
+    Fire,
+    Laugh,
+    Wave,
+    Gg,
+}
+
+/// Hook to screen chat messages before they reach the room.
+pub trait ChatFilter: Send + Sync {
+    /// Returns the text to broadcast, possibly masked, or `None` to drop the message.
+    fn filter(&self, text: &str) -> Option<String>;
+}
+
+/// Lets everything through, used until a real filter is plugged in.
+pub struct AllowAllFilter;
+
+impl ChatFilter for AllowAllFilter {
+    fn filter(&self, text: &str) -> Option<String> {
+        Some(text.to_string())
+    }
+}
+
+/// Masks configured words, matched case-insensitively as whole words.
+pub struct WordListFilter {
+    words: Vec<String>,
+}
+
+impl WordListFilter {
+    /// `words` is a comma separated list, as it comes from the environment.
+    pub fn new(words: &str) -> Self {
+        let words =
+            words.split(',').map(|w| w.trim().to_lowercase()).filter(|w| !w.is_empty()).collect();
+
+        Self { words }
+    }
+}
+
+impl ChatFilter for WordListFilter {
+    fn filter(&self, text: &str) -> Option<String> {
+        let masked = text
+            .split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+                if self.words.contains(&bare) {
+                    "*".repeat(word.chars().count())
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Some(masked)
+    }
+}
+
+/// Sliding window of the player's recent chat messages.
+#[derive(Clone, Default)]
+pub struct ChatThrottle {
+    sent: VecDeque<DateTime<Utc>>,
+}
+
+impl ChatThrottle {
+    /// Records the message if the player is still within the limit.
+    pub fn try_send(&mut self, now: DateTime<Utc>) -> bool {
+        while self.sent.front().is_some_and(|t| now - *t > CHAT_RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= CHAT_RATE_LIMIT {
+            return false;
+        }
+
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_list_masks_whole_words_in_any_case() {
+        let filter = WordListFilter::new(" darn, Heck ,,");
+
+        assert_eq!(filter.filter("Darn it, HECK!").as_deref(), Some("**** it, *****"));
+        assert_eq!(filter.filter("darning hecking").as_deref(), Some("darning hecking"));
+    }
+
+    #[test]
+    fn empty_word_list_lets_everything_through() {
+        let filter = WordListFilter::new("");
+
+        assert_eq!(filter.filter("darn  it").as_deref(), Some("darn  it"));
+        assert_eq!(filter.filter("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn throttle_frees_up_after_the_window() {
+        let mut throttle = ChatThrottle::default();
+        let start = Utc::now();
+
+        for _ in 0..CHAT_RATE_LIMIT {
+            assert!(throttle.try_send(start));
+        }
+        assert!(!throttle.try_send(start + CHAT_RATE_WINDOW));
+        assert!(throttle.try_send(start + CHAT_RATE_WINDOW + Duration::milliseconds(1)));
+    }
+}
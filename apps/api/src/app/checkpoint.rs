@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::db::{
+    custom_types::{Leagues, RoomStatuses},
+    models::{dictionary::Dictionary, result::ResultStats, text::Text},
+};
+
+use super::{
+    chat::ChatThrottle,
+    client_clock::ClientClock,
+    outbound::PlayerSender,
+    protocol::Protocol,
+    room::{Player, PlayerStatus, Room, RoomKind, RoomMode, Team, UserInfo},
+};
+
+/// How often a live room with changes is written to `room_snapshots`.
+pub const CHECKPOINT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// Time players get to reconnect to a restored room before it's aborted.
+pub const RECONNECT_GRACE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Everything needed to bring a room back after a restart. Connections are not part of it,
+/// players come back disconnected and resume their progress when they rejoin.
+#[derive(Serialize, Deserialize)]
+pub struct RoomState {
+    kind: RoomKind,
+    mode: RoomMode,
+    league: Leagues,
+    created_at: DateTime<Utc>,
+    host: Option<Uuid>,
+    teams: Vec<Team>,
+    text_id: Uuid,
+    dictionary_id: Uuid,
+    players: Vec<PlayerState>,
+    status: RoomStatuses,
+    ready_quorum: u8,
+    start_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerState {
+    id: Uuid,
+    user: UserInfo,
+    room_user_id: Uuid,
+    joined_at: DateTime<Utc>,
+    typed_text: String,
+    mistakes: i16,
+    last_is_mistake: bool,
+    progress: f32,
+    status: PlayerStatus,
+    stats: ResultStats,
+    placement: Option<i16>,
+    team: Option<i16>,
+    started_at: DateTime<Utc>,
+    finished_at: Option<DateTime<Utc>>,
+    chat_muted_until: Option<NaiveDateTime>,
+}
+
+impl RoomState {
+    pub fn capture(room: &Room) -> Self {
+        let players = room
+            .players
+            .values()
+            .map(|p| PlayerState {
+                id: p.id,
+                user: p.user.clone(),
+                room_user_id: p.room_user_id,
+                joined_at: p.joined_at,
+                typed_text: p.typed_text.clone(),
+                mistakes: p.mistakes,
+                last_is_mistake: p.last_is_mistake,
+                progress: p.progress,
+                status: p.status.clone(),
+                stats: p.stats.clone(),
+                placement: p.placement,
+                team: p.team,
+                started_at: p.started_at,
+                finished_at: p.finished_at,
+                chat_muted_until: p.chat_muted_until,
+            })
+            .collect();
+
+        Self {
+            kind: room.kind.clone(),
+            mode: room.mode,
+            league: room.league.clone(),
+            created_at: room.created_at,
+            host: room.host,
+            teams: room.teams.clone(),
+            text_id: room.text.id,
+            dictionary_id: room.dictionary.id,
+            players,
+            status: room.status,
+            ready_quorum: room.ready_quorum,
+            start_time: room.start_time,
+        }
+    }
+
+    pub fn text_id(&self) -> Uuid {
+        self.text_id
+    }
+
+    pub fn dictionary_id(&self) -> Uuid {
+        self.dictionary_id
+    }
+
+    pub fn restore(self, id: Uuid, text: Text, dictionary: Dictionary) -> Room {
+        let players = self
+            .players
+            .into_iter()
+            .map(|p| {
+                // Nobody listens until the player rejoins
+                let (sender, _, _) = PlayerSender::channel();
+
+                let player = Player {
+                    id: p.id,
+                    user: p.user,
+                    room_user_id: p.room_user_id,
+                    joined_at: p.joined_at,
+                    sender,
+                    room_binding: watch::channel(id).0,
+                    protocol: Protocol::legacy(),
+                    rematch: false,
+                    typed_text: p.typed_text,
+                    mistakes: p.mistakes,
+                    last_is_mistake: p.last_is_mistake,
+                    progress: p.progress,
+                    status: p.status,
+                    connected: false,
+                    stats: p.stats,
+                    placement: p.placement,
+                    team: p.team,
+                    started_at: p.started_at,
+                    finished_at: p.finished_at,
+                    chat_throttle: ChatThrottle::default(),
+                    chat_muted_until: p.chat_muted_until,
+                    clock: ClientClock::default(),
+                };
+
+                (p.id, player)
+            })
+            .collect::<HashMap<_, _>>();
+
+        Room {
+            id,
+            kind: self.kind,
+            mode: self.mode,
+            league: self.league,
+            created_at: self.created_at,
+            host: self.host,
+            teams: self.teams,
+            text,
+            dictionary,
+            players,
+            status: self.status,
+            ready_quorum: self.ready_quorum,
+            start_time: self.start_time,
+        }
+    }
+}
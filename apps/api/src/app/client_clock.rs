@@ -0,0 +1,141 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// Keys accepted in one `KeystrokeBatch`.
+pub const MAX_BATCH_KEYS: usize = 32;
+
+/// How far the client clock may wander from the offset seen on its first batch.
+/// Covers jitter in network latency and batching, not a clock that runs fast.
+const MAX_CLOCK_DRIFT: Duration = Duration::seconds(3);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClockError {
+    #[error("Keystroke timestamps must not go back in time")]
+    NotMonotonic,
+    #[error("Keystroke timestamps drifted too far from the server clock")]
+    Drift,
+    #[error("Invalid keystroke timestamp")]
+    Invalid,
+}
+
+/// Maps the client's keystroke times onto the server clock.
+#[derive(Clone, Debug, Default)]
+pub struct ClientClock {
+    /// Server time minus client time, fixed by the first batch
+    offset: Option<Duration>,
+    last: Option<DateTime<Utc>>,
+}
+
+impl ClientClock {
+    /// Converts client times in epoch milliseconds to server times, rejecting the whole batch
+    /// when they go back or the clock drifted. Nothing is recorded for a rejected batch.
+    pub fn map(
+        &mut self,
+        times: &[i64],
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>, ClockError> {
+        let times = times
+            .iter()
+            .map(|&ms| DateTime::from_timestamp_millis(ms).ok_or(ClockError::Invalid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let Some(&latest) = times.last() else {
+            return Ok(vec![]);
+        };
+
+        let mut previous = self.last;
+        for &time in &times {
+            if previous.is_some_and(|p| time < p) {
+                return Err(ClockError::NotMonotonic);
+            }
+            previous = Some(time);
+        }
+
+        // The batch is sent right after its last key, so that key is closest to `now`
+        let seen = now - latest;
+        let offset = *self.offset.get_or_insert(seen);
+        if (seen - offset).abs() > MAX_CLOCK_DRIFT {
+            return Err(ClockError::Drift);
+        }
+
+        self.last = Some(latest);
+
+        Ok(times.into_iter().map(|t| (t + offset).min(now)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(ms: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(1_800_000_000_000 + ms).unwrap()
+    }
+
+    /// Client clock one hour behind the server
+    const CLIENT: i64 = 1_800_000_000_000 - 3_600_000;
+
+    #[test]
+    fn first_batch_ends_at_now() {
+        let mut clock = ClientClock::default();
+
+        let mapped = clock.map(&[CLIENT, CLIENT + 100, CLIENT + 250], server(250)).unwrap();
+
+        assert_eq!(mapped, vec![server(0), server(100), server(250)]);
+    }
+
+    #[test]
+    fn later_batches_keep_the_first_offset() {
+        let mut clock = ClientClock::default();
+        clock.map(&[CLIENT], server(0)).unwrap();
+
+        // Arrived 500ms late, the keys keep their client spacing
+        let mapped = clock.map(&[CLIENT + 1000, CLIENT + 1100], server(1600)).unwrap();
+
+        assert_eq!(mapped, vec![server(1000), server(1100)]);
+    }
+
+    #[test]
+    fn rollback_within_a_batch_is_rejected() {
+        let mut clock = ClientClock::default();
+
+        let result = clock.map(&[CLIENT + 100, CLIENT], server(100));
+
+        assert!(matches!(result, Err(ClockError::NotMonotonic)));
+    }
+
+    #[test]
+    fn rollback_across_batches_is_rejected_and_not_recorded() {
+        let mut clock = ClientClock::default();
+        clock.map(&[CLIENT + 1000], server(1000)).unwrap();
+
+        let result = clock.map(&[CLIENT + 999], server(1100));
+        assert!(matches!(result, Err(ClockError::NotMonotonic)));
+
+        // Same timestamp again is fine, typing two keys in the same millisecond happens
+        assert_eq!(clock.map(&[CLIENT + 1000], server(1100)).unwrap(), vec![server(1000)]);
+    }
+
+    #[test]
+    fn drift_past_the_limit_is_rejected() {
+        let mut clock = ClientClock::default();
+        clock.map(&[CLIENT], server(0)).unwrap();
+
+        let late = MAX_CLOCK_DRIFT.num_milliseconds() + 1;
+        assert!(matches!(clock.map(&[CLIENT + 10], server(10 + late)), Err(ClockError::Drift)));
+
+        // A client clock running ahead of the server is capped at now once within the limit
+        let mapped = clock.map(&[CLIENT + 2000], server(1000)).unwrap();
+        assert_eq!(mapped, vec![server(1000)]);
+
+        assert!(matches!(clock.map(&[CLIENT + 9000], server(1000)), Err(ClockError::Drift)));
+    }
+
+    #[test]
+    fn empty_or_invalid_batches() {
+        let mut clock = ClientClock::default();
+
+        assert!(clock.map(&[], server(0)).unwrap().is_empty());
+        assert!(matches!(clock.map(&[i64::MAX], server(0)), Err(ClockError::Invalid)));
+        assert!(clock.offset.is_none());
+    }
+}
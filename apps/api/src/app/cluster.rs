@@ -0,0 +1,633 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use axum::extract::ws::Message;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::sql_types::Text;
+use diesel_async::RunQueryDsl;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, mpsc, oneshot, watch};
+use tokio_postgres::{AsyncMessage, NoTls};
+use uuid::Uuid;
+
+use crate::db::models::{
+    cluster::{ClusterMail, ClusterNode},
+    room::Room as RoomModel,
+};
+
+use super::{
+    error::MyError,
+    outbound::{Outgoing, PlayerSender},
+    protocol::{ClientMessage, Protocol, ServerMessage},
+    room::{RoomOptions, RoomStats, RoomsManager},
+    room_actor::RoomCommand,
+    room_directory::LobbyEvent,
+    types::{DbConn, DbPool},
+};
+
+pub const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Node without a heartbeat for this long is dead, its rooms get adopted by the others
+const NODE_TIMEOUT: Duration = Duration::seconds(15);
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+/// Postgres caps NOTIFY payloads at 8000 bytes, larger messages go through `cluster_mailbox`
+const NOTIFY_PAYLOAD_LIMIT: usize = 7900;
+const MAIL_TTL: Duration = Duration::minutes(1);
+
+/// Sent between API nodes over `LISTEN/NOTIFY`, each node listens on its own channel.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ClusterMessage {
+    // To the node that owns the room
+    CreateRoom {
+        request_id: Uuid,
+        from: String,
+        room_id: Uuid,
+        text_id: Uuid,
+        dictionary_id: Uuid,
+        options: RoomOptions,
+    },
+    Exists {
+        request_id: Uuid,
+        from: String,
+        room_id: Uuid,
+    },
+    Join {
+        request_id: Uuid,
+        from: String,
+        room_id: Uuid,
+        connection_id: Uuid,
+        user_id: Uuid,
+        protocol: Protocol,
+    },
+    Leave {
+        room_id: Uuid,
+        user_id: Uuid,
+    },
+    Message {
+        room_id: Uuid,
+        user_id: Uuid,
+        message: ClientMessage,
+    },
+    SendTo {
+        room_id: Uuid,
+        user_id: Uuid,
+        message: ServerMessage,
+    },
+    StartCountdown {
+        request_id: Uuid,
+        from: String,
+        room_id: Uuid,
+        user_id: Uuid,
+    },
+    // To every node
+    SetChatMute {
+        user_id: Uuid,
+        until: Option<NaiveDateTime>,
+    },
+    Stats {
+        request_id: Uuid,
+        from: String,
+    },
+    Lobby {
+        from: String,
+        event: LobbyEvent,
+    },
+    // To the node holding the socket
+    Deliver {
+        connection_id: Uuid,
+        frame: Frame,
+        droppable: bool,
+    },
+    Rebind {
+        connection_id: Uuid,
+        room_id: Uuid,
+    },
+    Reply {
+        request_id: Uuid,
+        reply: ClusterReply,
+    },
+    /// Too large for NOTIFY, the message waits in `cluster_mailbox`
+    Stored {
+        id: Uuid,
+    },
+}
+
+impl ClusterMessage {
+    /// Remote form of a command that needs no reply.
+    pub fn from_command(room_id: Uuid, command: RoomCommand) -> Option<Self> {
+        match command {
+            RoomCommand::Leave { player_id } => {
+                Some(ClusterMessage::Leave { room_id, user_id: player_id })
+            },
+            RoomCommand::Message { user_id, message } => {
+                Some(ClusterMessage::Message { room_id, user_id, message })
+            },
+            RoomCommand::SendTo { user_id, message } => {
+                Some(ClusterMessage::SendTo { room_id, user_id, message })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ClusterReply {
+    Done { result: Result<(), RemoteError> },
+    Stats { rooms: Vec<RoomStats> },
+}
+
+/// The part of `MyError` a caller on another node can act on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RemoteError {
+    NotFound,
+    Unauthorized,
+    Validation(String),
+    Internal,
+}
+
+impl From<MyError> for RemoteError {
+    fn from(error: MyError) -> Self {
+        match error {
+            MyError::NotFound => RemoteError::NotFound,
+            MyError::Unauthorized => RemoteError::Unauthorized,
+            MyError::Validation(message) => RemoteError::Validation(message),
+            _ => RemoteError::Internal,
+        }
+    }
+}
+
+impl From<RemoteError> for MyError {
+    fn from(error: RemoteError) -> Self {
+        match error {
+            RemoteError::NotFound => MyError::NotFound,
+            RemoteError::Unauthorized => MyError::Unauthorized,
+            RemoteError::Validation(message) => MyError::Validation(message),
+            RemoteError::Internal => MyError::InternalError,
+        }
+    }
+}
+
+/// WebSocket frame on its way to a socket on another node.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping,
+    Close,
+}
+
+impl Frame {
+    fn from_message(message: Message) -> Option<Self> {
+        match message {
+            Message::Text(text) => Some(Frame::Text(text.to_string())),
+            Message::Binary(bytes) => Some(Frame::Binary(bytes.to_vec())),
+            Message::Ping(_) => Some(Frame::Ping),
+            Message::Close(_) => Some(Frame::Close),
+            Message::Pong(_) => None,
+        }
+    }
+
+    fn into_message(self) -> Message {
+        match self {
+            Frame::Text(text) => Message::Text(text.into()),
+            Frame::Binary(bytes) => Message::Binary(bytes.into()),
+            Frame::Ping => Message::Ping(Default::default()),
+            Frame::Close => Message::Close(None),
+        }
+    }
+}
+
+/// Socket on this node that takes part in a room owned by another node.
+struct RemoteConnection {
+    sender: PlayerSender,
+    room_binding: watch::Sender<Uuid>,
+}
+
+/// Membership and messaging between the API nodes sharing one database.
+/// Rooms are spread over the live nodes by rendezvous hashing of their id.
+#[derive(Clone)]
+pub struct Cluster {
+    pub node_id: String,
+    db: DbPool,
+    database_url: String,
+    /// Sorted, as of the last heartbeat
+    live_nodes: Arc<RwLock<Vec<String>>>,
+    pending: Arc<Mutex<HashMap<Uuid, oneshot::Sender<ClusterReply>>>>,
+    connections: Arc<Mutex<HashMap<Uuid, RemoteConnection>>>,
+    /// Owners of rooms running on other nodes
+    owners: Arc<Mutex<HashMap<Uuid, String>>>,
+}
+
+impl Cluster {
+    pub fn new(db: DbPool, database_url: String, node_id: String) -> Self {
+        // Ends up in a channel name
+        assert!(
+            !node_id.is_empty()
+                && node_id.len() <= 64
+                && node_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "NODE_ID must be 1 to 64 letters, digits, `-` or `_`"
+        );
+
+        Self {
+            live_nodes: Arc::new(RwLock::new(vec![node_id.clone()])),
+            node_id,
+            db,
+            database_url,
+            pending: Default::default(),
+            connections: Default::default(),
+            owners: Default::default(),
+        }
+    }
+
+    pub fn is_local(&self, node: &str) -> bool {
+        node == self.node_id
+    }
+
+    pub fn is_alive(&self, node: &str) -> bool {
+        self.live_nodes.read().unwrap().iter().any(|n| n == node)
+    }
+
+    pub fn other_nodes(&self) -> Vec<String> {
+        self.live_nodes.read().unwrap().iter().filter(|n| !self.is_local(n)).cloned().collect()
+    }
+
+    /// Live node with the highest score for the room. Adding or losing a node only moves
+    /// the rooms that scored highest on it.
+    pub fn owner_for(&self, room_id: Uuid) -> String {
+        let nodes = self.live_nodes.read().unwrap();
+
+        nodes
+            .iter()
+            .max_by_key(|node| rendezvous_score(node, room_id))
+            .cloned()
+            .unwrap_or_else(|| self.node_id.clone())
+    }
+
+    /// Node running the room according to the database, cached while that node is alive.
+    pub async fn owner_of(&self, conn: &mut DbConn, room_id: Uuid) -> Option<String> {
+        if let Some(node) = self.owners.lock().unwrap().get(&room_id).cloned()
+            && self.is_alive(&node)
+        {
+            return Some(node);
+        }
+
+        let node = RoomModel::get_room_by_id(conn, room_id).await.ok()??.node_id?;
+        self.owners.lock().unwrap().insert(room_id, node.clone());
+
+        Some(node)
+    }
+
+    pub fn remember_owner(&self, room_id: Uuid, node: String) {
+        self.owners.lock().unwrap().insert(room_id, node);
+    }
+
+    /// Refreshes this node's heartbeat and the list of live nodes.
+    pub async fn heartbeat(&self, conn: &mut DbConn) {
+        let now = Utc::now().naive_utc();
+
+        let node = ClusterNode { id: self.node_id.clone(), heartbeat_at: now };
+        if let Err(e) = node.heartbeat(conn).await {
+            log::error!("Failed to send cluster heartbeat: {:?}", e);
+            return;
+        }
+
+        match ClusterNode::get_live_node_ids(conn, now - NODE_TIMEOUT).await {
+            Ok(mut nodes) => {
+                nodes.sort();
+                *self.live_nodes.write().unwrap() = nodes;
+            },
+            Err(e) => log::error!("Failed to load cluster nodes: {:?}", e),
+        }
+
+        let _ = ClusterMail::delete_mail_before(conn, now - MAIL_TTL).await;
+        self.connections.lock().unwrap().retain(|_, c| !c.sender.is_closed());
+    }
+
+    pub async fn publish(&self, node: &str, message: &ClusterMessage) {
+        let Ok(mut conn) = self.db.get().await else {
+            log::error!("Cluster message to {} dropped, no connection", node);
+            return;
+        };
+
+        let mut payload = serde_json::to_string(message).unwrap();
+
+        if payload.len() > NOTIFY_PAYLOAD_LIMIT {
+            let mail =
+                ClusterMail { id: Uuid::new_v4(), payload, created_at: Utc::now().naive_utc() };
+            let stored = ClusterMessage::Stored { id: mail.id };
+
+            if let Err(e) = mail.insert_mail(&mut conn).await {
+                log::error!("Failed to store cluster message for {}: {:?}", node, e);
+                return;
+            }
+            payload = serde_json::to_string(&stored).unwrap();
+        }
+
+        let sent = diesel::sql_query("SELECT pg_notify($1, $2)")
+            .bind::<Text, _>(channel(node))
+            .bind::<Text, _>(payload)
+            .execute(&mut conn)
+            .await;
+
+        if let Err(e) = sent {
+            log::error!("Failed to notify node {}: {:?}", node, e);
+        }
+    }
+
+    /// Sends the message built around a fresh request id and waits for the node to reply.
+    pub async fn request(
+        &self,
+        node: &str,
+        build: impl FnOnce(Uuid, String) -> ClusterMessage,
+    ) -> Option<ClusterReply> {
+        let request_id = Uuid::new_v4();
+        let (reply, response) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id, reply);
+
+        self.publish(node, &build(request_id, self.node_id.clone())).await;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, response).await;
+        self.pending.lock().unwrap().remove(&request_id);
+
+        match response {
+            Ok(Ok(reply)) => Some(reply),
+            _ => {
+                log::warn!("Node {} didn't answer request {}", node, request_id);
+                None
+            },
+        }
+    }
+
+    pub async fn reply(&self, node: &str, request_id: Uuid, reply: ClusterReply) {
+        self.publish(node, &ClusterMessage::Reply { request_id, reply }).await;
+    }
+
+    /// Keeps the socket reachable for the room's owner, returns the id the owner delivers to.
+    pub fn register_connection(
+        &self,
+        sender: PlayerSender,
+        room_binding: watch::Sender<Uuid>,
+    ) -> Uuid {
+        let connection_id = Uuid::new_v4();
+        let connection = RemoteConnection { sender, room_binding };
+        self.connections.lock().unwrap().insert(connection_id, connection);
+
+        connection_id
+    }
+
+    pub fn drop_connection(&self, connection_id: Uuid) {
+        self.connections.lock().unwrap().remove(&connection_id);
+    }
+
+    /// Relays what the room sends to a remote player back to the node holding their socket,
+    /// along with rematch moves of the player.
+    pub fn forward(
+        &self,
+        node: String,
+        connection_id: Uuid,
+        mut rx: mpsc::Receiver<Outgoing>,
+        overflow: Arc<Notify>,
+        room_binding: &watch::Sender<Uuid>,
+    ) {
+        let cluster = self.clone();
+        let mut binding = room_binding.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    outgoing = rx.recv() => {
+                        let Some((message, droppable)) = outgoing else {
+                            break;
+                        };
+                        let Some(frame) = Frame::from_message(message) else {
+                            continue;
+                        };
+
+                        let closing = matches!(frame, Frame::Close);
+                        let deliver = ClusterMessage::Deliver { connection_id, frame, droppable };
+                        cluster.publish(&node, &deliver).await;
+
+                        if closing {
+                            break;
+                        }
+                    },
+                    Ok(()) = binding.changed() => {
+                        let room_id = *binding.borrow_and_update();
+                        cluster.publish(&node, &ClusterMessage::Rebind { connection_id, room_id }).await;
+                    },
+                    _ = overflow.notified() => {
+                        let deliver =
+                            ClusterMessage::Deliver { connection_id, frame: Frame::Close, droppable: false };
+                        cluster.publish(&node, &deliver).await;
+                        break;
+                    },
+                }
+            }
+        });
+    }
+
+    /// Listens on this node's channel and hands the messages to the manager in arrival order.
+    pub fn spawn_listener(&self, manager: RoomsManager) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            loop {
+                cluster.listen(&tx).await;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        let cluster = self.clone();
+        tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                let message = match serde_json::from_str::<ClusterMessage>(&payload) {
+                    Ok(ClusterMessage::Stored { id }) => cluster.take_stored(id).await,
+                    Ok(message) => Some(message),
+                    Err(e) => {
+                        log::error!("Unreadable cluster message: {:?}", e);
+                        None
+                    },
+                };
+
+                if let Some(message) = message {
+                    cluster.dispatch(&manager, message).await;
+                }
+            }
+        });
+    }
+
+    /// Runs until the listening connection breaks.
+    async fn listen(&self, tx: &mpsc::UnboundedSender<String>) {
+        let (client, mut connection) =
+            match tokio_postgres::connect(&self.database_url, NoTls).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    log::error!("Cluster listener failed to connect: {:?}", e);
+                    return;
+                },
+            };
+
+        // Notifications come out of the connection, the client is only used to subscribe
+        let pump = tx.clone();
+        let driver = tokio::spawn(async move {
+            let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(notification)) => {
+                        let _ = pump.send(notification.payload().to_string());
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        log::error!("Cluster listener lost its connection: {:?}", e);
+                        break;
+                    },
+                }
+            }
+        });
+
+        match client.batch_execute(&format!("LISTEN \"{}\"", channel(&self.node_id))).await {
+            Ok(()) => log::info!("Node {} listening for cluster messages", self.node_id),
+            Err(e) => {
+                log::error!("Failed to listen for cluster messages: {:?}", e);
+                driver.abort();
+                return;
+            },
+        }
+
+        let _ = driver.await;
+        drop(client);
+    }
+
+    async fn take_stored(&self, id: Uuid) -> Option<ClusterMessage> {
+        let mut conn = self.db.get().await.ok()?;
+
+        match ClusterMail::take_mail(&mut conn, id).await {
+            Ok(Some(payload)) => serde_json::from_str(&payload).ok(),
+            other => {
+                log::error!("Stored cluster message {} is missing: {:?}", id, other.err());
+                None
+            },
+        }
+    }
+
+    /// Messages that only touch a room's inbox run in order, keystrokes depend on it.
+    /// Requests that wait on the database or on a room run on their own.
+    async fn dispatch(&self, manager: &RoomsManager, message: ClusterMessage) {
+        match message {
+            ClusterMessage::CreateRoom {
+                request_id,
+                from,
+                room_id,
+                text_id,
+                dictionary_id,
+                options,
+            } => {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let result = manager
+                        ._create_remote_room(room_id, text_id, dictionary_id, options)
+                        .await
+                        .map_err(Into::into);
+                    manager.cluster.reply(&from, request_id, ClusterReply::Done { result }).await;
+                });
+            },
+            ClusterMessage::Exists { request_id, from, room_id } => {
+                let result = match manager.rooms.read().await.contains_key(&room_id) {
+                    true => Ok(()),
+                    false => Err(RemoteError::NotFound),
+                };
+                self.reply(&from, request_id, ClusterReply::Done { result }).await;
+            },
+            ClusterMessage::Join {
+                request_id,
+                from,
+                room_id,
+                connection_id,
+                user_id,
+                protocol,
+            } => {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let result = manager
+                        ._join_remote_player(room_id, &from, connection_id, user_id, protocol)
+                        .await
+                        .map_err(Into::into);
+                    manager.cluster.reply(&from, request_id, ClusterReply::Done { result }).await;
+                });
+            },
+            // Never awaits a room inbox here, one busy room would hold up every message of
+            // the node, replies included
+            ClusterMessage::Leave { room_id, user_id } => {
+                manager._relay_command_local(room_id, RoomCommand::Leave { player_id: user_id });
+            },
+            ClusterMessage::Message { room_id, user_id, message } => {
+                manager._relay_command_local(room_id, RoomCommand::Message { user_id, message });
+            },
+            ClusterMessage::SendTo { room_id, user_id, message } => {
+                manager._relay_command_local(room_id, RoomCommand::SendTo { user_id, message });
+            },
+            ClusterMessage::StartCountdown { request_id, from, room_id, user_id } => {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let result =
+                        manager._start_countdown_local(room_id, user_id).await.map_err(Into::into);
+                    manager.cluster.reply(&from, request_id, ClusterReply::Done { result }).await;
+                });
+            },
+            ClusterMessage::SetChatMute { user_id, until } => {
+                manager._set_chat_mute_local(user_id, until).await;
+            },
+            ClusterMessage::Stats { request_id, from } => {
+                let rooms = manager.directory.snapshot();
+                self.reply(&from, request_id, ClusterReply::Stats { rooms }).await;
+            },
+            ClusterMessage::Lobby { from, event } => {
+                manager.directory.apply_remote(&from, &event);
+                manager.directory.announce(event);
+            },
+            ClusterMessage::Deliver { connection_id, frame, droppable } => {
+                let mut connections = self.connections.lock().unwrap();
+                let closing = matches!(frame, Frame::Close);
+
+                if let Some(connection) = connections.get(&connection_id) {
+                    connection.sender.send(frame.into_message(), droppable);
+                }
+                if closing {
+                    connections.remove(&connection_id);
+                }
+            },
+            ClusterMessage::Rebind { connection_id, room_id } => {
+                if let Some(connection) = self.connections.lock().unwrap().get(&connection_id) {
+                    connection.room_binding.send_replace(room_id);
+                }
+            },
+            ClusterMessage::Reply { request_id, reply } => {
+                if let Some(pending) = self.pending.lock().unwrap().remove(&request_id) {
+                    let _ = pending.send(reply);
+                }
+            },
+            ClusterMessage::Stored { id } => {
+                log::error!("Stored cluster message {} pointing to another one", id);
+            },
+        }
+    }
+}
+
+fn channel(node: &str) -> String {
+    format!("node_{}", node)
+}
+
+/// FNV-1a of the node and the room, the same on every node and build.
+fn rendezvous_score(node: &str, room_id: Uuid) -> u64 {
+    node.as_bytes()
+        .iter()
+        .chain(room_id.as_bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
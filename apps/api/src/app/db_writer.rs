@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use diesel_async::scoped_futures::ScopedBoxFuture;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::types::{DbConn, DbPool};
+
+type DbJob = Box<dyn for<'r> FnOnce(&'r mut DbConn) -> ScopedBoxFuture<'static, 'r, ()> + Send>;
+
+/// Workers writing at the same time, each one owns the rooms hashing to it
+const WRITER_SHARDS: usize = 8;
+/// Jobs a worker holds before `submit` waits for room
+const WRITER_QUEUE: usize = 1024;
+
+/// Runs the writes of live rooms outside the room actors. The writes of a room run one at a
+/// time in the order they were queued, so a result is never written before its room user, nor
+/// a room user before its room. Different rooms are written concurrently.
+#[derive(Clone)]
+pub struct DbWriter {
+    shards: Arc<[mpsc::Sender<DbJob>]>,
+}
+
+impl DbWriter {
+    pub fn spawn(pool: DbPool) -> Self {
+        let shards = (0..WRITER_SHARDS)
+            .map(|_| {
+                let (tx, mut rx) = mpsc::channel::<DbJob>(WRITER_QUEUE);
+                let pool = pool.clone();
+
+                tokio::spawn(async move {
+                    while let Some(job) = rx.recv().await {
+                        match pool.get().await {
+                            Ok(mut conn) => job(&mut conn).await,
+                            Err(e) => log::error!("Database write dropped, no connection: {:?}", e),
+                        }
+                    }
+                });
+
+                tx
+            })
+            .collect();
+
+        Self { shards }
+    }
+
+    /// Queues the job behind the earlier ones of the room, waits only while that queue is
+    /// full. Errors are up to the job to log.
+    pub async fn submit<F>(&self, room_id: Uuid, job: F)
+    where
+        F: for<'r> FnOnce(&'r mut DbConn) -> ScopedBoxFuture<'static, 'r, ()> + Send + 'static,
+    {
+        let shard = &self.shards[(room_id.as_u128() % self.shards.len() as u128) as usize];
+
+        if shard.send(Box::new(job)).await.is_err() {
+            log::error!("Database writer is gone, write dropped");
+        }
+    }
+}
@@ -1,9 +1,23 @@
+pub mod achievements;
 pub mod auth;
+pub mod chat;
+pub mod checkpoint;
+pub mod client_clock;
+pub mod cluster;
 pub mod config;
+pub mod db_writer;
 pub mod error;
+pub mod mailer;
 pub mod middleware;
 pub mod openapi;
+pub mod outbound;
+pub mod progression;
+pub mod protocol;
+pub mod rate_limit;
 pub mod room;
+pub mod room_actor;
+pub mod room_directory;
 pub mod router;
 pub mod state;
+pub mod tournament;
 pub mod types;
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use axum::extract::ws::Message;
+use tokio::sync::{
+    Notify,
+    mpsc::{self, Receiver, error::TrySendError},
+};
+
+/// Messages waiting for a slow client before the policy kicks in.
+pub const OUTBOUND_QUEUE_CAPACITY: usize = 64;
+
+/// Queued message and whether it may be skipped, forwarders to other nodes pass the flag on.
+pub type Outgoing = (Message, bool);
+
+/// Bounded queue of messages to one socket. When the client can't keep up, droppable messages
+/// are skipped and anything else disconnects it through `overflow`.
+#[derive(Clone, Debug)]
+pub struct PlayerSender {
+    tx: mpsc::Sender<Outgoing>,
+    overflow: Arc<Notify>,
+}
+
+impl PlayerSender {
+    pub fn channel() -> (Self, Receiver<Outgoing>, Arc<Notify>) {
+        let (tx, rx) = mpsc::channel(OUTBOUND_QUEUE_CAPACITY);
+        let overflow = Arc::new(Notify::new());
+
+        (Self { tx, overflow: overflow.clone() }, rx, overflow)
+    }
+
+    pub fn send(&self, message: Message, droppable: bool) {
+        match self.tx.try_send((message, droppable)) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) if droppable => {
+                log::debug!("Outbound queue full, message dropped");
+            },
+            Err(TrySendError::Full(_)) => {
+                log::warn!("Outbound queue full, disconnecting slow client");
+                self.overflow.notify_one();
+            },
+            // Socket is already gone
+            Err(TrySendError::Closed(_)) => {},
+        }
+    }
+
+    pub fn close(&self) {
+        self.send(Message::Close(None), false);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Messages queued but not yet written to the socket.
+    pub fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+}
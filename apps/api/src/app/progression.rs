@@ -0,0 +1,259 @@
+use chrono::{Days, NaiveDate, Utc};
+use diesel_async::{AsyncConnection, scoped_futures::ScopedFutureExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app::{
+        error::MyError,
+        types::{DbConn, MyResult},
+    },
+    db::models::{
+        user::User,
+        xp_ledger::{XpBreakdown, XpLedgerEntry},
+    },
+};
+
+/// Characters of text worth one XP before multipliers.
+const CHARS_PER_XP: usize = 5;
+/// Share of the length XP that the winner of a multiplayer race gets on top.
+const MAX_PLACEMENT_BONUS: f32 = 0.5;
+/// XP needed to go from level 1 to level 2, every next level needs one step more.
+const LEVEL_STEP_XP: i64 = 100;
+
+pub struct RaceOutcome {
+    pub user_id: Uuid,
+    pub result_id: Uuid,
+    pub text_length: usize,
+    pub accuracy: f32,
+    pub placement: i16,
+    pub players: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, utoipa::ToSchema)]
+pub struct ProgressionInfo {
+    pub xp: i64,
+    pub level: i32,
+    /// Total XP at which the current level started
+    pub level_xp: i64,
+    /// Total XP needed for the next level
+    pub next_level_xp: i64,
+    pub streak_days: i32,
+    pub best_streak_days: i32,
+}
+
+impl ProgressionInfo {
+    pub fn from_user(user: &User) -> Self {
+        let today = Utc::now().date_naive();
+
+        Self {
+            xp: user.xp,
+            level: user.level,
+            level_xp: xp_for_level(user.level),
+            next_level_xp: xp_for_level(user.level + 1),
+            streak_days: current_streak(user.last_active_on, user.streak_days, today),
+            best_streak_days: user.best_streak_days,
+        }
+    }
+}
+
+pub fn xp_for_race(outcome: &RaceOutcome) -> XpBreakdown {
+    let length_xp = (outcome.text_length / CHARS_PER_XP).max(1) as i32;
+    let accuracy_multiplier = (outcome.accuracy / 100.0).clamp(0.0, 1.0).powi(2);
+
+    let placement_bonus = if outcome.players > 1 {
+        let beaten = outcome.players.saturating_sub(outcome.placement.max(1) as usize) as f32;
+        let share = beaten / (outcome.players - 1) as f32;
+        (length_xp as f32 * MAX_PLACEMENT_BONUS * share).round() as i32
+    } else {
+        0
+    };
+
+    XpBreakdown {
+        text_length: outcome.text_length,
+        accuracy: outcome.accuracy,
+        placement: outcome.placement,
+        players: outcome.players,
+        length_xp,
+        accuracy_multiplier,
+        placement_bonus,
+    }
+}
+
+impl XpBreakdown {
+    pub fn amount(&self) -> i32 {
+        (self.length_xp as f32 * self.accuracy_multiplier).round() as i32 + self.placement_bonus
+    }
+}
+
+/// Total XP at which `level` starts: 0, 100, 300, 600, ...
+pub fn xp_for_level(level: i32) -> i64 {
+    let level = level.max(1) as i64;
+    LEVEL_STEP_XP * level * (level - 1) / 2
+}
+
+pub fn level_for_xp(xp: i64) -> i32 {
+    let mut level = 1;
+    while xp_for_level(level + 1) <= xp {
+        level += 1;
+    }
+    level
+}
+
+/// Stored streak is only bumped on activity, so a missed day is applied when reading it.
+pub fn current_streak(
+    last_active_on: Option<NaiveDate>,
+    streak_days: i32,
+    today: NaiveDate,
+) -> i32 {
+    match last_active_on {
+        Some(day) if day == today || Some(day) == today.checked_sub_days(Days::new(1)) => {
+            streak_days
+        },
+        _ => 0,
+    }
+}
+
+fn next_streak(last_active_on: Option<NaiveDate>, streak_days: i32, today: NaiveDate) -> i32 {
+    match last_active_on {
+        Some(day) if day == today => streak_days.max(1),
+        Some(day) if Some(day) == today.checked_sub_days(Days::new(1)) => streak_days + 1,
+        _ => 1,
+    }
+}
+
+/// Writes the ledger entry for a finished race and updates XP, level and streak of the user.
+pub async fn apply_race_progression(
+    conn: &mut DbConn,
+    outcome: &RaceOutcome,
+) -> MyResult<(XpLedgerEntry, ProgressionInfo)> {
+    let breakdown = xp_for_race(outcome);
+    let now = Utc::now().naive_utc();
+    let today = now.date();
+
+    conn.transaction::<_, MyError, _>(|conn| {
+        async move {
+            let mut user =
+                User::get_user_for_update(conn, outcome.user_id).await?.ok_or(MyError::NotFound)?;
+
+            let entry = XpLedgerEntry {
+                id: Uuid::new_v4(),
+                user_id: outcome.user_id,
+                result_id: Some(outcome.result_id),
+                amount: breakdown.amount(),
+                breakdown,
+                created_at: now,
+            };
+            let entry = entry.insert_entry(conn).await?;
+
+            user.xp += entry.amount as i64;
+            user.level = level_for_xp(user.xp);
+            user.streak_days = next_streak(user.last_active_on, user.streak_days, today);
+            user.best_streak_days = user.best_streak_days.max(user.streak_days);
+            user.last_active_on = Some(today);
+
+            let user = user.set_progression(conn).await?;
+
+            Ok((entry, ProgressionInfo::from_user(&user)))
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Rebuilds XP, level and streaks of the user from the ledger.
+pub async fn recompute_progression(conn: &mut DbConn, user_id: Uuid) -> MyResult<User> {
+    conn.transaction::<_, MyError, _>(|conn| {
+        async move {
+            let mut user =
+                User::get_user_for_update(conn, user_id).await?.ok_or(MyError::NotFound)?;
+
+            let xp = XpLedgerEntry::get_total_xp_by_user_id(conn, user_id).await?;
+            let dates = XpLedgerEntry::get_entry_dates_by_user_id(conn, user_id).await?;
+
+            let mut last_active_on = None;
+            let mut streak_days = 0;
+            let mut best_streak_days = 0;
+
+            for day in dates.into_iter().map(|d| d.date()) {
+                streak_days = next_streak(last_active_on, streak_days, day);
+                best_streak_days = best_streak_days.max(streak_days);
+                last_active_on = Some(day);
+            }
+
+            user.xp = xp;
+            user.level = level_for_xp(xp);
+            user.streak_days = streak_days;
+            user.best_streak_days = best_streak_days;
+            user.last_active_on = last_active_on;
+
+            user.set_progression(conn).await
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(text_length: usize, accuracy: f32, placement: i16, players: usize) -> RaceOutcome {
+        RaceOutcome {
+            user_id: Uuid::nil(),
+            result_id: Uuid::nil(),
+            text_length,
+            accuracy,
+            placement,
+            players,
+        }
+    }
+
+    #[test]
+    fn solo_race_has_no_placement_bonus() {
+        let breakdown = xp_for_race(&outcome(500, 100.0, 1, 1));
+
+        assert_eq!(breakdown.length_xp, 100);
+        assert_eq!(breakdown.placement_bonus, 0);
+        assert_eq!(breakdown.amount(), 100);
+    }
+
+    #[test]
+    fn placement_bonus_scales_with_players_beaten() {
+        assert_eq!(xp_for_race(&outcome(500, 100.0, 1, 3)).placement_bonus, 50);
+        assert_eq!(xp_for_race(&outcome(500, 100.0, 2, 3)).placement_bonus, 25);
+        assert_eq!(xp_for_race(&outcome(500, 100.0, 3, 3)).placement_bonus, 0);
+    }
+
+    #[test]
+    fn accuracy_is_squared_and_clamped() {
+        assert_eq!(xp_for_race(&outcome(500, 50.0, 1, 1)).amount(), 25);
+        assert_eq!(xp_for_race(&outcome(500, 150.0, 1, 1)).amount(), 100);
+        assert_eq!(xp_for_race(&outcome(500, -10.0, 1, 1)).amount(), 0);
+    }
+
+    #[test]
+    fn short_text_is_worth_at_least_one_xp() {
+        assert_eq!(xp_for_race(&outcome(0, 100.0, 1, 1)).length_xp, 1);
+    }
+
+    #[test]
+    fn placement_past_the_field_gets_nothing() {
+        assert_eq!(xp_for_race(&outcome(500, 100.0, 5, 3)).placement_bonus, 0);
+        assert_eq!(xp_for_race(&outcome(500, 100.0, 0, 3)).placement_bonus, 50);
+    }
+
+    #[test]
+    fn levels_start_at_their_threshold() {
+        assert_eq!(level_for_xp(0), 1);
+        assert_eq!(level_for_xp(99), 1);
+        assert_eq!(level_for_xp(100), 2);
+        assert_eq!(level_for_xp(299), 2);
+        assert_eq!(level_for_xp(300), 3);
+        assert_eq!(level_for_xp(-5), 1);
+
+        for level in 1..50 {
+            assert_eq!(level_for_xp(xp_for_level(level)), level);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use axum::extract::ws::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    chat::Reaction,
+    room::{PlayerStats, TeamStats},
+};
+
+/// Version this server speaks. Bump it on any breaking change of the messages below.
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Clients that never send `Hello` predate the handshake.
+pub const LEGACY_PROTOCOL_VERSION: u16 = 1;
+
+/// Close code sent to clients whose version is no longer supported.
+pub const UNSUPPORTED_VERSION_CLOSE_CODE: u16 = 4001;
+
+/// Optional message groups, a client gets only the ones it asked for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Chat,
+    Teams,
+    Rematch,
+    Progression,
+}
+
+const ALL_CAPABILITIES: [Capability; 4] =
+    [Capability::Chat, Capability::Teams, Capability::Rematch, Capability::Progression];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnexpectedMessage,
+    NotAllowed,
+    Muted,
+    RateLimited,
+    UnsupportedVersion,
+}
+
+/// Wire format of server messages, JSON in text frames or MessagePack in binary ones.
+/// Clients may send either regardless of what they asked to receive.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+/// Negotiated per connection during the handshake.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Protocol {
+    pub version: u16,
+    pub capabilities: Vec<Capability>,
+    pub encoding: Encoding,
+}
+
+impl Protocol {
+    /// Legacy clients only know the messages from before the handshake, they get none of the
+    /// optional groups.
+    pub fn legacy() -> Self {
+        Self { version: LEGACY_PROTOCOL_VERSION, capabilities: vec![], encoding: Encoding::Json }
+    }
+
+    /// Settles on the highest version both sides speak, `None` if it's below `min_version`.
+    pub fn negotiate(
+        version: u16,
+        capabilities: &[Capability],
+        encoding: Encoding,
+        min_version: u16,
+    ) -> Option<Self> {
+        let version = version.min(PROTOCOL_VERSION);
+        if version < min_version {
+            return None;
+        }
+
+        let capabilities =
+            ALL_CAPABILITIES.into_iter().filter(|c| capabilities.contains(c)).collect();
+
+        Some(Self { version, capabilities, encoding })
+    }
+
+    pub fn supports(&self, message: &ServerMessage) -> bool {
+        message.capability().is_none_or(|c| self.capabilities.contains(&c))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    Hello {
+        version: u16,
+        capabilities: Vec<Capability>,
+        #[serde(default)]
+        encoding: Encoding,
+    },
+    Keystroke {
+        key: String,
+        timestamp: u64,
+    },
+    /// Several keys in typing order, `timestamp` is the client clock in epoch milliseconds
+    KeystrokeBatch {
+        timestamp: u64,
+        keys: Vec<BatchedKey>,
+    },
+    Ready {
+        ready: bool,
+    },
+    RematchVote {
+        rematch: bool,
+    },
+    Chat {
+        text: String,
+    },
+    React {
+        reaction: Reaction,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchedKey {
+    pub key: String,
+    /// Milliseconds after the batch `timestamp`
+    pub offset_ms: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid MessagePack: {0}")]
+    Msgpack(#[from] rmp_serde::decode::Error),
+}
+
+impl ClientMessage {
+    /// Decodes a data frame by its kind, `None` for control frames.
+    pub fn decode(frame: &Message) -> Option<Result<Self, DecodeError>> {
+        match frame {
+            Message::Text(text) => Some(serde_json::from_str(text).map_err(Into::into)),
+            Message::Binary(bytes) => Some(rmp_serde::from_slice(bytes).map_err(Into::into)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ServerMessage {
+    Welcome { version: u16, capabilities: Vec<Capability>, encoding: Encoding },
+    Start { text: String, start_time: DateTime<Utc> },
+    Update { progress: f32, mistakes: i16, speed_wpm: f32 },
+    Finished { total_time_ms: u64, mistakes: i16, accuracy: f32, speed_wpm: f32 },
+    UserLeft { user_id: Uuid },
+    UserFinished { user_id: Uuid },
+    RoomUpdate { users: Vec<PlayerStats> },
+    AchievementUnlocked { code: String, name: String, description: String },
+    ProgressionUpdate { xp_gained: i32, xp: i64, level: i32, streak_days: i32 },
+    SegmentHandOff { team: i16, leg: usize, user_id: Uuid, text: String },
+    TeamStandings { teams: Vec<TeamStats> },
+    RaceOver { rematch_until: DateTime<Utc> },
+    RematchVotes { usernames: Vec<String> },
+    Rematch { room_id: Uuid },
+    ChatMessage { user_id: Uuid, username: String, text: String, sent_at: DateTime<Utc> },
+    Reaction { user_id: Uuid, username: String, reaction: Reaction },
+    Error { code: ErrorCode, message: String },
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error { code, message: message.into() }
+    }
+
+    pub fn to_frame(&self, encoding: Encoding) -> Message {
+        match encoding {
+            Encoding::Json => {
+                let text = serde_json::to_string(self).unwrap();
+                log::debug!("Socket message: {}", &text);
+                Message::Text(text.into())
+            },
+            // Named fields keep the `type` tag readable for internally tagged decoding
+            Encoding::Msgpack => Message::Binary(rmp_serde::to_vec_named(self).unwrap().into()),
+        }
+    }
+
+    /// Intermediate state that the next message of the same kind supersedes,
+    /// safe to skip for a client that lags behind.
+    pub fn is_droppable(&self) -> bool {
+        matches!(self, ServerMessage::RoomUpdate { .. } | ServerMessage::Update { .. })
+    }
+
+    /// Group of the message, `None` for the ones every client understands. Messages added
+    /// after the handshake belong to a group, older clients would choke on them.
+    fn capability(&self) -> Option<Capability> {
+        match self {
+            ServerMessage::ChatMessage { .. } | ServerMessage::Reaction { .. } => {
+                Some(Capability::Chat)
+            },
+            ServerMessage::SegmentHandOff { .. } | ServerMessage::TeamStandings { .. } => {
+                Some(Capability::Teams)
+            },
+            ServerMessage::RaceOver { .. }
+            | ServerMessage::RematchVotes { .. }
+            | ServerMessage::Rematch { .. } => Some(Capability::Rematch),
+            ServerMessage::AchievementUnlocked { .. } | ServerMessage::ProgressionUpdate { .. } => {
+                Some(Capability::Progression)
+            },
+            // Only sent in reply to `Hello`
+            ServerMessage::Welcome { .. } => None,
+            ServerMessage::Start { .. }
+            | ServerMessage::Update { .. }
+            | ServerMessage::Finished { .. }
+            | ServerMessage::UserLeft { .. }
+            | ServerMessage::UserFinished { .. }
+            | ServerMessage::RoomUpdate { .. }
+            | ServerMessage::Error { .. } => None,
+        }
+    }
+}
+
+/// Encodes a message at most once per encoding, the frames share their buffer between recipients.
+pub struct EncodedFrames<'a> {
+    message: &'a ServerMessage,
+    json: Option<Message>,
+    msgpack: Option<Message>,
+}
+
+impl<'a> EncodedFrames<'a> {
+    pub fn new(message: &'a ServerMessage) -> Self {
+        Self { message, json: None, msgpack: None }
+    }
+
+    pub fn get(&mut self, encoding: Encoding) -> Message {
+        let slot = match encoding {
+            Encoding::Json => &mut self.json,
+            Encoding::Msgpack => &mut self.msgpack,
+        };
+
+        slot.get_or_insert_with(|| self.message.to_frame(encoding)).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_below_the_minimum_is_refused() {
+        assert_eq!(Protocol::negotiate(1, &[], Encoding::Json, 2), None);
+        assert_eq!(Protocol::negotiate(0, &[], Encoding::Json, LEGACY_PROTOCOL_VERSION), None);
+    }
+
+    #[test]
+    fn newer_client_gets_the_server_version() {
+        let protocol = Protocol::negotiate(u16::MAX, &[], Encoding::Msgpack, 1).unwrap();
+
+        assert_eq!(protocol.version, PROTOCOL_VERSION);
+        assert_eq!(protocol.encoding, Encoding::Msgpack);
+    }
+
+    #[test]
+    fn older_supported_client_keeps_its_version() {
+        let protocol = Protocol::negotiate(1, &[], Encoding::Json, 1).unwrap();
+
+        assert_eq!(protocol.version, 1);
+    }
+
+    #[test]
+    fn capabilities_are_deduplicated_in_a_fixed_order() {
+        let requested = [Capability::Rematch, Capability::Chat, Capability::Rematch];
+        let protocol =
+            Protocol::negotiate(PROTOCOL_VERSION, &requested, Encoding::Json, 1).unwrap();
+
+        assert_eq!(protocol.capabilities, vec![Capability::Chat, Capability::Rematch]);
+    }
+
+    #[test]
+    fn messages_are_gated_by_capability() {
+        let chat = ServerMessage::Reaction {
+            user_id: Uuid::nil(),
+            username: "player".to_string(),
+            reaction: Reaction::Gg,
+        };
+        let left = ServerMessage::UserLeft { user_id: Uuid::nil() };
+
+        let legacy = Protocol::legacy();
+        assert!(!legacy.supports(&chat));
+        assert!(legacy.supports(&left));
+
+        let protocol =
+            Protocol::negotiate(PROTOCOL_VERSION, &[Capability::Chat], Encoding::Json, 1).unwrap();
+        assert!(protocol.supports(&chat));
+        assert!(!protocol.supports(&ServerMessage::Rematch { room_id: Uuid::nil() }));
+    }
+
+    #[test]
+    fn client_messages_decode_from_either_encoding() {
+        let json = Message::Text(r#"{"type":"Hello","version":2,"capabilities":["chat"]}"#.into());
+        let Some(Ok(ClientMessage::Hello { version, capabilities, encoding })) =
+            ClientMessage::decode(&json)
+        else {
+            panic!("Hello didn't decode from JSON");
+        };
+        assert_eq!((version, capabilities, encoding), (2, vec![Capability::Chat], Encoding::Json));
+
+        let ready = ClientMessage::Ready { ready: true };
+        let msgpack = Message::Binary(rmp_serde::to_vec_named(&ready).unwrap().into());
+        assert!(matches!(
+            ClientMessage::decode(&msgpack),
+            Some(Ok(ClientMessage::Ready { ready: true }))
+        ));
+
+        assert!(ClientMessage::decode(&Message::Ping(vec![].into())).is_none());
+        assert!(matches!(
+            ClientMessage::decode(&Message::Text("{".into())),
+            Some(Err(DecodeError::Json(_)))
+        ));
+    }
+}
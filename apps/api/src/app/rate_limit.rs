@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+/// Violations tolerated in `VIOLATION_WINDOW` before the connection is dropped.
+const MAX_VIOLATIONS: u32 = 50;
+const VIOLATION_WINDOW: Duration = Duration::seconds(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageClass {
+    Keystroke,
+    /// Everything else, including payloads that failed to parse
+    Control,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Budget {
+    burst: f64,
+    per_second: f64,
+}
+
+// Far above human typing speed, a bot pasting the text hits it at once
+const CONNECTION_KEYSTROKES: Budget = Budget { burst: 40.0, per_second: 25.0 };
+const CONNECTION_CONTROL: Budget = Budget { burst: 10.0, per_second: 2.0 };
+// Shared by all sockets of the user, so opening more of them doesn't raise the limit
+const USER_KEYSTROKES: Budget = Budget { burst: 60.0, per_second: 35.0 };
+const USER_CONTROL: Budget = Budget { burst: 20.0, per_second: 4.0 };
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    budget: Budget,
+    tokens: f64,
+    refilled_at: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    fn new(budget: Budget) -> Self {
+        Self { budget, tokens: budget.burst, refilled_at: Utc::now() }
+    }
+
+    pub fn try_take(&mut self, now: DateTime<Utc>, cost: f64) -> bool {
+        let elapsed = (now - self.refilled_at).as_seconds_f64().max(0.0);
+        self.tokens = (self.tokens + elapsed * self.budget.per_second).min(self.budget.burst);
+        // A clock stepping back refills nothing, and doesn't get the same time refilled twice
+        self.refilled_at = self.refilled_at.max(now);
+
+        if self.tokens < cost {
+            return false;
+        }
+
+        self.tokens -= cost;
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Buckets {
+    keystrokes: TokenBucket,
+    control: TokenBucket,
+}
+
+impl Buckets {
+    fn new(keystrokes: Budget, control: Budget) -> Self {
+        Self { keystrokes: TokenBucket::new(keystrokes), control: TokenBucket::new(control) }
+    }
+
+    fn bucket(&mut self, class: MessageClass) -> &mut TokenBucket {
+        match class {
+            MessageClass::Keystroke => &mut self.keystrokes,
+            MessageClass::Control => &mut self.control,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct UserLimits {
+    buckets: Buckets,
+    connections: usize,
+    violations: u64,
+}
+
+/// Budgets shared by all connections of a user.
+#[derive(Clone, Default)]
+pub struct WsRateLimits {
+    users: Arc<Mutex<HashMap<Uuid, UserLimits>>>,
+}
+
+impl WsRateLimits {
+    pub fn connect(&self, user_id: Uuid) -> ConnectionLimiter {
+        let mut users = self.users.lock().unwrap();
+        let user = users.entry(user_id).or_insert_with(|| UserLimits {
+            buckets: Buckets::new(USER_KEYSTROKES, USER_CONTROL),
+            connections: 0,
+            violations: 0,
+        });
+        user.connections += 1;
+
+        ConnectionLimiter {
+            user_id,
+            limits: self.clone(),
+            buckets: Buckets::new(CONNECTION_KEYSTROKES, CONNECTION_CONTROL),
+            violations: 0,
+            window_start: Utc::now(),
+            throttled: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Drop the message, `notify` is set on the first one of a streak
+    Throttle {
+        notify: bool,
+    },
+    Disconnect,
+}
+
+pub struct ConnectionLimiter {
+    user_id: Uuid,
+    limits: WsRateLimits,
+    buckets: Buckets,
+    violations: u32,
+    window_start: DateTime<Utc>,
+    throttled: bool,
+}
+
+impl ConnectionLimiter {
+    /// `cost` is the number of messages the frame stands for, a keystroke batch pays per key.
+    pub fn check(&mut self, class: MessageClass, cost: usize) -> Verdict {
+        let now = Utc::now();
+        let cost = cost as f64;
+
+        // Both budgets are charged, a message refused by one still counts against the other
+        let connection_ok = self.buckets.bucket(class).try_take(now, cost);
+        let user_ok = {
+            let mut users = self.limits.users.lock().unwrap();
+            users.get_mut(&self.user_id).is_none_or(|u| u.buckets.bucket(class).try_take(now, cost))
+        };
+
+        if connection_ok && user_ok {
+            self.throttled = false;
+            return Verdict::Allow;
+        }
+
+        if now - self.window_start > VIOLATION_WINDOW {
+            self.window_start = now;
+            self.violations = 0;
+        }
+        self.violations += 1;
+
+        let total = {
+            let mut users = self.limits.users.lock().unwrap();
+            users.get_mut(&self.user_id).map_or(0, |u| {
+                u.violations += 1;
+                u.violations
+            })
+        };
+
+        if self.violations > MAX_VIOLATIONS {
+            log::warn!(
+                target: "anti_abuse",
+                "User {} disconnected for flooding: {} {:?} violations in a minute, {} total",
+                self.user_id,
+                self.violations,
+                class,
+                total
+            );
+            return Verdict::Disconnect;
+        }
+
+        let notify = !self.throttled;
+        if notify {
+            log::warn!(
+                target: "anti_abuse",
+                "User {} throttled on {:?} messages, {} violations total",
+                self.user_id,
+                class,
+                total
+            );
+        }
+        self.throttled = true;
+
+        Verdict::Throttle { notify }
+    }
+}
+
+impl Drop for ConnectionLimiter {
+    fn drop(&mut self) {
+        let mut users = self.limits.users.lock().unwrap();
+
+        if let Some(user) = users.get_mut(&self.user_id) {
+            user.connections -= 1;
+            if user.connections == 0 {
+                if user.violations > 0 {
+                    log::info!(
+                        target: "anti_abuse",
+                        "User {} left with {} rate limit violations",
+                        self.user_id,
+                        user.violations
+                    );
+                }
+                users.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Budget = Budget { burst: 2.0, per_second: 1.0 };
+
+    #[test]
+    fn empty_bucket_refuses_until_refilled() {
+        let mut bucket = TokenBucket::new(BUDGET);
+        let start = bucket.refilled_at;
+
+        assert!(bucket.try_take(start, 1.0));
+        assert!(bucket.try_take(start, 1.0));
+        assert!(!bucket.try_take(start, 1.0));
+        assert!(!bucket.try_take(start + Duration::milliseconds(500), 1.0));
+        assert!(bucket.try_take(start + Duration::seconds(1), 1.0));
+    }
+
+    #[test]
+    fn refill_stops_at_the_burst() {
+        let mut bucket = TokenBucket::new(BUDGET);
+        let later = bucket.refilled_at + Duration::hours(1);
+
+        assert!(bucket.try_take(later, 2.0));
+        assert!(!bucket.try_take(later, 1.0));
+    }
+
+    #[test]
+    fn cost_above_the_burst_is_never_allowed() {
+        let mut bucket = TokenBucket::new(BUDGET);
+
+        assert!(!bucket.try_take(bucket.refilled_at, 3.0));
+        assert!(bucket.try_take(bucket.refilled_at, 2.0));
+    }
+
+    #[test]
+    fn clock_rollback_does_not_refill() {
+        let mut bucket = TokenBucket::new(BUDGET);
+        let start = bucket.refilled_at;
+
+        assert!(bucket.try_take(start, 2.0));
+        assert!(!bucket.try_take(start - Duration::seconds(10), 1.0));
+        assert!(!bucket.try_take(start, 1.0));
+    }
+
+    #[test]
+    fn throttle_notifies_once_per_streak() {
+        let limits = WsRateLimits::default();
+        let mut limiter = limits.connect(Uuid::new_v4());
+
+        for _ in 0..CONNECTION_CONTROL.burst as usize {
+            assert_eq!(limiter.check(MessageClass::Control, 1), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(MessageClass::Control, 1), Verdict::Throttle { notify: true });
+        assert_eq!(limiter.check(MessageClass::Control, 1), Verdict::Throttle { notify: false });
+        assert_eq!(limiter.check(MessageClass::Keystroke, 1), Verdict::Allow);
+    }
+
+    #[test]
+    fn flooding_disconnects() {
+        let limits = WsRateLimits::default();
+        let mut limiter = limits.connect(Uuid::new_v4());
+
+        let verdicts: Vec<_> = (0..CONNECTION_CONTROL.burst as u32 + MAX_VIOLATIONS + 1)
+            .map(|_| limiter.check(MessageClass::Control, 1))
+            .collect();
+
+        assert_eq!(verdicts.last(), Some(&Verdict::Disconnect));
+    }
+
+    #[test]
+    fn user_budget_is_shared_between_connections() {
+        let limits = WsRateLimits::default();
+        let user_id = Uuid::new_v4();
+        let mut first = limits.connect(user_id);
+        let mut second = limits.connect(user_id);
+
+        assert_eq!(first.check(MessageClass::Control, 10), Verdict::Allow);
+        assert_eq!(second.check(MessageClass::Control, 10), Verdict::Allow);
+
+        let mut third = limits.connect(user_id);
+        assert_eq!(third.check(MessageClass::Control, 1), Verdict::Throttle { notify: true });
+
+        drop((first, second, third));
+        assert!(limits.users.lock().unwrap().is_empty());
+    }
+}
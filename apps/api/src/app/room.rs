@@ -1,19 +1,23 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use axum::extract::ws::Message;
 use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel_async::scoped_futures::ScopedFutureExt;
 use serde::{Deserialize, Serialize};
-use tokio::sync::{RwLock, mpsc::UnboundedSender, watch};
+use tokio::sync::{RwLock, mpsc, oneshot, watch};
 use uuid::Uuid;
 
 use crate::{
-    app::types::DbPool,
+    app::types::{DbConn, DbPool},
     db::{
-        custom_types::{Leagues, UserRoles},
+        custom_types::{Leagues, LeaveReasons, RoomKinds, RoomModes, RoomStatuses, UserRoles},
         models::{
             dictionary::Dictionary,
-            result::{Keystroke, ResultStats, Results},
+            result::{Keystroke, ResultStats},
             room::Room as RoomModel,
+            room_snapshot::RoomSnapshot,
             room_user::RoomUser,
             text::Text,
             user::User,
@@ -21,24 +25,24 @@ use crate::{
     },
 };
 
-use super::{error::MyError, types::MyResult};
-
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
-pub enum WsMessage {
-    Start { text: String, start_time: DateTime<Utc> },
-    Keystroke { key: String, timestamp: u64 },
-    Update { progress: f32, mistakes: i16, speed_wpm: f32 },
-    Finished { total_time_ms: u64, mistakes: i16, accuracy: f32, speed_wpm: f32 },
-    UserLeft { user_id: Uuid },
-    UserFinished { user_id: Uuid },
-    RoomUpdate { users: Vec<PlayerStats> },
-    Error { message: String },
-}
+use super::{
+    chat::{AllowAllFilter, ChatFilter, ChatThrottle},
+    checkpoint::{RECONNECT_GRACE, RoomState},
+    client_clock::ClientClock,
+    cluster::{Cluster, ClusterMessage, ClusterReply, HEARTBEAT_INTERVAL},
+    db_writer::DbWriter,
+    error::MyError,
+    outbound::PlayerSender,
+    protocol::{ClientMessage, DecodeError, EncodedFrames, ErrorCode, Protocol, ServerMessage},
+    room_actor::{RoomActor, RoomCommand},
+    room_directory::{LobbyEvent, RoomDirectory},
+    types::MyResult,
+};
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug, utoipa::ToSchema)]
 pub enum PlayerStatus {
     Idle,
+    Ready,
     Started,
     Dropped,
     Finished,
@@ -51,31 +55,253 @@ pub struct PlayerStats {
     pub mistakes: i16,
     pub progress: f32,
     pub status: PlayerStatus,
+    pub team: Option<i16>,
+    pub host: bool,
 }
 
-#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TeamStats {
+    pub team: i16,
+    pub members: Vec<String>,
+    pub score: f32,
+    pub placement: i16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct RoomStats {
     pub room_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// First player who joined
+    pub host: Option<String>,
     pub players: usize,
+    pub capacity: usize,
+    /// Still in the lobby with a free seat
+    pub joinable: bool,
     pub started: bool,
+    pub status: RoomStatuses,
+    /// When the race starts, once the countdown is running
+    pub countdown_ends_at: Option<DateTime<Utc>>,
+    /// Of the players racing, 0 before the start
+    pub average_wpm: f32,
     pub dictionary: Dictionary,
+    pub kind: RoomKind,
+    pub mode: RoomMode,
+    pub league: Leagues,
+    /// Messages waiting in the outbound queues of all players
+    pub queued_messages: usize,
+    /// Deepest outbound queue, close to the capacity means a client is about to be dropped
+    pub max_queue_depth: usize,
+}
+
+/// What the room is for. Special kinds pin the text and get extra bookkeeping on results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RoomKind {
+    Regular,
+    /// Played alone by `user_id`. `ranked_attempt` is the attempt reserved for a ranked run,
+    /// unset for retries.
+    Daily {
+        challenge_id: Uuid,
+        user_id: Uuid,
+        ranked_attempt: Option<Uuid>,
+    },
+    Tournament {
+        tournament_id: Uuid,
+        match_id: Uuid,
+        players: Vec<Uuid>,
+    },
+}
+
+impl RoomKind {
+    pub fn db_kind(&self) -> RoomKinds {
+        match self {
+            RoomKind::Regular => RoomKinds::Regular,
+            RoomKind::Daily { .. } => RoomKinds::Daily,
+            RoomKind::Tournament { .. } => RoomKinds::Tournament,
+        }
+    }
+
+    /// Only players of the match may enter a tournament room, only its player a daily one.
+    pub fn allows_player(&self, user_id: Uuid) -> bool {
+        match self {
+            RoomKind::Daily { user_id: player, .. } => *player == user_id,
+            RoomKind::Tournament { players, .. } => players.contains(&user_id),
+            _ => true,
+        }
+    }
+}
+
+/// How players compete. In a relay every team types the whole text, one segment per member.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RoomMode {
+    Solo,
+    Team { teams: u8 },
+    Relay { teams: u8 },
+}
+
+impl RoomMode {
+    pub fn db_mode(&self) -> RoomModes {
+        match self {
+            RoomMode::Solo => RoomModes::Solo,
+            RoomMode::Team { .. } => RoomModes::Team,
+            RoomMode::Relay { .. } => RoomModes::Relay,
+        }
+    }
+
+    pub fn teams(&self) -> u8 {
+        match self {
+            RoomMode::Solo => 0,
+            RoomMode::Team { teams } | RoomMode::Relay { teams } => *teams,
+        }
+    }
+}
+
+impl RoomStatuses {
+    /// Statuses a room may come to this one from. Rooms only move forward, and a room can only
+    /// be aborted before its race is over.
+    pub fn previous(&self) -> &'static [RoomStatuses] {
+        match self {
+            RoomStatuses::Created => &[],
+            RoomStatuses::Countdown => &[RoomStatuses::Created],
+            RoomStatuses::Running => &[RoomStatuses::Countdown],
+            RoomStatuses::Finished => &[RoomStatuses::Running],
+            RoomStatuses::Aborted => {
+                &[RoomStatuses::Created, RoomStatuses::Countdown, RoomStatuses::Running]
+            },
+        }
+    }
+
+    /// Teams and texts are handed out
+    pub fn countdown_started(&self) -> bool {
+        !matches!(self, RoomStatuses::Created | RoomStatuses::Aborted)
+    }
+
+    /// Players may type, or typed already
+    pub fn started(&self) -> bool {
+        matches!(self, RoomStatuses::Running | RoomStatuses::Finished)
+    }
+}
+
+/// How a room is set up, fixed once it's created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomOptions {
+    pub kind: RoomKind,
+    pub mode: RoomMode,
+    pub ready_quorum: u8,
+    pub league: Leagues,
+    /// Series the room continues, a new series starting with the room if unset
+    pub series_id: Option<Uuid>,
+    /// Room this one is a rematch of
+    pub previous_room_id: Option<Uuid>,
+}
+
+impl RoomOptions {
+    /// Solo web room everyone has to be ready for.
+    pub fn new(kind: RoomKind) -> Self {
+        Self {
+            kind,
+            mode: RoomMode::Solo,
+            ready_quorum: DEFAULT_READY_QUORUM,
+            league: Leagues::Web,
+            series_id: None,
+            previous_room_id: None,
+        }
+    }
+}
+
+/// Players a room takes, tournament rooms take their match players only.
+pub const ROOM_CAPACITY: usize = 16;
+
+/// How long players may vote for a rematch once the race is over.
+pub const REMATCH_WINDOW: Duration = Duration::seconds(30);
+
+/// Everyone has to be ready unless the room asks for less.
+pub const DEFAULT_READY_QUORUM: u8 = 100;
+
+/// Points a member adds to the team score for every player finishing behind them.
+const PLACEMENT_POINTS: f32 = 10.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Team {
+    pub index: i16,
+    /// In join order, which is also the relay order
+    pub members: Vec<Uuid>,
+    /// Relay only, filled on start with one segment per member
+    pub segments: Vec<String>,
+    pub active_leg: usize,
+    /// Relay only, order in which the team crossed the line
+    pub placement: Option<i16>,
+}
+
+/// What happened to the relay after a runner finished their segment.
+pub enum LegEnd {
+    HandOff { team: i16, leg: usize, user_id: Uuid },
+    TeamFinished { members: Vec<Uuid> },
+}
+
+/// Splits the text on word boundaries into `parts` segments of about the same length.
+/// Fewer segments come out only if the text has fewer words than `parts`.
+fn split_segments(text: &str, parts: usize) -> Vec<String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let parts = parts.clamp(1, words.len().max(1));
+    let target = text.chars().count().div_ceil(parts);
+
+    let mut segments = vec![];
+    let mut current = String::new();
+
+    for (i, word) in words.iter().enumerate() {
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+
+        let words_left = words.len() - i - 1;
+        let cuts_left = parts.saturating_sub(segments.len() + 1);
+        let length = current.chars().count();
+        // Cut here if taking the next word would land further past the target than we are short
+        let with_next = words.get(i + 1).map_or(length, |next| length + 1 + next.chars().count());
+
+        if cuts_left > 0
+            && words_left >= cuts_left
+            && (length >= target
+                || with_next.saturating_sub(target) > target - length
+                || words_left == cuts_left)
+        {
+            segments.push(std::mem::take(&mut current));
+        }
+    }
+
+    if !current.is_empty() {
+        segments.push(current);
+    }
+
+    segments
 }
 
 #[derive(Clone, Serialize)]
 pub struct Room {
     pub id: Uuid,
+    pub kind: RoomKind,
+    pub mode: RoomMode,
+    pub league: Leagues,
+    pub created_at: DateTime<Utc>,
+    /// First player who joined
+    pub host: Option<Uuid>,
+    pub teams: Vec<Team>,
     pub text: Text,
     pub dictionary: Dictionary,
     pub players: HashMap<Uuid, Player>,
-    pub started: bool,
+    /// Only changed through `transition`, `Finished` opens the rematch vote
+    pub status: RoomStatuses,
+    /// Percent of the connected players that must be ready to start the countdown
+    pub ready_quorum: u8,
     pub start_time: DateTime<Utc>,
-    #[serde(skip)]
-    pub start_notifier: watch::Sender<bool>,
 }
 
 #[derive(Serialize, Deserialize, utoipa::ToSchema, Clone)]
 pub struct UserInfo {
-    username: String,
+    pub username: String,
     created_at: NaiveDateTime,
     role: UserRoles,
 }
@@ -85,8 +311,15 @@ pub struct Player {
     pub id: Uuid,
     pub user: UserInfo,
     pub room_user_id: Uuid,
+    pub joined_at: DateTime<Utc>,
     #[serde(skip_deserializing, skip_serializing)]
-    pub sender: UnboundedSender<Message>, // axum::extract::ws::WebSocketSender,
+    pub sender: PlayerSender,
+    /// Room the player's socket talks to, moved on rematch
+    #[serde(skip)]
+    pub room_binding: watch::Sender<Uuid>,
+    #[serde(skip)]
+    pub protocol: Protocol,
+    pub rematch: bool,
     pub typed_text: String,
     pub mistakes: i16,
     pub last_is_mistake: bool,
@@ -94,128 +327,526 @@ pub struct Player {
     pub status: PlayerStatus,
     pub connected: bool,
     pub stats: ResultStats,
+    pub placement: Option<i16>,
+    pub team: Option<i16>,
+    /// Race start, or the hand-off moment for later relay legs
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub chat_throttle: ChatThrottle,
+    pub chat_muted_until: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub clock: ClientClock,
+}
+
+impl Player {
+    /// Sends the message if the player's protocol has it.
+    pub fn send(&self, message: ServerMessage) {
+        if self.protocol.supports(&message) {
+            self.sender.send(message.to_frame(self.protocol.encoding), message.is_droppable());
+        }
+    }
+
+    /// Compares the key with the expected character and records it in the stats.
+    pub fn type_key(&mut self, key: String, text: &str, at: DateTime<Utc>) {
+        let timestamp = at.naive_utc();
+        let expected_char = text.chars().nth(self.typed_text.chars().count());
+
+        if let Some(expected) = expected_char {
+            if key == expected.to_string() {
+                self.typed_text.push_str(&key);
+                self.last_is_mistake = false;
+                self.stats.keystrokes.push(Keystroke {
+                    key,
+                    mistake: false,
+                    expected: None,
+                    timestamp,
+                })
+            } else {
+                log::debug!("Mistake: expected '{}' but got '{}'", &expected, &key);
+                if !self.last_is_mistake {
+                    self.last_is_mistake = true;
+                    self.mistakes += 1;
+                    self.stats.keystrokes.push(Keystroke {
+                        key,
+                        mistake: true,
+                        expected: Some(expected.to_string()),
+                        timestamp,
+                    });
+                } else if let Some(keystroke) = self.stats.keystrokes.last_mut() {
+                    keystroke.key += &key
+                };
+            }
+        } else {
+            // extra key presses after end
+            if !self.last_is_mistake {
+                self.last_is_mistake = true;
+                self.mistakes += 1;
+                self.stats.keystrokes.push(Keystroke {
+                    key,
+                    mistake: true,
+                    expected: None,
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    /// Why the player is out if they leave now, `None` once that's settled.
+    pub fn leave_reason(&self, room_status: RoomStatuses) -> Option<LeaveReasons> {
+        match room_status {
+            RoomStatuses::Created => Some(LeaveReasons::Left),
+            RoomStatuses::Countdown | RoomStatuses::Running => {
+                if self.finished_at.is_some() {
+                    None
+                } else if self.status == PlayerStatus::Spectator {
+                    Some(LeaveReasons::Left)
+                } else {
+                    Some(LeaveReasons::Dropped)
+                }
+            },
+            RoomStatuses::Finished | RoomStatuses::Aborted => None,
+        }
+    }
+
+    /// Speed in WPM and CPM from the player's start up to `until`.
+    pub fn speeds(&self, until: DateTime<Utc>) -> (f32, f32) {
+        let elapsed_secs = (until - self.started_at).as_seconds_f32();
+        if elapsed_secs <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        let words_typed = self.typed_text.split_whitespace().count() as f32;
+        let typed_count = self.typed_text.chars().count() as f32;
+
+        ((words_typed / elapsed_secs) * 60.0, (typed_count / elapsed_secs) * 60.0)
+    }
 }
 
 #[derive(Clone)]
 pub struct RoomsManager {
     pub db: DbPool,
-    pub rooms: Arc<RwLock<HashMap<Uuid, Arc<RwLock<Room>>>>>,
+    pub writer: DbWriter,
+    /// Inboxes of the room actors running on this node
+    pub rooms: Arc<RwLock<HashMap<Uuid, mpsc::Sender<RoomCommand>>>>,
+    /// Commands from other nodes on their way into the inboxes, one queue per room
+    pub relays: Arc<Mutex<HashMap<Uuid, mpsc::UnboundedSender<RoomCommand>>>>,
+    pub chat_filter: Arc<dyn ChatFilter>,
+    pub cluster: Cluster,
+    pub directory: RoomDirectory,
+}
+
+/// Where commands for a room go.
+enum RoomRoute {
+    Local(mpsc::Sender<RoomCommand>),
+    Remote(String),
+    Missing,
 }
 
 impl RoomsManager {
-    pub fn new(db: DbPool) -> Self {
-        Self { db, rooms: Arc::new(RwLock::new(HashMap::new())) }
+    pub fn new(db: DbPool, cluster: Cluster) -> Self {
+        Self {
+            writer: DbWriter::spawn(db.clone()),
+            db,
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+            relays: Default::default(),
+            chat_filter: Arc::new(AllowAllFilter),
+            cluster,
+            directory: RoomDirectory::default(),
+        }
     }
 
-    pub async fn create_room(&self, text: Text, dictionary: Dictionary) -> Uuid {
-        let (start_notifier, _) = watch::channel(false);
-        let id = Uuid::new_v4();
+    pub fn with_chat_filter(mut self, filter: impl ChatFilter + 'static) -> Self {
+        self.chat_filter = Arc::new(filter);
+        self
+    }
 
-        let room = Room {
-            id,
-            text,
-            dictionary,
-            players: HashMap::new(),
-            started: false,
-            start_time: chrono::Utc::now(),
-            start_notifier,
-        };
+    /// Joins the cluster and brings back the rooms this node ran before the last shutdown.
+    /// From then on it heartbeats and adopts the rooms of nodes that stopped doing so.
+    pub async fn start(&self) {
+        let mut conn = self.db.get().await.unwrap();
+        self.cluster.heartbeat(&mut conn).await;
+        self.adopt_rooms(&mut conn, true).await;
+        drop(conn);
+
+        self.cluster.spawn_listener(self.clone());
+        self.sync_directory(&[]);
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                let Ok(mut conn) = manager.db.get().await else {
+                    log::error!("Cluster heartbeat skipped, no connection");
+                    continue;
+                };
+                let known = manager.cluster.other_nodes();
+                manager.cluster.heartbeat(&mut conn).await;
+                manager.sync_directory(&known);
+                manager.adopt_rooms(&mut conn, false).await;
+            }
+        });
+    }
+
+    pub async fn create_room(
+        &self,
+        text: Text,
+        dictionary: Dictionary,
+        options: RoomOptions,
+    ) -> MyResult<Uuid> {
+        let id = Uuid::new_v4();
+        let owner = self.cluster.owner_for(id);
+        let room = Self::_new_room(id, text, dictionary, options.clone());
 
         let room_model = RoomModel {
             id,
             text_id: room.text.id,
             created_at: chrono::Utc::now().naive_utc(),
-            // Update after some time
-            started_at: chrono::Utc::now().naive_utc(),
-            ended_at: chrono::Utc::now().naive_utc(),
+            started_at: None,
+            ended_at: None,
+            kind: room.kind.db_kind(),
+            mode: room.mode.db_mode(),
+            series_id: options.series_id.unwrap_or(id),
+            previous_room_id: options.previous_room_id,
+            node_id: Some(owner.clone()),
+            status: RoomStatuses::Created,
         };
 
-        let mut conn = self.db.get().await.unwrap();
-        let _ = room_model.insert_room(&mut conn).await;
+        // Other nodes find the owner through the row, so it goes in before anyone can join
+        let mut conn = self.db.get().await?;
+        room_model.insert_room(&mut conn).await?;
+
+        if !self.cluster.is_local(&owner) {
+            let reply = self
+                .cluster
+                .request(&owner, |request_id, from| ClusterMessage::CreateRoom {
+                    request_id,
+                    from,
+                    room_id: id,
+                    text_id: room.text.id,
+                    dictionary_id: room.dictionary.id,
+                    options,
+                })
+                .await;
+
+            if let Some(ClusterReply::Done { result: Ok(()) }) = reply {
+                self.cluster.remember_owner(id, owner);
+                return Ok(id);
+            }
 
-        let locked_room = Arc::new(RwLock::new(room));
+            log::warn!("Node {} didn't take room {}, running it here", owner, id);
+            let _ = RoomModel::claim_room(&mut conn, id, Some(&owner), &self.cluster.node_id).await;
+        }
 
-        self.rooms.write().await.insert(id, locked_room);
+        let inbox = RoomActor::spawn(room, self.clone());
+        self.rooms.write().await.insert(id, inbox);
 
-        let rooms = self.rooms.clone();
+        Ok(id)
+    }
 
-        // Stats autoupdate task
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+    /// Runs a room another node created for this one.
+    pub async fn _create_remote_room(
+        &self,
+        room_id: Uuid,
+        text_id: Uuid,
+        dictionary_id: Uuid,
+        options: RoomOptions,
+    ) -> MyResult<()> {
+        let mut conn = self.db.get().await?;
 
-                let Some(room) = rooms.read().await.get(&id).cloned() else {
-                    log::debug!("Room {} destroyed, task also", id);
-                    break;
-                };
+        let (Some(text), Some(dictionary)) = (
+            Text::get_text_by_id(&mut conn, text_id).await?,
+            Dictionary::get_dictionary_by_id(&mut conn, dictionary_id).await?,
+        ) else {
+            return Err(MyError::NotFound);
+        };
 
-                let room = room.read().await;
+        let room = Self::_new_room(room_id, text, dictionary, options);
+        let inbox = RoomActor::spawn(room, self.clone());
+        self.rooms.write().await.insert(room_id, inbox);
 
-                if !room.started {
+        Ok(())
+    }
+
+    fn _new_room(id: Uuid, text: Text, dictionary: Dictionary, options: RoomOptions) -> Room {
+        let teams = (0..options.mode.teams() as i16)
+            .map(|index| Team {
+                index,
+                members: vec![],
+                segments: vec![],
+                active_leg: 0,
+                placement: None,
+            })
+            .collect();
+
+        Room {
+            id,
+            kind: options.kind,
+            mode: options.mode,
+            league: options.league,
+            created_at: chrono::Utc::now(),
+            host: None,
+            teams,
+            text,
+            dictionary,
+            players: HashMap::new(),
+            status: RoomStatuses::Created,
+            ready_quorum: options.ready_quorum.clamp(1, 100),
+            start_time: chrono::Utc::now(),
+        }
+    }
+
+    /// Restores the checkpointed rooms this node is responsible for: on startup the ones it ran
+    /// before, and at any time the ones of dead nodes it wins by rendezvous hashing. Those are
+    /// claimed in the database first, so a room is never adopted twice.
+    async fn adopt_rooms(&self, conn: &mut DbConn, startup: bool) {
+        let snapshots = match RoomSnapshot::get_snapshots_with_node(conn).await {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                log::error!("Failed to load room snapshots: {:?}", e);
+                return;
+            },
+        };
+
+        for (snapshot, node) in snapshots {
+            let room_id = snapshot.room_id;
+            let owned = node.as_deref().is_some_and(|n| self.cluster.is_local(n));
+
+            // Running rooms of this node are already in `rooms`, or about to drop the snapshot
+            let adopt = match owned {
+                true => startup,
+                false => {
+                    node.as_deref().is_none_or(|n| !self.cluster.is_alive(n))
+                        && self.cluster.is_local(&self.cluster.owner_for(room_id))
+                },
+            };
+            if !adopt {
+                continue;
+            }
+
+            if !owned {
+                let claimed =
+                    RoomModel::claim_room(conn, room_id, node.as_deref(), &self.cluster.node_id)
+                        .await;
+                if !matches!(claimed, Ok(true)) {
                     continue;
                 }
+                log::info!("Room {} adopted from node {:?}", room_id, node);
+            }
 
-                let stats = room
-                    .players
-                    .values()
-                    .map(|v| PlayerStats {
-                        username: v.user.username.clone(),
-                        mistakes: v.mistakes,
-                        progress: v.progress,
-                        status: v.status.clone(),
-                    })
-                    .collect();
+            self._restore_room(conn, snapshot).await;
+        }
+    }
 
-                room.broadcast_message(WsMessage::RoomUpdate { users: stats }).await;
-            }
+    /// Rooms nobody rejoins within `RECONNECT_GRACE`, or that can't be rebuilt, are aborted.
+    async fn _restore_room(&self, conn: &mut DbConn, snapshot: RoomSnapshot) {
+        let room_id = snapshot.room_id;
+
+        let Ok(state) = serde_json::from_value::<RoomState>(snapshot.state) else {
+            log::error!("Room {} has an unreadable snapshot", room_id);
+            self._abort_room(room_id).await;
+            return;
+        };
+
+        let (Ok(Some(text)), Ok(Some(dictionary))) = (
+            Text::get_text_by_id(conn, state.text_id()).await,
+            Dictionary::get_dictionary_by_id(conn, state.dictionary_id()).await,
+        ) else {
+            log::error!("Text or dictionary of room {} is gone", room_id);
+            self._abort_room(room_id).await;
+            return;
+        };
+
+        let inbox = RoomActor::spawn(state.restore(room_id, text, dictionary), self.clone());
+        self.rooms.write().await.insert(room_id, inbox.clone());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(RECONNECT_GRACE).await;
+            let _ = inbox.send(RoomCommand::ReconnectDeadline).await;
         });
 
-        id
+        log::info!("Room {} restored from checkpoint", room_id);
     }
 
-    pub async fn get_stats(&self) -> Vec<RoomStats> {
-        let rooms = self.rooms.read().await;
+    /// Closes the room in the database for good, for rooms that end without a race result.
+    /// Players still in it dropped out.
+    pub async fn _abort_room(&self, room_id: Uuid) {
+        let ended_at = chrono::Utc::now().naive_utc();
 
-        let mut list = vec![];
-        for i in rooms.values() {
-            let room = i.read().await;
-            let stats = RoomStats {
-                room_id: room.id,
-                players: room.players.len(),
-                started: room.started,
-                dictionary: room.dictionary.clone(),
-            };
+        self.writer
+            .submit(room_id, move |conn| {
+                async move {
+                    let _ = RoomSnapshot::delete_snapshot(conn, room_id).await;
+
+                    match RoomModel::set_status(conn, room_id, RoomStatuses::Aborted, ended_at)
+                        .await
+                    {
+                        Ok(true) => log::info!("Room {} aborted", room_id),
+                        Ok(false) => log::debug!("Room {} was closed already", room_id),
+                        Err(e) => log::error!("Failed to abort room {}: {:?}", room_id, e),
+                    }
+
+                    let dropped = LeaveReasons::Dropped;
+                    if let Err(e) =
+                        RoomUser::close_remaining(conn, room_id, ended_at, dropped).await
+                    {
+                        log::error!("Failed to close players of room {}: {:?}", room_id, e);
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+    }
+
+    pub async fn has_room(&self, room_id: Uuid) -> bool {
+        match self._route(room_id).await {
+            RoomRoute::Local(_) => true,
+            RoomRoute::Remote(node) => {
+                let reply = self
+                    .cluster
+                    .request(&node, |request_id, from| ClusterMessage::Exists {
+                        request_id,
+                        from,
+                        room_id,
+                    })
+                    .await;
 
-            list.push(stats);
+                matches!(reply, Some(ClusterReply::Done { result: Ok(()) }))
+            },
+            RoomRoute::Missing => false,
         }
+    }
 
-        list
+    /// Loads the rooms of nodes that joined since `known`, later changes come as lobby
+    /// events. Rooms of nodes that left are dropped.
+    fn sync_directory(&self, known: &[String]) {
+        let nodes = self.cluster.other_nodes();
+        self.directory.retain_nodes(&nodes);
+
+        for node in nodes.into_iter().filter(|node| !known.contains(node)) {
+            let manager = self.clone();
+            tokio::spawn(async move {
+                let reply = manager
+                    .cluster
+                    .request(&node, |request_id, from| ClusterMessage::Stats { request_id, from })
+                    .await;
+
+                match reply {
+                    Some(ClusterReply::Stats { rooms }) => {
+                        manager.directory.sync_node(&node, rooms)
+                    },
+                    _ => log::warn!("Node {} didn't send its rooms", node),
+                }
+            });
+        }
     }
 
+    /// The socket stays on this node when the room runs on another one, the owner relays
+    /// the room's messages back to it.
     pub async fn join_room(
         &self,
         room_id: Uuid,
         user: User,
-        sender: UnboundedSender<Message>,
+        sender: PlayerSender,
+        room_binding: watch::Sender<Uuid>,
+        protocol: Protocol,
     ) -> MyResult<()> {
-        let Some(room) = self._get_room(room_id).await else {
+        log::debug!("Joining room {}", room_id);
+
+        match self._route(room_id).await {
+            RoomRoute::Local(inbox) => {
+                let player = Self::_new_player(user, sender, room_binding, protocol);
+                Self::_join_local(inbox, player).await
+            },
+            RoomRoute::Remote(node) => {
+                let connection_id = self.cluster.register_connection(sender, room_binding);
+
+                let reply = self
+                    .cluster
+                    .request(&node, |request_id, from| ClusterMessage::Join {
+                        request_id,
+                        from,
+                        room_id,
+                        connection_id,
+                        user_id: user.id,
+                        protocol,
+                    })
+                    .await;
+
+                let joined = match reply {
+                    Some(ClusterReply::Done { result }) => result.map_err(Into::into),
+                    _ => Err(MyError::InternalError),
+                };
+                if joined.is_err() {
+                    self.cluster.drop_connection(connection_id);
+                }
+
+                joined
+            },
+            RoomRoute::Missing => {
+                log::error!("Try to join room that doesn't exist");
+                Err(MyError::InternalError)
+            },
+        }
+    }
+
+    /// Joins a player whose socket is on node `from`, through a sender that forwards there.
+    pub async fn _join_remote_player(
+        &self,
+        room_id: Uuid,
+        from: &str,
+        connection_id: Uuid,
+        user_id: Uuid,
+        protocol: Protocol,
+    ) -> MyResult<()> {
+        let inbox = self.rooms.read().await.get(&room_id).cloned().ok_or(MyError::NotFound)?;
+
+        let mut conn = self.db.get().await?;
+        let user = User::get_user(&mut conn, user_id).await?.ok_or(MyError::NotFound)?;
+        drop(conn);
+
+        let (sender, rx, overflow) = PlayerSender::channel();
+        let (room_binding, _) = watch::channel(room_id);
+        self.cluster.forward(from.to_string(), connection_id, rx, overflow, &room_binding);
+
+        Self::_join_local(inbox, Self::_new_player(user, sender, room_binding, protocol)).await
+    }
+
+    async fn _join_local(inbox: mpsc::Sender<RoomCommand>, player: Player) -> MyResult<()> {
+        let (reply, joined) = oneshot::channel();
+        let command = RoomCommand::Join { player: Box::new(player), reply };
+
+        if inbox.send(command).await.is_err() {
             log::error!("Try to join room that doesn't exist");
             return Err(MyError::InternalError);
-        };
-
-        log::debug!("Joining room {}", room_id);
+        }
 
-        let room_user_id = Uuid::new_v4();
+        joined.await.unwrap_or(Err(MyError::InternalError))
+    }
 
-        let live_player = Player {
+    fn _new_player(
+        user: User,
+        sender: PlayerSender,
+        room_binding: watch::Sender<Uuid>,
+        protocol: Protocol,
+    ) -> Player {
+        Player {
             id: user.id,
             user: UserInfo {
                 username: user.username,
                 created_at: user.created_at,
                 role: user.role,
             },
-            room_user_id,
+            room_user_id: Uuid::new_v4(),
+            joined_at: chrono::Utc::now(),
             sender,
+            room_binding,
+            protocol,
+            rematch: false,
             status: PlayerStatus::Idle,
             typed_text: String::new(),
             mistakes: 0,
@@ -223,393 +854,513 @@ impl RoomsManager {
             progress: 0.0,
             connected: true,
             stats: ResultStats { keystrokes: vec![] },
-        };
-
-        let player_model = RoomUser {
-            id: room_user_id,
-            room_id,
-            user_id: live_player.id,
-            joined_at: chrono::Utc::now().naive_utc(),
-            left_at: chrono::Utc::now().naive_utc(),
-            league: Leagues::Web,
-        };
+            placement: None,
+            team: None,
+            started_at: chrono::Utc::now(),
+            finished_at: None,
+            chat_throttle: ChatThrottle::default(),
+            chat_muted_until: user.chat_muted_until,
+            clock: ClientClock::default(),
+        }
+    }
 
-        let mut conn = self.db.get().await.unwrap();
+    pub async fn leave_room(&self, room_id: Uuid, player_id: Uuid) {
+        if !self._send_command(room_id, RoomCommand::Leave { player_id }).await {
+            log::debug!("Room {} already gone when player {} left", room_id, player_id);
+        }
+    }
 
-        let _ = player_model.insert_room_user(&mut conn).await;
-        let mut room = room.write().await;
-        room.players.insert(live_player.id, live_player);
+    pub async fn start_countdown(&self, room_id: Uuid, user_id: Uuid) -> MyResult<()> {
+        let RoomRoute::Remote(node) = self._route(room_id).await else {
+            return self._start_countdown_local(room_id, user_id).await;
+        };
 
-        let room = room.downgrade();
-        let users = room
-            .players
-            .values()
-            .map(|player| PlayerStats {
-                username: player.user.username.clone(),
-                mistakes: player.mistakes,
-                progress: player.progress,
-                status: player.status.clone(),
+        let reply = self
+            .cluster
+            .request(&node, |request_id, from| ClusterMessage::StartCountdown {
+                request_id,
+                from,
+                room_id,
+                user_id,
             })
-            .collect();
-
-        room.broadcast_message(WsMessage::RoomUpdate { users }).await;
+            .await;
 
-        log::debug!("Inserted to room {}", room_id);
-
-        Ok(())
+        match reply {
+            Some(ClusterReply::Done { result }) => result.map_err(Into::into),
+            _ => Err(MyError::InternalError),
+        }
     }
 
-    pub async fn leave_room(&self, room_id: Uuid, player_id: Uuid) {
-        let mut to_delete = false;
+    pub async fn _start_countdown_local(&self, room_id: Uuid, user_id: Uuid) -> MyResult<()> {
+        let (reply, started) = oneshot::channel();
+        let command = RoomCommand::StartCountdown { requested_by: user_id, reply };
 
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Room {} to leave room that doesn't exist", room_id);
-            return;
-        };
+        if !self._send_command_local(room_id, command).await {
+            return Err(MyError::NotFound);
+        }
 
-        {
-            let mut room = room.write().await;
-            let Some(player) = room.players.get_mut(&player_id) else {
-                log::error!("Player {} not found in room {}", player_id, room_id);
-                return;
-            };
+        started.await.unwrap_or(Err(MyError::NotFound))
+    }
 
-            player.connected = false;
+    pub async fn handle_message(&self, room_id: Uuid, user_id: Uuid, msg: ClientMessage) {
+        self._send_command(room_id, RoomCommand::Message { user_id, message: msg }).await;
+    }
 
-            let room = room.downgrade();
+    /// Pushes a room list change to the lobby feeds of every node
+    pub fn announce(&self, event: LobbyEvent) {
+        let nodes = self.cluster.other_nodes();
+        if !nodes.is_empty() {
+            let cluster = self.cluster.clone();
+            let from = self.cluster.node_id.clone();
+            let message = ClusterMessage::Lobby { from, event: event.clone() };
+            tokio::spawn(async move {
+                for node in nodes {
+                    cluster.publish(&node, &message).await;
+                }
+            });
+        }
 
-            // Check if room is empty
-            if room.players.iter().all(|(_, p)| !p.connected) {
-                // Try to avoid long locks
-                to_delete = true;
-            }
+        self.directory.announce(event);
+    }
 
-            // Notify others
-            let message = WsMessage::UserLeft { user_id: player_id };
-            room.broadcast_message(message).await;
+    /// Applies a moderator mute to the user in every room they are in, on every node.
+    pub async fn set_chat_mute(&self, user_id: Uuid, until: Option<NaiveDateTime>) {
+        for node in self.cluster.other_nodes() {
+            self.cluster.publish(&node, &ClusterMessage::SetChatMute { user_id, until }).await;
         }
 
-        if to_delete {
-            self.rooms.write().await.remove(&room_id);
+        self._set_chat_mute_local(user_id, until).await;
+    }
+
+    pub async fn _set_chat_mute_local(&self, user_id: Uuid, until: Option<NaiveDateTime>) {
+        let inboxes: Vec<_> = self.rooms.read().await.values().cloned().collect();
+
+        for inbox in inboxes {
+            let _ = inbox.send(RoomCommand::SetChatMute { user_id, until }).await;
         }
     }
 
-    pub async fn wait_to_start_typing_session(&self, room_id: Uuid) {
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Room {} not found", room_id);
-            return;
-        };
+    pub async fn handle_error(&self, room_id: Uuid, user_id: Uuid, err: DecodeError) {
+        log::debug!("Error parsing message: {:?}", err);
+        let message = ServerMessage::error(ErrorCode::InvalidMessage, err.to_string());
 
-        let mut start_receiver = room.read().await.start_notifier.subscribe();
+        self._send_message_to_user_in_room(room_id, user_id, message).await;
+    }
 
-        loop {
-            if *start_receiver.borrow() {
-                break;
-            }
+    pub async fn _send_message_to_user_in_room(
+        &self,
+        room_id: Uuid,
+        user_id: Uuid,
+        message: ServerMessage,
+    ) {
+        if !self._send_command(room_id, RoomCommand::SendTo { user_id, message }).await {
+            log::error!("Try to send message to room that doesn't exist");
+        }
+    }
 
-            if start_receiver.changed().await.is_err() {
-                break;
-            }
+    /// Queues the command in the room's inbox, or passes it on to the node running the room.
+    /// `false` if the room is gone.
+    async fn _send_command(&self, room_id: Uuid, command: RoomCommand) -> bool {
+        match self._route(room_id).await {
+            RoomRoute::Local(inbox) => inbox.send(command).await.is_ok(),
+            RoomRoute::Remote(node) => {
+                let Some(message) = ClusterMessage::from_command(room_id, command) else {
+                    return false;
+                };
+
+                self.cluster.publish(&node, &message).await;
+                true
+            },
+            RoomRoute::Missing => false,
         }
     }
 
-    pub async fn start_countdown(&self, room_id: Uuid) {
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Room {} not found", room_id);
-            return;
+    /// For commands that arrived from another node, those never travel further.
+    pub async fn _send_command_local(&self, room_id: Uuid, command: RoomCommand) -> bool {
+        let Some(inbox) = self.rooms.read().await.get(&room_id).cloned() else {
+            return false;
         };
 
-        let mut room = room.write().await;
-        let start_time = Utc::now() + Duration::seconds(10);
-        let start_msg = WsMessage::Start { text: room.text.content.clone(), start_time };
-
-        room.start_time = start_time;
+        inbox.send(command).await.is_ok()
+    }
 
-        let room = room.downgrade();
-        room.broadcast_message(start_msg).await;
+    /// Queues a command from another node. It waits for room in the inbox behind the earlier
+    /// ones of the same room, without holding up the caller.
+    pub fn _relay_command_local(&self, room_id: Uuid, command: RoomCommand) {
+        let mut relays = self.relays.lock().unwrap();
 
-        drop(room);
+        let relay = relays.entry(room_id).or_insert_with(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let manager = self.clone();
 
-        // Wait until real start moment
-        let now = Utc::now();
-        let delay = (start_time - now).to_std().unwrap_or_default();
-        tokio::time::sleep(delay).await;
+            tokio::spawn(async move {
+                while let Some(command) = rx.recv().await {
+                    if !manager._send_command_local(room_id, command).await {
+                        log::debug!("Room {} is gone, remote commands dropped", room_id);
+                        break;
+                    }
+                }
+                manager.relays.lock().unwrap().remove(&room_id);
+            });
 
-        let mut conn = self.db.get().await.unwrap();
+            tx
+        });
 
-        let Ok(Some(mut room_model)) = RoomModel::get_room_by_id(&mut conn, room_id).await else {
-            log::error!("Room not found after countdown");
-            return;
-        };
+        let _ = relay.send(command);
+    }
 
-        room_model.started_at = chrono::Utc::now().naive_utc();
-        let _ = room_model.modify_room(&mut conn).await;
+    async fn _route(&self, room_id: Uuid) -> RoomRoute {
+        if let Some(inbox) = self.rooms.read().await.get(&room_id).cloned() {
+            return RoomRoute::Local(inbox);
+        }
 
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Room {} not found", room_id);
-            return;
+        let Ok(mut conn) = self.db.get().await else {
+            return RoomRoute::Missing;
         };
 
-        let mut room = room.write().await;
-        room.started = true;
-        let room = room.downgrade();
-        let _ = room.start_notifier.send(true);
+        match self.cluster.owner_of(&mut conn, room_id).await {
+            Some(node) if !self.cluster.is_local(&node) => RoomRoute::Remote(node),
+            _ => RoomRoute::Missing,
+        }
     }
+}
 
-    pub async fn handle_message(&self, room_id: Uuid, user_id: Uuid, msg: WsMessage) {
-        match msg {
-            WsMessage::Keystroke { key, timestamp } => {
-                log::debug!("Received key '{}' at {}", key, timestamp);
+impl Room {
+    pub fn stats(&self) -> RoomStats {
+        let now = Utc::now();
+        let speeds: Vec<f32> = self
+            .players
+            .values()
+            .filter(|p| matches!(p.status, PlayerStatus::Started | PlayerStatus::Finished))
+            .map(|p| p.speeds(p.finished_at.unwrap_or(now)).0)
+            .collect();
 
-                let Some(room) = self._get_room(room_id).await else {
-                    log::debug!("room not exist, how?");
-                    return;
-                };
+        let average_wpm = match self.status.started() && !speeds.is_empty() {
+            true => speeds.iter().sum::<f32>() / speeds.len() as f32,
+            false => 0.0,
+        };
 
-                let mut room = room.write().await;
-                let start_time = room.start_time;
-                let text_to_type = room.text.content.clone();
-                let Some(p) = room.players.get_mut(&user_id) else {
-                    log::debug!("player not exist, how?");
-                    return;
-                };
+        RoomStats {
+            room_id: self.id,
+            created_at: self.created_at,
+            host: self.host.and_then(|id| self.players.get(&id)).map(|p| p.user.username.clone()),
+            players: self.players.len(),
+            capacity: self.capacity(),
+            joinable: self.is_joinable(),
+            started: self.status.started(),
+            status: self.status,
+            countdown_ends_at: (self.status == RoomStatuses::Countdown).then_some(self.start_time),
+            average_wpm,
+            dictionary: self.dictionary.clone(),
+            kind: self.kind.clone(),
+            mode: self.mode,
+            league: self.league.clone(),
+            queued_messages: self.players.values().map(|p| p.sender.depth()).sum(),
+            max_queue_depth: self.players.values().map(|p| p.sender.depth()).max().unwrap_or(0),
+        }
+    }
 
-                // Compare key with expected character
-                {
-                    let expected_char = text_to_type.chars().nth(p.typed_text.chars().count());
-
-                    if let Some(expected) = expected_char {
-                        if key == expected.to_string() {
-                            p.typed_text.push_str(&key);
-                            p.last_is_mistake = false;
-                            p.stats.keystrokes.push(Keystroke {
-                                key,
-                                mistake: false,
-                                expected: None,
-                                timestamp: chrono::Utc::now().naive_utc(),
-                            })
-                        } else {
-                            log::debug!("Mistake: expected '{}' but got '{}'", &expected, &key);
-                            if !p.last_is_mistake {
-                                p.last_is_mistake = true;
-                                p.mistakes += 1;
-                                p.stats.keystrokes.push(Keystroke {
-                                    key,
-                                    mistake: true,
-                                    expected: Some(expected.to_string()),
-                                    timestamp: chrono::Utc::now().naive_utc(),
-                                });
-                            } else if let Some(keystroke) = p.stats.keystrokes.last_mut() {
-                                keystroke.key += &key
-                            };
-                        }
-                    } else {
-                        // extra key presses after end
-                        if !p.last_is_mistake {
-                            p.last_is_mistake = true;
-                            p.mistakes += 1;
-                            p.stats.keystrokes.push(Keystroke {
-                                key,
-                                mistake: true,
-                                expected: None,
-                                timestamp: chrono::Utc::now().naive_utc(),
-                            });
-                        }
-                    }
-                };
+    pub fn player_stats(&self) -> Vec<PlayerStats> {
+        self.players
+            .values()
+            .map(|player| PlayerStats {
+                username: player.user.username.clone(),
+                mistakes: player.mistakes,
+                progress: player.progress,
+                status: player.status.clone(),
+                team: player.team,
+                host: self.host == Some(player.id),
+            })
+            .collect()
+    }
 
-                let typed_count = p.typed_text.chars().count() as u32;
-                let expected_count = text_to_type.chars().count() as u32;
-                // Send Update message
-                let progress = 100.0 / (expected_count as f32 / typed_count as f32);
-                p.progress = progress;
+    /// Hands the host role to the connected player who joined first, `None` if nobody is left.
+    pub fn pass_host(&mut self) -> Option<Uuid> {
+        self.host = self
+            .players
+            .values()
+            .filter(|p| p.connected && p.status != PlayerStatus::Spectator)
+            .min_by_key(|p| p.joined_at)
+            .map(|p| p.id);
 
-                // TODO: make locks more non-blocking
-                if p.typed_text == text_to_type {
-                    p.status = PlayerStatus::Finished;
-                }
+        self.host
+    }
 
-                // We don't need any writes - we can downgrade.
+    pub fn capacity(&self) -> usize {
+        match &self.kind {
+            RoomKind::Tournament { players, .. } => players.len(),
+            _ => ROOM_CAPACITY,
+        }
+    }
 
-                let p = p.clone();
-                let room = room.downgrade().clone();
+    /// Open to anyone for the next race.
+    pub fn is_joinable(&self) -> bool {
+        self.status == RoomStatuses::Created
+            && self.players.len() < self.capacity()
+            && !matches!(self.kind, RoomKind::Tournament { .. })
+    }
 
-                let now = chrono::Utc::now();
-                let elapsed_secs = (now - start_time).as_seconds_f32();
-                let words_typed = p.typed_text.split_whitespace().count() as f32;
-                let speed_wpm =
-                    if elapsed_secs > 0.0 { (words_typed / elapsed_secs) * 60.0 } else { 0.0 };
-                let speed_cpm = if elapsed_secs > 0.0 {
-                    (typed_count as f32 / elapsed_secs) * 60.0
-                } else {
-                    0.0
-                };
+    /// Moves the room along its lifecycle, refusing anything `RoomStatuses::previous` doesn't
+    /// allow. The database follows through `RoomModel::set_status`.
+    pub fn transition(&mut self, to: RoomStatuses) -> MyResult<()> {
+        if !to.previous().contains(&self.status) {
+            log::error!("Room {} can't go from {:?} to {:?}", self.id, self.status, to);
+            return Err(MyError::Validation(format!(
+                "Room can't go from {:?} to {:?}",
+                self.status, to
+            )));
+        }
 
-                let update_msg = WsMessage::Update { progress, mistakes: p.mistakes, speed_wpm };
-                // TODO: check if success
-                room.send_message_to_player(user_id, update_msg).await;
+        self.status = to;
+        Ok(())
+    }
 
-                // Finish!
-                let is_finish = p.typed_text == text_to_type;
-                if is_finish {
-                    // TODO: make this function more... maintainable...
-                    self._user_finished_typing(p, room, speed_cpm, speed_wpm, typed_count).await;
-                }
-            },
-            other => {
-                log::debug!("Unexpected message: {:?}", other);
-                let message = WsMessage::Error { message: "Unexpected message type".to_string() };
-                self._send_message_to_user_in_room(room_id, user_id, message).await;
-            },
+    /// Puts the player in the smallest team. Returns the team and the player's relay leg.
+    pub fn assign_team(&mut self, player_id: Uuid) -> Option<(i16, usize)> {
+        if let Some(assigned) = self.relay_leg(player_id) {
+            return Some(assigned);
         }
-    }
 
-    pub async fn handle_error(&self, room_id: Uuid, user_id: Uuid, err: serde_json::Error) {
-        log::debug!("Error parsing message: {:?}", err);
-        let message = WsMessage::Error { message: "Invalid JSON format".to_string() };
+        let team = self.teams.iter_mut().min_by_key(|t| t.members.len())?;
+        team.members.push(player_id);
 
-        self._send_message_to_user_in_room(room_id, user_id, message).await;
+        Some((team.index, team.members.len() - 1))
     }
 
-    pub async fn _send_message_to_user_in_room(
-        &self,
-        room_id: Uuid,
-        user_id: Uuid,
-        message: WsMessage,
-    ) {
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Try to send message to room that doesn't exist");
-            return;
-        };
-
-        room.read().await.send_message_to_player(user_id, message).await;
+    pub fn relay_leg(&self, player_id: Uuid) -> Option<(i16, usize)> {
+        self.teams.iter().find_map(|team| {
+            team.members.iter().position(|m| *m == player_id).map(|leg| (team.index, leg))
+        })
     }
 
-    pub async fn _user_finished_typing(
-        &self,
-        p: Player,
-        room: Room,
-        speed_cpm: f32,
-        speed_wpm: f32,
-        typed_count: u32,
-    ) {
-        let now = chrono::Utc::now();
-        let total_time_ms = (now - room.start_time).num_milliseconds() as u64;
-        let multiplier = typed_count.saturating_sub(p.mistakes as u32) as f32;
+    /// Lobby is ready once the quorum of connected players is. Tournament matches wait for
+    /// every opponent instead.
+    pub fn ready_to_start(&self) -> bool {
+        if self.status != RoomStatuses::Created {
+            return false;
+        }
 
-        let accuracy = 100.0 * multiplier / (typed_count as f32);
+        let is_ready =
+            |id: &Uuid| self.players.get(id).is_some_and(|p| p.status == PlayerStatus::Ready);
 
-        let finished_msg = WsMessage::Finished {
-            total_time_ms,
-            mistakes: p.mistakes,
-            accuracy: accuracy.max(0.0),
-            speed_wpm,
-        };
-        let _ = room.send_message_to_player(p.id, finished_msg).await;
+        if let RoomKind::Tournament { players, .. } = &self.kind {
+            return players.iter().all(is_ready);
+        }
 
-        let users = room
-            .players
-            .values()
-            .map(|player| PlayerStats {
-                username: player.user.username.clone(),
-                mistakes: player.mistakes,
-                status: player.status.clone(),
-                progress: player.progress,
-            })
-            .collect();
+        let connected: Vec<_> = self.players.values().filter(|p| p.connected).collect();
+        let ready = connected.iter().filter(|p| p.status == PlayerStatus::Ready).count();
 
-        room.broadcast_message(WsMessage::RoomUpdate { users }).await;
-
-        let result = Results {
-            id: Uuid::new_v4(),
-            room_user_id: p.room_user_id,
-            start_time: room.start_time.naive_utc(),
-            end_time: now.naive_utc(),
-            mistakes: p.mistakes,
-            wpm: speed_wpm,
-            cpm: speed_cpm,
-            stats: p.stats,
-        };
+        ready > 0 && ready * 100 >= connected.len() * self.ready_quorum as usize
+    }
 
-        let mut conn = self.db.get().await.unwrap();
+    /// Ready players start racing, everyone else only watches.
+    pub fn prepare_start(&mut self) {
+        for player in self.players.values_mut() {
+            player.started_at = self.start_time;
+            player.status = match player.status {
+                PlayerStatus::Ready => PlayerStatus::Started,
+                _ => PlayerStatus::Spectator,
+            };
+            if player.status == PlayerStatus::Spectator {
+                player.team = None;
+            }
+        }
 
-        let Ok(Some(mut room_user)) =
-            RoomUser::get_room_user_by_id(&mut conn, p.room_user_id).await
-        else {
-            log::error!("Failed to get room user");
-            return;
-        };
+        for team in self.teams.iter_mut() {
+            team.members.retain(|id| self.players.get(id).is_some_and(|p| p.team.is_some()));
+        }
 
-        room_user.left_at = now.naive_utc();
+        if let RoomMode::Relay { .. } = self.mode {
+            for team in self.teams.iter_mut() {
+                team.segments = split_segments(&self.text.content, team.members.len());
+                team.active_leg = 0;
+            }
+        }
+    }
 
-        let _ = room_user.modify_room_user(&mut conn).await;
+    /// Text the player has to type: the whole text, or their segment in a relay.
+    pub fn text_for(&self, player_id: Uuid) -> &str {
+        if let RoomMode::Relay { .. } = self.mode
+            && let Some((team, leg)) = self.relay_leg(player_id)
+        {
+            return self.teams[team as usize].segments.get(leg).map_or("", String::as_str);
+        }
 
-        let _ = result.insert_result(&mut conn).await;
+        &self.text.content
+    }
 
-        let should_close = room.players.iter().all(|(_, p)| p.status == PlayerStatus::Finished);
-        let room_id = room.id;
+    /// Relay runners type only after their teammate handed the segment off.
+    /// Spectators never type.
+    pub fn is_players_turn(&self, player_id: Uuid) -> bool {
+        if self.players.get(&player_id).is_none_or(|p| p.status == PlayerStatus::Spectator) {
+            return false;
+        }
 
-        if should_close {
-            self._close_room(room_id).await;
-            log::debug!("Room finished!");
-        } else {
-            log::error!("Its not finished yet!");
+        match self.mode {
+            RoomMode::Relay { .. } => self
+                .relay_leg(player_id)
+                .is_some_and(|(team, leg)| self.teams[team as usize].active_leg == leg),
+            _ => true,
         }
-        log::debug!("User finished typing!");
     }
 
-    pub async fn _close_room(&self, room_id: Uuid) {
-        let ended_at = chrono::Utc::now().naive_utc();
+    /// Hands the segment to the next runner still connected, legs of runners who left are
+    /// skipped. The team finishes when nobody is left to run.
+    pub fn end_leg(&mut self, player_id: Uuid, now: DateTime<Utc>) -> Option<LegEnd> {
+        let (index, leg) = self.relay_leg(player_id)?;
 
-        let Some(room) = self._get_room(room_id).await else {
-            log::error!("Try to close room that doesn't exist");
-            return;
-        };
+        let team = &self.teams[index as usize];
+        let next_leg = (leg + 1..team.segments.len().min(team.members.len()))
+            .find(|&l| self.players.get(&team.members[l]).is_some_and(|p| p.connected));
+
+        if let Some(next_leg) = next_leg {
+            let next = team.members[next_leg];
 
-        let mut room = room.write().await;
+            self.teams[index as usize].active_leg = next_leg;
+            if let Some(p) = self.players.get_mut(&next) {
+                // Handed off during the countdown, the runner starts with the race
+                p.started_at = now.max(self.start_time);
+            }
 
-        for (_, player) in room.players.iter_mut() {
-            player.connected = false;
+            return Some(LegEnd::HandOff { team: index, leg: next_leg, user_id: next });
         }
-        let room = room.downgrade();
 
-        room.close_connections().await;
-        drop(room);
+        let placement = self.teams.iter().filter(|t| t.placement.is_some()).count() as i16 + 1;
+        let team = &mut self.teams[index as usize];
+        team.placement = Some(placement);
+        let members = team.members.clone();
 
-        let mut conn = self.db.get().await.unwrap();
-        let Ok(Some(mut room_model)) = RoomModel::get_room_by_id(&mut conn, room_id).await else {
-            log::error!("Room not found after closing");
-            return;
-        };
+        for member in &members {
+            if let Some(p) = self.players.get_mut(member) {
+                p.placement = Some(placement);
+                // Runners without a segment are done along with the team
+                p.status = PlayerStatus::Finished;
+            }
+        }
 
-        room_model.ended_at = ended_at;
-        let _ = room_model.modify_room(&mut conn).await;
+        Some(LegEnd::TeamFinished { members })
     }
 
-    pub async fn _get_room(&self, room_id: Uuid) -> Option<Arc<RwLock<Room>>> {
-        self.rooms.read().await.get(&room_id).cloned()
+    pub fn all_finished(&self) -> bool {
+        self.players
+            .values()
+            .filter(|p| p.status != PlayerStatus::Spectator)
+            .all(|p| p.status == PlayerStatus::Finished)
     }
-}
 
-impl Room {
-    pub async fn broadcast_message(&self, message: WsMessage) {
-        for player in self.players.values() {
-            let text = serde_json::to_string(&message).unwrap();
-            log::debug!("Socket broadcast: {}", &text);
-            let _ = player.sender.send(Message::Text(text.into()));
+    /// Field a placement is out of: the teams running a relay, the racers otherwise.
+    pub fn competitors(&self) -> usize {
+        match self.mode {
+            RoomMode::Relay { .. } => self.teams.iter().filter(|t| !t.members.is_empty()).count(),
+            _ => self.players.values().filter(|p| p.status != PlayerStatus::Spectator).count(),
+        }
+    }
+
+    /// Team score is the sum of the members' WPM and placement points.
+    /// Relay teams are ranked by finish order, team races by score.
+    pub fn team_standings(&self) -> Vec<TeamStats> {
+        let competitors = self.competitors() as f32;
+
+        let mut standings: Vec<_> = self
+            .teams
+            .iter()
+            .map(|team| {
+                let members: Vec<_> =
+                    team.members.iter().filter_map(|id| self.players.get(id)).collect();
+
+                let score = members
+                    .iter()
+                    .filter_map(|p| {
+                        let placement = p.placement? as f32;
+                        let (wpm, _) = p.speeds(p.finished_at?);
+                        Some(wpm + PLACEMENT_POINTS * (competitors - placement))
+                    })
+                    .sum();
+
+                let usernames = members.iter().map(|p| p.user.username.clone()).collect();
+
+                (team, TeamStats { team: team.index, members: usernames, score, placement: 0 })
+            })
+            .collect();
+
+        standings.sort_by(|(a, a_stats), (b, b_stats)| {
+            let by_score = b_stats.score.total_cmp(&a_stats.score);
+            match self.mode {
+                RoomMode::Relay { .. } => a
+                    .placement
+                    .unwrap_or(i16::MAX)
+                    .cmp(&b.placement.unwrap_or(i16::MAX))
+                    .then(by_score),
+                _ => by_score,
+            }
+        });
+
+        standings
+            .into_iter()
+            .enumerate()
+            .map(|(i, (_, stats))| TeamStats { placement: i as i16 + 1, ..stats })
+            .collect()
+    }
+
+    pub async fn broadcast_message(&self, message: ServerMessage) {
+        let mut frames = EncodedFrames::new(&message);
+
+        for player in self.players.values().filter(|p| p.protocol.supports(&message)) {
+            player.sender.send(frames.get(player.protocol.encoding), message.is_droppable());
         }
     }
 
-    pub async fn send_message_to_player(&self, player_id: Uuid, message: WsMessage) {
+    pub async fn send_message_to_player(&self, player_id: Uuid, message: ServerMessage) {
         if let Some(player) = self.players.get(&player_id) {
-            let text = serde_json::to_string(&message).unwrap();
-            let _ = player.sender.send(Message::Text(text.into()));
+            player.send(message);
         }
     }
 
     pub async fn close_connections(&self) {
         for player in self.players.values() {
-            let _ = player.sender.send(Message::Close(None));
+            player.sender.close();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_split_on_word_boundaries() {
+        let segments = split_segments("one two three four five six", 3);
+
+        assert_eq!(segments, vec!["one two", "three four", "five six"]);
+    }
+
+    #[test]
+    fn segments_keep_every_word_in_order() {
+        let text = "the quick brown fox jumps over the lazy dog";
+
+        for parts in 1..=9 {
+            let segments = split_segments(text, parts);
+            assert_eq!(segments.len(), parts);
+            assert_eq!(segments.join(" "), text);
+        }
+    }
+
+    #[test]
+    fn fewer_words_than_parts_gives_one_segment_per_word() {
+        assert_eq!(split_segments("one two", 4), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn single_part_or_none_is_the_whole_text() {
+        assert_eq!(split_segments("one  two\tthree", 1), vec!["one two three"]);
+        assert_eq!(split_segments("one two", 0), vec!["one two"]);
+        assert!(split_segments("", 3).is_empty());
+    }
+
+    #[test]
+    fn long_first_word_still_leaves_words_for_the_others() {
+        let segments = split_segments("antidisestablishmentarianism a b", 3);
+
+        assert_eq!(segments, vec!["antidisestablishmentarianism", "a", "b"]);
+    }
+}
@@ -0,0 +1,1176 @@
+use std::ops::ControlFlow;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::db::{
+    custom_types::{LeaveReasons, RoomStatuses},
+    models::{
+        daily_challenge::DailyChallengeAttempt,
+        dictionary::Dictionary,
+        result::{ResultStats, Results},
+        room::Room as RoomModel,
+        room_snapshot::RoomSnapshot,
+        room_user::RoomUser,
+        user::User,
+    },
+};
+
+use super::{
+    achievements::{AchievementContext, evaluate_achievements},
+    chat::CHAT_MAX_LENGTH,
+    checkpoint::{CHECKPOINT_INTERVAL, RoomState},
+    client_clock::{ClockError, MAX_BATCH_KEYS},
+    error::MyError,
+    progression::{RaceOutcome, apply_race_progression},
+    protocol::{ClientMessage, ErrorCode, ServerMessage},
+    room::{
+        LegEnd, Player, PlayerStatus, REMATCH_WINDOW, Room, RoomKind, RoomMode, RoomOptions,
+        RoomsManager,
+    },
+    room_directory::LobbyEvent,
+    tournament,
+    types::{DbConn, MyResult},
+};
+
+/// Commands a room holds before the sockets feeding it have to wait.
+const ROOM_INBOX_CAPACITY: usize = 256;
+const STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
+
+pub enum RoomCommand {
+    Join {
+        player: Box<Player>,
+        reply: oneshot::Sender<MyResult<()>>,
+    },
+    Leave {
+        player_id: Uuid,
+    },
+    Message {
+        user_id: Uuid,
+        message: ClientMessage,
+    },
+    SendTo {
+        user_id: Uuid,
+        message: ServerMessage,
+    },
+    /// Explicit start by the host, replies `NotFound` once the race is running
+    StartCountdown {
+        requested_by: Uuid,
+        reply: oneshot::Sender<MyResult<()>>,
+    },
+    CountdownElapsed,
+    SetChatMute {
+        user_id: Uuid,
+        until: Option<NaiveDateTime>,
+    },
+    ResolveRematch,
+    /// Restored room stops unless someone came back
+    ReconnectDeadline,
+}
+
+/// Owns a live room and applies its commands one at a time, so the room state needs no locks.
+/// Database writes are handed to the manager's writer instead of being awaited here.
+pub struct RoomActor {
+    room: Room,
+    inbox: mpsc::Receiver<RoomCommand>,
+    /// Own inbox, for timers that report back
+    handle: mpsc::Sender<RoomCommand>,
+    manager: RoomsManager,
+    /// Changed since the last checkpoint
+    dirty: bool,
+    /// Players, status and host as last published to the room directory
+    listed: (usize, RoomStatuses, Option<Uuid>),
+}
+
+impl RoomActor {
+    pub fn spawn(room: Room, manager: RoomsManager) -> mpsc::Sender<RoomCommand> {
+        let (handle, inbox) = mpsc::channel(ROOM_INBOX_CAPACITY);
+
+        let listed = (room.players.len(), room.status, room.host);
+        let actor = Self { room, inbox, handle: handle.clone(), manager, dirty: true, listed };
+        tokio::spawn(actor.run());
+
+        handle
+    }
+
+    async fn run(mut self) {
+        let mut stats = tokio::time::interval(STATS_INTERVAL);
+        let mut checkpoint = tokio::time::interval(CHECKPOINT_INTERVAL);
+
+        // Restored in the middle of a countdown
+        if self.room.status == RoomStatuses::Countdown {
+            self.schedule_start();
+        }
+
+        let room = self.room.stats();
+        self.manager.directory.publish(room.clone());
+        self.manager.announce(LobbyEvent::Created { room });
+
+        loop {
+            tokio::select! {
+                command = self.inbox.recv() => {
+                    let Some(command) = command else {
+                        break;
+                    };
+
+                    self.dirty = true;
+                    if self.handle(command).await.is_break() {
+                        break;
+                    }
+
+                    self.list();
+                },
+                _ = checkpoint.tick() => self.checkpoint().await,
+                // Stats autoupdate
+                _ = stats.tick() => {
+                    self.manager.directory.publish(self.room.stats());
+
+                    if self.room.status.started() {
+                        let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+                        self.room.broadcast_message(message).await;
+                    }
+                },
+            }
+        }
+
+        self.manager.rooms.write().await.remove(&self.room.id);
+        self.manager.relays.lock().unwrap().remove(&self.room.id);
+        self.manager.directory.remove(self.room.id);
+        self.manager.announce(LobbyEvent::Closed { room_id: self.room.id });
+
+        // Everyone left before the race was over
+        if self.room.status != RoomStatuses::Finished {
+            self.manager._abort_room(self.room.id).await;
+        }
+
+        log::debug!("Room {} destroyed, actor also", self.room.id);
+    }
+
+    /// Republishes the room and tells the lobby feeds when something the room list shows
+    /// changed, typing progress waits for the next stats tick
+    fn list(&mut self) {
+        let listed = (self.room.players.len(), self.room.status, self.room.host);
+        if listed == self.listed {
+            return;
+        }
+
+        let started = listed.1 == RoomStatuses::Running && self.listed.1 != RoomStatuses::Running;
+        self.listed = listed;
+
+        let room = self.room.stats();
+        self.manager.directory.publish(room.clone());
+        self.manager.announce(if started {
+            LobbyEvent::Started { room }
+        } else {
+            LobbyEvent::Updated { room }
+        });
+    }
+
+    async fn handle(&mut self, command: RoomCommand) -> ControlFlow<()> {
+        match command {
+            RoomCommand::Join { player, reply } => {
+                let _ = reply.send(self.join(*player).await);
+            },
+            RoomCommand::Leave { player_id } => return self.leave(player_id).await,
+            RoomCommand::Message { user_id, message } => {
+                return self.handle_message(user_id, message).await;
+            },
+            RoomCommand::SendTo { user_id, message } => {
+                self.room.send_message_to_player(user_id, message).await;
+            },
+            RoomCommand::StartCountdown { requested_by, reply } => {
+                let _ = reply.send(self.host_start(requested_by).await);
+            },
+            RoomCommand::CountdownElapsed => self.countdown_elapsed().await,
+            RoomCommand::SetChatMute { user_id, until } => {
+                if let Some(p) = self.room.players.get_mut(&user_id) {
+                    p.chat_muted_until = until;
+                }
+            },
+            RoomCommand::ResolveRematch => return self.resolve_rematch(),
+            RoomCommand::ReconnectDeadline => {
+                if self.room.players.values().all(|p| !p.connected) {
+                    return ControlFlow::Break(());
+                }
+            },
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    async fn checkpoint(&mut self) {
+        // Closed rooms have nothing left to restore
+        if !self.dirty || self.room.status == RoomStatuses::Finished {
+            return;
+        }
+        self.dirty = false;
+
+        let snapshot = RoomSnapshot {
+            room_id: self.room.id,
+            state: serde_json::to_value(RoomState::capture(&self.room)).unwrap(),
+            saved_at: chrono::Utc::now().naive_utc(),
+        };
+
+        self.manager
+            .writer
+            .submit(self.room.id, move |conn| {
+                async move {
+                    if let Err(e) = snapshot.save_snapshot(conn).await {
+                        log::error!("Failed to checkpoint room: {:?}", e);
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+    }
+
+    /// Moves the room along its lifecycle and queues the same move for the database.
+    async fn set_status(&mut self, status: RoomStatuses) -> MyResult<()> {
+        self.room.transition(status)?;
+
+        let room_id = self.room.id;
+        let at = chrono::Utc::now().naive_utc();
+
+        self.manager
+            .writer
+            .submit(room_id, move |conn| {
+                async move {
+                    match RoomModel::set_status(conn, room_id, status, at).await {
+                        Ok(true) => {},
+                        Ok(false) => log::error!("Room {} is not ready for {:?}", room_id, status),
+                        Err(e) => {
+                            log::error!("Failed to move room {} to {:?}: {:?}", room_id, status, e)
+                        },
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        Ok(())
+    }
+
+    /// Writes why the player is out, or that they are back when `reason` is `None`.
+    async fn record_leave(&self, room_user_id: Uuid, reason: Option<LeaveReasons>) {
+        let at = reason.as_ref().map(|_| chrono::Utc::now().naive_utc());
+
+        self.manager
+            .writer
+            .submit(self.room.id, move |conn| {
+                async move {
+                    if let Err(e) = RoomUser::set_left(conn, room_user_id, at, reason).await {
+                        log::error!("Failed to record leave of {}: {:?}", room_user_id, e);
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+    }
+
+    /// Reports back once the countdown is over.
+    fn schedule_start(&self) {
+        let handle = self.handle.clone();
+        let delay = (self.room.start_time - Utc::now()).to_std().unwrap_or_default();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = handle.send(RoomCommand::CountdownElapsed).await;
+        });
+    }
+
+    async fn join(&mut self, mut player: Player) -> MyResult<()> {
+        let room_id = self.room.id;
+
+        if !self.room.kind.allows_player(player.id) {
+            log::debug!("User {} is not allowed in room {}", player.id, room_id);
+            return Err(MyError::Unauthorized);
+        }
+
+        // Coming back keeps the progress, only the connection is new
+        if let Some(existing) = self.room.players.get_mut(&player.id) {
+            let returning =
+                !existing.connected && existing.leave_reason(self.room.status).is_some();
+            let room_user_id = existing.room_user_id;
+
+            existing.sender = player.sender;
+            existing.room_binding = player.room_binding;
+            existing.protocol = player.protocol;
+            existing.connected = true;
+
+            let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+            self.room.broadcast_message(message).await;
+
+            if returning {
+                self.record_leave(room_user_id, None).await;
+            }
+
+            if self.room.status.countdown_started() {
+                let text = self.room.text_for(player.id).to_string();
+                let message = ServerMessage::Start { text, start_time: self.room.start_time };
+                self.room.send_message_to_player(player.id, message).await;
+            }
+
+            return Ok(());
+        }
+
+        if self.room.players.len() >= self.room.capacity() {
+            log::debug!("Room {} is full", room_id);
+            return Err(MyError::Validation("Room is full".to_string()));
+        }
+
+        if self.room.host.is_none() {
+            self.room.host = Some(player.id);
+        }
+
+        // Teams are fixed once the race started, late comers only watch
+        let assignment = if self.room.status.started() && self.room.mode != RoomMode::Solo {
+            player.status = PlayerStatus::Spectator;
+            None
+        } else {
+            self.room.assign_team(player.id)
+        };
+        player.team = assignment.map(|(team, _)| team);
+
+        let player_model = RoomUser {
+            id: player.room_user_id,
+            room_id,
+            user_id: player.id,
+            joined_at: chrono::Utc::now().naive_utc(),
+            left_at: None,
+            league: self.room.league.clone(),
+            team: player.team,
+            // Known once the race starts and idle players are out
+            leg: None,
+            leave_reason: None,
+        };
+
+        self.room.players.insert(player.id, player);
+
+        let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+        self.room.broadcast_message(message).await;
+
+        self.manager
+            .writer
+            .submit(room_id, move |conn| {
+                async move {
+                    let _ = player_model.insert_room_user(conn).await;
+                    log::debug!("Inserted to room {}", room_id);
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn leave(&mut self, player_id: Uuid) -> ControlFlow<()> {
+        let Some(player) = self.room.players.get_mut(&player_id) else {
+            log::error!("Player {} not found in room {}", player_id, self.room.id);
+            return ControlFlow::Continue(());
+        };
+
+        player.connected = false;
+
+        let room_user_id = player.room_user_id;
+        if let Some(reason) = player.leave_reason(self.room.status) {
+            self.record_leave(room_user_id, Some(reason)).await;
+        }
+
+        // Check if room is empty
+        if self.room.players.values().all(|p| !p.connected) {
+            return ControlFlow::Break(());
+        }
+
+        // Notify others
+        let message = ServerMessage::UserLeft { user_id: player_id };
+        self.room.broadcast_message(message).await;
+
+        // Someone has to be able to start the room
+        if self.room.host == Some(player_id) && self.room.pass_host().is_some() {
+            let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+            self.room.broadcast_message(message).await;
+        }
+
+        // A runner leaving on their leg passes the baton on, or the team is done
+        if let RoomMode::Relay { .. } = self.room.mode
+            && matches!(self.room.status, RoomStatuses::Countdown | RoomStatuses::Running)
+            && self.room.is_players_turn(player_id)
+            && self.room.players.get(&player_id).is_some_and(|p| p.status != PlayerStatus::Finished)
+        {
+            let leg_end = self.room.end_leg(player_id, chrono::Utc::now());
+            self.finish_leg(leg_end).await;
+
+            // During the countdown the room closes as soon as the race would start
+            if self.room.status == RoomStatuses::Running && self.room.all_finished() {
+                self.close_room().await;
+            }
+        }
+
+        // The one who held the lobby back may be gone
+        if self.room.ready_to_start() {
+            let _ = self.start_countdown().await;
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// The host starting without waiting for the quorum races everyone connected
+    async fn host_start(&mut self, requested_by: Uuid) -> MyResult<()> {
+        if self.room.host != Some(requested_by) {
+            return Err(MyError::Unauthorized);
+        }
+
+        if self.room.status == RoomStatuses::Created {
+            for player in self.room.players.values_mut() {
+                if player.connected && player.status == PlayerStatus::Idle {
+                    player.status = PlayerStatus::Ready;
+                }
+            }
+        }
+
+        self.start_countdown().await
+    }
+
+    async fn start_countdown(&mut self) -> MyResult<()> {
+        let room_id = self.room.id;
+
+        if self.room.status.started() {
+            return Err(MyError::NotFound);
+        }
+
+        if self.room.status == RoomStatuses::Countdown {
+            log::debug!("Room {} countdown already started", room_id);
+            return Ok(());
+        }
+
+        // Nobody would race, the room would sit in Running until everyone left
+        if self.room.players.values().all(|p| p.status != PlayerStatus::Ready) {
+            return Err(MyError::Validation("Nobody is ready".to_string()));
+        }
+
+        let start_time = Utc::now() + Duration::seconds(10);
+
+        self.set_status(RoomStatuses::Countdown).await?;
+        self.room.start_time = start_time;
+        self.room.prepare_start();
+
+        let room = &self.room;
+        room.broadcast_message(ServerMessage::RoomUpdate { users: room.player_stats() }).await;
+
+        let assignments: Vec<_> = room
+            .players
+            .values()
+            .filter(|_| room.mode != RoomMode::Solo)
+            .map(|p| {
+                let leg = match room.mode {
+                    RoomMode::Relay { .. } => room.relay_leg(p.id).map(|(_, leg)| leg as i16),
+                    _ => None,
+                };
+                (p.room_user_id, p.team, leg)
+            })
+            .collect();
+
+        // Relay runners get their own segment
+        for player_id in room.players.keys() {
+            let start_msg =
+                ServerMessage::Start { text: room.text_for(*player_id).to_string(), start_time };
+            room.send_message_to_player(*player_id, start_msg).await;
+        }
+
+        self.manager
+            .writer
+            .submit(room_id, move |conn| {
+                async move {
+                    for (room_user_id, team, leg) in assignments {
+                        let _ = RoomUser::set_team_and_leg(conn, room_user_id, team, leg).await;
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        // Wait until real start moment
+        self.schedule_start();
+
+        Ok(())
+    }
+
+    async fn countdown_elapsed(&mut self) {
+        if self.set_status(RoomStatuses::Running).await.is_err() {
+            return;
+        }
+
+        // Relay teams may have run out of runners during the countdown
+        if self.room.all_finished() {
+            self.close_room().await;
+        }
+    }
+
+    async fn handle_message(&mut self, user_id: Uuid, msg: ClientMessage) -> ControlFlow<()> {
+        match msg {
+            ClientMessage::Keystroke { key, timestamp } => {
+                log::debug!("Received key '{}' at {}", key, timestamp);
+                self.handle_keystrokes(user_id, vec![key], None).await;
+            },
+            ClientMessage::KeystrokeBatch { timestamp, keys } => {
+                log::debug!("Received {} keys at {}", keys.len(), timestamp);
+
+                if keys.is_empty() || keys.len() > MAX_BATCH_KEYS {
+                    let message = ServerMessage::error(
+                        ErrorCode::InvalidMessage,
+                        format!("A batch carries 1 to {} keys", MAX_BATCH_KEYS),
+                    );
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                }
+
+                // Both come from the client, an absurd timestamp must not overflow
+                let times: Option<Vec<i64>> = keys
+                    .iter()
+                    .map(|k| i64::try_from(timestamp).ok()?.checked_add(k.offset_ms as i64))
+                    .collect();
+                let Some(times) = times else {
+                    let message = ServerMessage::error(
+                        ErrorCode::InvalidMessage,
+                        ClockError::Invalid.to_string(),
+                    );
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                };
+
+                let keys = keys.into_iter().map(|k| k.key).collect();
+                self.handle_keystrokes(user_id, keys, Some(times)).await;
+            },
+            ClientMessage::Ready { ready } => {
+                let in_lobby = self.room.status == RoomStatuses::Created;
+
+                let Some(p) = self.room.players.get_mut(&user_id) else {
+                    log::debug!("player not exist, how?");
+                    return ControlFlow::Continue(());
+                };
+
+                if !in_lobby || p.status == PlayerStatus::Spectator {
+                    let message =
+                        ServerMessage::error(ErrorCode::NotAllowed, "Ready check is already over");
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                }
+
+                p.status = if ready { PlayerStatus::Ready } else { PlayerStatus::Idle };
+
+                let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+                self.room.broadcast_message(message).await;
+
+                if self.room.ready_to_start() {
+                    let _ = self.start_countdown().await;
+                }
+            },
+            ClientMessage::RematchVote { rematch } => {
+                let ended = self.room.status == RoomStatuses::Finished;
+
+                let Some(p) = self.room.players.get_mut(&user_id) else {
+                    log::debug!("player not exist, how?");
+                    return ControlFlow::Continue(());
+                };
+
+                if !ended {
+                    let message =
+                        ServerMessage::error(ErrorCode::NotAllowed, "Race is not over yet");
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                }
+
+                p.rematch = rematch;
+
+                let voters: Vec<_> = self.room.players.values().filter(|p| p.connected).collect();
+                let usernames =
+                    voters.iter().filter(|p| p.rematch).map(|p| p.user.username.clone()).collect();
+                let everyone_in = voters.iter().all(|p| p.rematch);
+
+                self.room.broadcast_message(ServerMessage::RematchVotes { usernames }).await;
+
+                if everyone_in {
+                    return self.resolve_rematch();
+                }
+            },
+            ClientMessage::Chat { text } => {
+                if text.chars().count() > CHAT_MAX_LENGTH {
+                    let message = ServerMessage::error(
+                        ErrorCode::InvalidMessage,
+                        format!("Message is longer than {} characters", CHAT_MAX_LENGTH),
+                    );
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                }
+
+                let text = text.trim();
+                if text.is_empty() {
+                    return ControlFlow::Continue(());
+                }
+
+                let Some(text) = self.manager.chat_filter.filter(text) else {
+                    let message = ServerMessage::error(
+                        ErrorCode::NotAllowed,
+                        "Message was blocked by filter",
+                    );
+                    self.room.send_message_to_player(user_id, message).await;
+                    return ControlFlow::Continue(());
+                };
+
+                self.send_chat(user_id, |user_id, username, sent_at| ServerMessage::ChatMessage {
+                    user_id,
+                    username,
+                    text,
+                    sent_at,
+                })
+                .await;
+            },
+            ClientMessage::React { reaction } => {
+                self.send_chat(user_id, |user_id, username, _| ServerMessage::Reaction {
+                    user_id,
+                    username,
+                    reaction,
+                })
+                .await;
+            },
+            other => {
+                log::debug!("Unexpected message: {:?}", other);
+                let message =
+                    ServerMessage::error(ErrorCode::UnexpectedMessage, "Unexpected message type");
+                self.room.send_message_to_player(user_id, message).await;
+            },
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Applies the keys in one go. `client_times` are the client's epoch milliseconds
+    /// for each key, without them the keys are stamped with the server clock.
+    async fn handle_keystrokes(
+        &mut self,
+        user_id: Uuid,
+        keys: Vec<String>,
+        client_times: Option<Vec<i64>>,
+    ) {
+        if !self.room.status.started() {
+            let message = ServerMessage::error(ErrorCode::NotAllowed, "Race has not started yet");
+            self.room.send_message_to_player(user_id, message).await;
+            return;
+        }
+
+        if !self.room.is_players_turn(user_id) {
+            let message = ServerMessage::error(ErrorCode::NotAllowed, "Not your turn to type");
+            self.room.send_message_to_player(user_id, message).await;
+            return;
+        }
+
+        let text_to_type = self.room.text_for(user_id).to_string();
+        let Some(p) = self.room.players.get_mut(&user_id) else {
+            log::debug!("player not exist, how?");
+            return;
+        };
+
+        let now = chrono::Utc::now();
+        let times = match client_times {
+            Some(times) => match p.clock.map(&times, now) {
+                // Nothing counts from before the player's start
+                Ok(times) => times.into_iter().map(|t| t.max(p.started_at)).collect(),
+                Err(e) => {
+                    log::debug!("Rejected keystrokes of {}: {}", user_id, e);
+                    let message = ServerMessage::error(ErrorCode::InvalidMessage, e.to_string());
+                    self.room.send_message_to_player(user_id, message).await;
+                    return;
+                },
+            },
+            None => vec![now; keys.len()],
+        };
+
+        // Extra keys after the end must not finish the race twice
+        let was_finished = p.status == PlayerStatus::Finished;
+        let mut finished_at = now;
+
+        for (key, at) in keys.into_iter().zip(times) {
+            p.type_key(key, &text_to_type, at);
+
+            // Rest of the batch is typed past the end, ignore it
+            if !was_finished && p.typed_text == text_to_type {
+                finished_at = at;
+                break;
+            }
+        }
+
+        let typed_count = p.typed_text.chars().count() as u32;
+        let expected_count = text_to_type.chars().count() as u32;
+        // Send Update message
+        let progress = 100.0 / (expected_count as f32 / typed_count as f32);
+        p.progress = progress;
+
+        let just_finished = p.typed_text == text_to_type && !was_finished;
+
+        let mut leg_end = None;
+
+        if just_finished {
+            p.status = PlayerStatus::Finished;
+            p.finished_at = Some(finished_at);
+
+            if let RoomMode::Relay { .. } = self.room.mode {
+                leg_end = self.room.end_leg(user_id, finished_at);
+            } else {
+                // Players finish one at a time in the actor, so the count is the place
+                let placement = self
+                    .room
+                    .players
+                    .values()
+                    .filter(|p| p.status == PlayerStatus::Finished)
+                    .count() as i16;
+
+                if let Some(p) = self.room.players.get_mut(&user_id) {
+                    p.placement = Some(placement);
+                }
+            }
+        }
+
+        let Some(p) = self.room.players.get(&user_id) else {
+            return;
+        };
+
+        let (speed_wpm, _) = p.speeds(p.finished_at.unwrap_or(now));
+
+        let update_msg = ServerMessage::Update { progress, mistakes: p.mistakes, speed_wpm };
+        self.room.send_message_to_player(user_id, update_msg).await;
+
+        // Finish!
+        match leg_end {
+            Some(leg_end) => self.finish_leg(Some(leg_end)).await,
+            None if just_finished => {
+                // TODO: make this function more... maintainable...
+                self.user_finished_typing(user_id).await;
+            },
+            None => {},
+        }
+
+        if just_finished && self.room.all_finished() {
+            self.close_room().await;
+            log::debug!("Room finished!");
+        }
+    }
+
+    async fn finish_leg(&self, leg_end: Option<LegEnd>) {
+        match leg_end {
+            Some(LegEnd::HandOff { team, leg, user_id }) => {
+                let text = self.room.text_for(user_id).to_string();
+                let message = ServerMessage::SegmentHandOff { team, leg, user_id, text };
+                self.room.broadcast_message(message).await;
+            },
+            // The whole team is ranked at once when its last runner finishes
+            Some(LegEnd::TeamFinished { members }) => {
+                for member in members {
+                    self.user_finished_typing(member).await;
+                }
+            },
+            None => {},
+        }
+    }
+
+    /// Broadcasts a chat message or a reaction if the sender is allowed to talk right now.
+    async fn send_chat(
+        &mut self,
+        user_id: Uuid,
+        build: impl FnOnce(Uuid, String, DateTime<Utc>) -> ServerMessage,
+    ) {
+        let now = chrono::Utc::now();
+        let racing = self.room.status.started();
+
+        let Some(p) = self.room.players.get_mut(&user_id) else {
+            log::debug!("player not exist, how?");
+            return;
+        };
+
+        let refusal = if p.chat_muted_until.is_some_and(|until| until > now.naive_utc()) {
+            Some((ErrorCode::Muted, "You are muted"))
+        } else if racing
+            && p.status != PlayerStatus::Finished
+            && p.status != PlayerStatus::Spectator
+        {
+            Some((ErrorCode::NotAllowed, "Chat is disabled while racing"))
+        } else if !p.chat_throttle.try_send(now) {
+            Some((ErrorCode::RateLimited, "Too many messages, slow down"))
+        } else {
+            None
+        };
+
+        let username = p.user.username.clone();
+
+        match refusal {
+            Some((code, reason)) => {
+                let message = ServerMessage::error(code, reason);
+                self.room.send_message_to_player(user_id, message).await;
+            },
+            None => self.room.broadcast_message(build(user_id, username, now)).await,
+        }
+    }
+
+    async fn user_finished_typing(&self, player_id: Uuid) {
+        let Some(p) = self.room.players.get(&player_id) else {
+            return;
+        };
+
+        let now = p.finished_at.unwrap_or_else(chrono::Utc::now);
+        let (speed_wpm, speed_cpm) = p.speeds(now);
+        let typed_count = p.typed_text.chars().count() as u32;
+        let total_time_ms = (now - p.started_at).num_milliseconds() as u64;
+        let multiplier = typed_count.saturating_sub(p.mistakes as u32) as f32;
+
+        let accuracy = match typed_count {
+            0 => 0.0,
+            _ => 100.0 * multiplier / (typed_count as f32),
+        };
+
+        let finished_msg =
+            ServerMessage::Finished { total_time_ms, mistakes: p.mistakes, accuracy, speed_wpm };
+        self.room.send_message_to_player(p.id, finished_msg).await;
+
+        let message = ServerMessage::RoomUpdate { users: self.room.player_stats() };
+        self.room.broadcast_message(message).await;
+
+        let placement = p.placement.unwrap_or(1);
+        let players = self.room.competitors();
+        let kind = self.room.kind.clone();
+
+        // Achievements and progression arrive after the writer is done
+        let mut p = p.clone();
+
+        let result = Results {
+            id: Uuid::new_v4(),
+            room_user_id: p.room_user_id,
+            start_time: p.started_at.naive_utc(),
+            end_time: now.naive_utc(),
+            mistakes: p.mistakes,
+            wpm: speed_wpm,
+            cpm: speed_cpm,
+            stats: ResultStats { keystrokes: std::mem::take(&mut p.stats.keystrokes) },
+            placement: Some(placement),
+        };
+
+        self.manager
+            .writer
+            .submit(self.room.id, move |conn| {
+                async move {
+                    let Ok(Some(mut room_user)) =
+                        RoomUser::get_room_user_by_id(conn, p.room_user_id).await
+                    else {
+                        log::error!("Failed to get room user");
+                        return;
+                    };
+
+                    room_user.left_at = Some(now.naive_utc());
+                    room_user.leave_reason = Some(LeaveReasons::Finished);
+
+                    let _ = room_user.modify_room_user(conn).await;
+
+                    // Runners whose team finished before their leg have no race to record
+                    if typed_count == 0 {
+                        return;
+                    }
+
+                    match result.insert_result(conn).await {
+                        Ok(result) => {
+                            let ctx = AchievementContext {
+                                user_id: p.id,
+                                result_id: result.id,
+                                wpm: result.wpm,
+                                mistakes: result.mistakes,
+                                text_length: typed_count as usize,
+                            };
+
+                            match evaluate_achievements(conn, &ctx).await {
+                                Ok(unlocked) => {
+                                    for achievement in unlocked {
+                                        p.send(ServerMessage::AchievementUnlocked {
+                                            code: achievement.code,
+                                            name: achievement.name,
+                                            description: achievement.description,
+                                        });
+                                    }
+                                },
+                                Err(e) => log::error!("Failed to evaluate achievements: {:?}", e),
+                            }
+
+                            let outcome = RaceOutcome {
+                                user_id: p.id,
+                                result_id: result.id,
+                                text_length: ctx.text_length,
+                                accuracy,
+                                placement,
+                                players,
+                            };
+
+                            match apply_race_progression(conn, &outcome).await {
+                                Ok((entry, progression)) => {
+                                    p.send(ServerMessage::ProgressionUpdate {
+                                        xp_gained: entry.amount,
+                                        xp: progression.xp,
+                                        level: progression.level,
+                                        streak_days: progression.streak_days,
+                                    });
+                                },
+                                Err(e) => log::error!("Failed to apply progression: {:?}", e),
+                            }
+
+                            if let RoomKind::Daily { challenge_id, ranked_attempt, .. } = kind {
+                                record_daily_attempt(
+                                    conn,
+                                    challenge_id,
+                                    p.id,
+                                    result.id,
+                                    ranked_attempt,
+                                )
+                                .await;
+                            }
+                        },
+                        Err(e) => log::error!("Failed to insert result: {:?}", e),
+                    }
+
+                    log::debug!("User finished typing!");
+                }
+                .scope_boxed()
+            })
+            .await;
+    }
+
+    async fn close_room(&mut self) {
+        let room_id = self.room.id;
+
+        // Racers still typing are out of time, watchers simply leave
+        let remaining: Vec<_> = self
+            .room
+            .players
+            .values()
+            .filter(|p| p.connected)
+            .filter_map(|p| p.leave_reason(self.room.status).map(|r| (p.room_user_id, r)))
+            .collect();
+
+        if self.set_status(RoomStatuses::Finished).await.is_err() {
+            return;
+        }
+
+        for (room_user_id, reason) in remaining {
+            self.record_leave(room_user_id, Some(reason)).await;
+        }
+
+        // Special rooms are one-offs, regular ones stay open for a rematch vote
+        let rematch_allowed = self.room.kind == RoomKind::Regular;
+        let rematch_until = Utc::now() + REMATCH_WINDOW;
+
+        if !rematch_allowed {
+            for (_, player) in self.room.players.iter_mut() {
+                player.connected = false;
+            }
+        }
+
+        let room = &self.room;
+
+        if room.mode != RoomMode::Solo {
+            room.broadcast_message(ServerMessage::TeamStandings { teams: room.team_standings() })
+                .await;
+        }
+
+        if rematch_allowed {
+            room.broadcast_message(ServerMessage::RaceOver { rematch_until }).await;
+        } else {
+            room.close_connections().await;
+        }
+
+        let kind = room.kind.clone();
+        let winner = room
+            .players
+            .values()
+            .filter(|p| p.placement.is_some())
+            .min_by_key(|p| p.placement)
+            .map(|p| p.id);
+
+        let manager = self.manager.clone();
+        self.manager
+            .writer
+            .submit(room_id, move |conn| {
+                async move {
+                    let _ = RoomSnapshot::delete_snapshot(conn, room_id).await;
+
+                    // Reported once the room is written, but off the writer: pairing the next
+                    // round creates rooms, possibly on other nodes
+                    if let RoomKind::Tournament { match_id, .. } = kind {
+                        tokio::spawn(async move {
+                            let report = match manager.db.get().await {
+                                Ok(mut conn) => {
+                                    tournament::report_match_result(
+                                        &manager, &mut conn, match_id, winner,
+                                    )
+                                    .await
+                                },
+                                Err(e) => Err(e.into()),
+                            };
+
+                            if let Err(e) = report {
+                                log::error!(
+                                    "Failed to report tournament match {}: {:?}",
+                                    match_id,
+                                    e
+                                );
+                            }
+                        });
+                    }
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        if rematch_allowed {
+            let handle = self.handle.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(REMATCH_WINDOW.to_std().unwrap_or_default()).await;
+                let _ = handle.send(RoomCommand::ResolveRematch).await;
+            });
+        }
+    }
+
+    /// Closes everyone who didn't vote for a rematch and hands the voters over to a new room.
+    /// The actor stops afterwards.
+    fn resolve_rematch(&mut self) -> ControlFlow<()> {
+        let (voters, others): (Vec<_>, Vec<_>) =
+            self.room.players.drain().map(|(_, p)| p).partition(|p| p.connected && p.rematch);
+
+        for player in others {
+            player.sender.close();
+        }
+
+        if !voters.is_empty() {
+            let manager = self.manager.clone();
+            let room = &self.room;
+            let (room_id, dictionary) = (room.id, room.dictionary.clone());
+            let options = RoomOptions {
+                kind: RoomKind::Regular,
+                mode: room.mode,
+                ready_quorum: room.ready_quorum,
+                league: room.league.clone(),
+                series_id: None,
+                previous_room_id: Some(room_id),
+            };
+
+            tokio::spawn(async move {
+                start_rematch(manager, room_id, dictionary, options, voters).await;
+            });
+        }
+
+        ControlFlow::Break(())
+    }
+}
+
+/// Moves the voters into a new room with a fresh text from the same dictionary.
+async fn start_rematch(
+    manager: RoomsManager,
+    room_id: Uuid,
+    dictionary: Dictionary,
+    options: RoomOptions,
+    voters: Vec<Player>,
+) {
+    let mut conn = match manager.db.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            log::error!("Rematch of room {} dropped, no connection: {:?}", room_id, e);
+            for player in voters {
+                player.sender.close();
+            }
+            return;
+        },
+    };
+
+    let text = match dictionary.get_random_text_in_dictionary(&mut conn).await {
+        Ok(Some(text)) => text,
+        other => {
+            log::error!("Failed to pick a rematch text: {:?}", other.err());
+            for player in voters {
+                player.sender.close();
+            }
+            return;
+        },
+    };
+
+    // The rematch continues the series of the room it follows
+    let series_id = match RoomModel::get_room_by_id(&mut conn, room_id).await {
+        Ok(Some(previous)) => previous.series_id,
+        _ => room_id,
+    };
+    let options = RoomOptions { series_id: Some(series_id), ..options };
+
+    let new_room_id = match manager.create_room(text, dictionary, options).await {
+        Ok(id) => id,
+        Err(e) => {
+            log::error!("Failed to create the rematch of room {}: {:?}", room_id, e);
+            for player in voters {
+                player.sender.close();
+            }
+            return;
+        },
+    };
+
+    for player in voters {
+        let Ok(Some(user)) = User::get_user(&mut conn, player.id).await else {
+            player.sender.close();
+            continue;
+        };
+
+        // The socket keeps running, its messages go to the new room from now on
+        player.room_binding.send_replace(new_room_id);
+
+        let joined = manager
+            .join_room(
+                new_room_id,
+                user,
+                player.sender.clone(),
+                player.room_binding.clone(),
+                player.protocol.clone(),
+            )
+            .await;
+
+        match joined {
+            Ok(()) => {
+                let message = ServerMessage::Rematch { room_id: new_room_id };
+                manager._send_message_to_user_in_room(new_room_id, player.id, message).await;
+            },
+            Err(_) => {
+                player.sender.close();
+            },
+        }
+    }
+}
+
+async fn record_daily_attempt(
+    conn: &mut DbConn,
+    challenge_id: Uuid,
+    user_id: Uuid,
+    result_id: Uuid,
+    ranked_attempt: Option<Uuid>,
+) {
+    // The ranked slot was taken when the room was made, retries are stored unranked
+    let recorded = match ranked_attempt {
+        Some(attempt_id) => DailyChallengeAttempt::set_result(conn, attempt_id, result_id).await,
+        None => {
+            let attempt = DailyChallengeAttempt {
+                id: Uuid::new_v4(),
+                challenge_id,
+                user_id,
+                result_id: Some(result_id),
+                ranked: false,
+                created_at: chrono::Utc::now().naive_utc(),
+            };
+
+            attempt.insert_attempt(conn).await.map(|_| ())
+        },
+    };
+
+    if let Err(e) = recorded {
+        log::error!("Failed to record daily attempt: {:?}", e);
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use arc_swap::ArcSwap;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use super::room::RoomStats;
+
+/// Lobby events kept for clients resuming after a reconnect
+const LOBBY_HISTORY: usize = 256;
+const LOBBY_CHANNEL_CAPACITY: usize = 256;
+
+/// Change in the room list, pushed to the lobby feed.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LobbyEvent {
+    /// Every listed room, sent first when the feed can't resume from the last event
+    Snapshot {
+        rooms: Vec<RoomStats>,
+    },
+    Created {
+        room: RoomStats,
+    },
+    /// Players, host or status changed
+    Updated {
+        room: RoomStats,
+    },
+    Started {
+        room: RoomStats,
+    },
+    Closed {
+        room_id: Uuid,
+    },
+}
+
+#[derive(Clone)]
+pub struct LobbyEntry {
+    pub seq: u64,
+    pub event: Arc<LobbyEvent>,
+}
+
+pub struct LobbySubscription {
+    /// Events after the requested one, `None` if it is no longer in the history
+    pub missed: Option<Vec<LobbyEntry>>,
+    /// Sequence of the newest event, the receiver gets everything after it
+    pub seq: u64,
+    pub events: broadcast::Receiver<LobbyEntry>,
+}
+
+#[derive(Default)]
+struct LobbyHistory {
+    seq: u64,
+    entries: VecDeque<LobbyEntry>,
+}
+
+/// Room running on another node, as of the last lobby event it sent
+#[derive(Clone)]
+struct RemoteRoom {
+    node: String,
+    stats: Arc<RoomStats>,
+}
+
+/// Latest stats of the rooms running on this node. Room actors publish whenever their room
+/// changes, listing the rooms only loads the current map and never waits on a room.
+///
+/// Rooms of the other nodes are kept from their lobby events, so the list of the whole
+/// cluster never waits on another node either.
+///
+/// Also numbers the lobby events seen by this node, local rooms and the ones announced by
+/// the other nodes, and keeps the latest for feeds resuming after a reconnect.
+#[derive(Clone)]
+pub struct RoomDirectory {
+    rooms: Arc<ArcSwap<HashMap<Uuid, Arc<RoomStats>>>>,
+    remote: Arc<ArcSwap<HashMap<Uuid, RemoteRoom>>>,
+    /// Event ids from another directory, like one from before a restart, never resume here
+    generation: i64,
+    history: Arc<Mutex<LobbyHistory>>,
+    events: broadcast::Sender<LobbyEntry>,
+}
+
+impl Default for RoomDirectory {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(LOBBY_CHANNEL_CAPACITY);
+
+        Self {
+            rooms: Default::default(),
+            remote: Default::default(),
+            generation: Utc::now().timestamp_millis(),
+            history: Default::default(),
+            events,
+        }
+    }
+}
+
+impl RoomDirectory {
+    pub fn publish(&self, stats: RoomStats) {
+        let stats = Arc::new(stats);
+
+        self.rooms.rcu(|rooms| {
+            let mut rooms = HashMap::clone(rooms);
+            rooms.insert(stats.room_id, stats.clone());
+            rooms
+        });
+    }
+
+    pub fn remove(&self, room_id: Uuid) {
+        self.rooms.rcu(|rooms| {
+            let mut rooms = HashMap::clone(rooms);
+            rooms.remove(&room_id);
+            rooms
+        });
+    }
+
+    /// Rooms of this node only
+    pub fn snapshot(&self) -> Vec<RoomStats> {
+        self.rooms.load().values().map(|stats| RoomStats::clone(stats)).collect()
+    }
+
+    /// Rooms of the whole cluster
+    pub fn list(&self) -> Vec<RoomStats> {
+        let remote = self.remote.load();
+        let remote = remote.values().map(|room| RoomStats::clone(&room.stats));
+
+        self.snapshot().into_iter().chain(remote).collect()
+    }
+
+    /// Keeps the room list of `node` in line with the lobby event it sent
+    pub fn apply_remote(&self, node: &str, event: &LobbyEvent) {
+        match event {
+            LobbyEvent::Created { room }
+            | LobbyEvent::Updated { room }
+            | LobbyEvent::Started { room } => {
+                let room = RemoteRoom { node: node.to_string(), stats: Arc::new(room.clone()) };
+
+                self.remote.rcu(|rooms| {
+                    let mut rooms = HashMap::clone(rooms);
+                    rooms.insert(room.stats.room_id, room.clone());
+                    rooms
+                });
+            },
+            LobbyEvent::Closed { room_id } => {
+                self.remote.rcu(|rooms| {
+                    let mut rooms = HashMap::clone(rooms);
+                    rooms.remove(room_id);
+                    rooms
+                });
+            },
+            LobbyEvent::Snapshot { .. } => {},
+        }
+    }
+
+    /// Replaces the rooms of `node`, for a node that just joined
+    pub fn sync_node(&self, node: &str, stats: Vec<RoomStats>) {
+        self.remote.rcu(|rooms| {
+            let mut rooms = HashMap::clone(rooms);
+            rooms.retain(|_, room| room.node != node);
+            rooms.extend(stats.iter().map(|stats| {
+                let room = RemoteRoom { node: node.to_string(), stats: Arc::new(stats.clone()) };
+                (stats.room_id, room)
+            }));
+            rooms
+        });
+    }
+
+    /// Forgets the rooms of nodes that are gone, their rooms come back once adopted
+    pub fn retain_nodes(&self, live_nodes: &[String]) {
+        self.remote.rcu(|rooms| {
+            let mut rooms = HashMap::clone(rooms);
+            rooms.retain(|_, room| live_nodes.contains(&room.node));
+            rooms
+        });
+    }
+
+    /// Numbers the event and pushes it to the feeds of this node
+    pub fn announce(&self, event: LobbyEvent) {
+        let mut history = self.history.lock().unwrap();
+
+        history.seq += 1;
+        let entry = LobbyEntry { seq: history.seq, event: Arc::new(event) };
+
+        if history.entries.len() == LOBBY_HISTORY {
+            history.entries.pop_front();
+        }
+        history.entries.push_back(entry.clone());
+
+        // Sent under the lock, so a subscriber never misses or repeats an event
+        let _ = self.events.send(entry);
+    }
+
+    /// `last_event_id` is the `Last-Event-ID` a reconnecting client sent
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> LobbySubscription {
+        let history = self.history.lock().unwrap();
+        let events = self.events.subscribe();
+
+        let missed = last_event_id.and_then(|id| self.parse_event_id(id)).and_then(|last| {
+            let oldest = history.entries.front().map_or(history.seq + 1, |entry| entry.seq);
+
+            (last <= history.seq && last + 1 >= oldest)
+                .then(|| history.entries.iter().filter(|entry| entry.seq > last).cloned().collect())
+        });
+
+        LobbySubscription { missed, seq: history.seq, events }
+    }
+
+    pub fn event_id(&self, seq: u64) -> String {
+        format!("{}.{}", self.generation, seq)
+    }
+
+    fn parse_event_id(&self, id: &str) -> Option<u64> {
+        let (generation, seq) = id.split_once('.')?;
+
+        if generation.parse::<i64>().ok()? != self.generation {
+            return None;
+        }
+
+        seq.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed(directory: &RoomDirectory, count: u128) {
+        for i in 0..count {
+            directory.announce(LobbyEvent::Closed { room_id: Uuid::from_u128(i) });
+        }
+    }
+
+    fn seqs(entries: &[LobbyEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.seq).collect()
+    }
+
+    #[test]
+    fn resumes_after_the_last_event() {
+        let directory = RoomDirectory::default();
+        closed(&directory, 5);
+
+        let subscription = directory.subscribe(Some(&directory.event_id(3)));
+
+        assert_eq!(seqs(&subscription.missed.unwrap()), vec![4, 5]);
+        assert_eq!(subscription.seq, 5);
+    }
+
+    #[test]
+    fn up_to_date_client_misses_nothing() {
+        let directory = RoomDirectory::default();
+
+        let subscription = directory.subscribe(Some(&directory.event_id(0)));
+        assert_eq!(subscription.missed.map(|missed| missed.len()), Some(0));
+
+        closed(&directory, 2);
+        let subscription = directory.subscribe(Some(&directory.event_id(2)));
+        assert_eq!(subscription.missed.map(|missed| missed.len()), Some(0));
+    }
+
+    #[test]
+    fn no_resume_without_a_usable_event_id() {
+        let directory = RoomDirectory::default();
+        closed(&directory, 2);
+
+        assert!(directory.subscribe(None).missed.is_none());
+        assert!(directory.subscribe(Some("garbage")).missed.is_none());
+        let bad_seq = format!("{}.x", directory.generation);
+        assert!(directory.subscribe(Some(&bad_seq)).missed.is_none());
+        // Ahead of this directory, the client saw a sequence from before a restart
+        assert!(directory.subscribe(Some(&directory.event_id(3))).missed.is_none());
+    }
+
+    #[test]
+    fn event_ids_of_another_generation_never_resume() {
+        let directory = RoomDirectory::default();
+        let restarted = RoomDirectory { generation: directory.generation + 1, ..directory.clone() };
+        closed(&directory, 2);
+
+        assert!(directory.subscribe(Some(&restarted.event_id(1))).missed.is_none());
+    }
+
+    #[test]
+    fn no_resume_once_the_event_left_the_history() {
+        let directory = RoomDirectory::default();
+        closed(&directory, LOBBY_HISTORY as u128 + 2);
+
+        assert!(directory.subscribe(Some(&directory.event_id(1))).missed.is_none());
+
+        let missed = directory.subscribe(Some(&directory.event_id(2))).missed.unwrap();
+        assert_eq!(missed.len(), LOBBY_HISTORY);
+        assert_eq!(missed.first().map(|entry| entry.seq), Some(3));
+    }
+
+    #[test]
+    fn receiver_gets_the_events_after_the_subscription() {
+        let directory = RoomDirectory::default();
+        closed(&directory, 1);
+
+        let mut subscription = directory.subscribe(None);
+        closed(&directory, 1);
+
+        let entry = subscription.events.try_recv().unwrap();
+        assert_eq!(entry.seq, subscription.seq + 1);
+        assert!(subscription.events.try_recv().is_err());
+    }
+}
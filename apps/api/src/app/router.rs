@@ -7,7 +7,10 @@ use super::openapi::OpenApiDoc;
 
 pub fn build_router(state: AppState) -> Router<()> {
     let (router, openapi) = OpenApiDoc::router()
+        .routes(routes!(routes::auth::jwks))
         .routes(routes!(routes::user::login))
+        .routes(routes!(routes::user::refresh))
+        .routes(routes!(routes::user::logout))
         .routes(routes!(routes::user::signup))
         .routes(routes!(routes::user::profile))
         .routes(routes!(
@@ -18,14 +21,39 @@ pub fn build_router(state: AppState) -> Router<()> {
         .routes(routes!(routes::texts::get_texts, routes::texts::insert_text))
         .routes(routes!(routes::ws::ws_handler))
         .routes(routes!(routes::rooms::get_rooms, routes::rooms::create_room,))
+        .routes(routes!(routes::rooms::room_events))
         .routes(routes!(routes::rooms::start_room))
+        .routes(routes!(routes::rooms::get_room_series))
+        .routes(routes!(routes::rooms::get_room))
+        .routes(routes!(routes::rooms::get_room_replay))
+        .routes(routes!(routes::user::user_rooms))
         .routes(routes!(routes::texts::review_pending_text))
         .routes(routes!(routes::user::me_stats))
+        .routes(routes!(routes::user::me_sessions))
+        .routes(routes!(routes::user::revoke_session))
+        .routes(routes!(routes::user::change_password))
+        .routes(routes!(routes::user::request_password_reset))
+        .routes(routes!(routes::user::confirm_password_reset))
         .routes(routes!(routes::user::user_stats))
         .routes(routes!(routes::user::user_profile))
         .routes(routes!(routes::leaderboard::get_leaderboard))
         .routes(routes!(routes::user::patch_user))
+        .routes(routes!(routes::user::recompute_user_progression))
+        .routes(routes!(routes::user::mute_user, routes::user::unmute_user))
         .routes(routes!(routes::texts::get_pending_texts))
+        .routes(routes!(routes::daily::get_daily_challenge))
+        .routes(routes!(routes::daily::play_daily_challenge))
+        .routes(routes!(routes::daily::set_daily_challenge))
+        .routes(routes!(routes::daily::get_daily_leaderboard))
+        .routes(routes!(routes::daily::get_daily_archive))
+        .routes(routes!(
+            routes::tournaments::get_tournaments,
+            routes::tournaments::create_tournament
+        ))
+        .routes(routes!(routes::tournaments::get_tournament_bracket))
+        .routes(routes!(routes::tournaments::register_in_tournament))
+        .routes(routes!(routes::tournaments::start_tournament))
+        .routes(routes!(routes::tournaments::set_match_winner))
         .split_for_parts();
 
     router
@@ -1,3 +1,7 @@
+use std::sync::Arc;
+
+use super::mailer::Mailer;
+use super::rate_limit::WsRateLimits;
 use super::room::RoomsManager;
 use super::types::{DbPool, DeadpoolResult};
 
@@ -6,6 +10,8 @@ pub struct AppState {
     pub pool: DbPool,
     pub rooms_manager: RoomsManager,
     pub config: crate::app::config::AppEnvConfig,
+    pub ws_limits: WsRateLimits,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 impl AppState {
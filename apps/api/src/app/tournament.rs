@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use diesel_async::{AsyncConnection, scoped_futures::ScopedFutureExt};
+use uuid::Uuid;
+
+use crate::{
+    app::{
+        error::MyError,
+        room::{RoomKind, RoomOptions, RoomsManager},
+        types::{DbConn, MyResult},
+    },
+    db::{
+        custom_types::{MatchStatuses, TournamentFormats, TournamentStatuses},
+        models::{
+            dictionary::Dictionary,
+            tournament::{Tournament, TournamentMatch, TournamentParticipant},
+        },
+    },
+};
+
+/// Pair of players for a match, `None` opponent is a bye.
+type Pairing = (Uuid, Option<Uuid>);
+
+/// Rounds needed to get a single winner out of `players`.
+fn rounds_for(players: usize) -> i32 {
+    (players.max(2) as f64).log2().ceil() as i32
+}
+
+/// Best seed against the worst one. With an odd count the best seed gets a bye.
+fn seeded_pairings(seeded: &[Uuid]) -> Vec<Pairing> {
+    let mut players = seeded;
+    let mut pairings = vec![];
+
+    if players.len() % 2 == 1 {
+        pairings.push((players[0], None));
+        players = &players[1..];
+    }
+
+    let half = players.len() / 2;
+    for i in 0..half {
+        pairings.push((players[i], Some(players[players.len() - 1 - i])));
+    }
+
+    pairings
+}
+
+/// Winners of neighbouring matches meet in the next round, like in a printed bracket.
+fn bracket_pairings(winners: &[Uuid]) -> Vec<Pairing> {
+    winners.chunks(2).map(|pair| (pair[0], pair.get(1).copied())).collect()
+}
+
+/// Standings order in, everyone meets the closest player they haven't met yet.
+/// The lowest player without a bye sits out on an odd count.
+fn swiss_pairings(
+    standings: &[Uuid],
+    played: &HashSet<(Uuid, Uuid)>,
+    had_bye: &HashSet<Uuid>,
+) -> Vec<Pairing> {
+    let mut pool: Vec<Uuid> = standings.to_vec();
+    let mut pairings = vec![];
+
+    if pool.len() % 2 == 1 {
+        let bye_index = pool.iter().rposition(|p| !had_bye.contains(p)).unwrap_or(pool.len() - 1);
+        let bye = pool.remove(bye_index);
+        pairings.push((bye, None));
+    }
+
+    while !pool.is_empty() {
+        let player = pool.remove(0);
+        let opponent_index = pool
+            .iter()
+            .position(|o| !played.contains(&(player, *o)) && !played.contains(&(*o, player)))
+            .unwrap_or(0);
+        let opponent = pool.remove(opponent_index);
+        pairings.push((player, Some(opponent)));
+    }
+
+    pairings
+}
+
+async fn insert_round(
+    conn: &mut DbConn,
+    tournament_id: Uuid,
+    round: i32,
+    pairings: Vec<Pairing>,
+    participants: &mut [TournamentParticipant],
+) -> MyResult<()> {
+    let now = Utc::now().naive_utc();
+
+    for (position, (player_one_id, player_two_id)) in pairings.into_iter().enumerate() {
+        let is_bye = player_two_id.is_none();
+
+        let tournament_match = TournamentMatch {
+            id: Uuid::new_v4(),
+            tournament_id,
+            round,
+            position: position as i32,
+            player_one_id,
+            player_two_id,
+            room_id: None,
+            winner_id: is_bye.then_some(player_one_id),
+            status: if is_bye { MatchStatuses::Finished } else { MatchStatuses::Pending },
+            created_at: now,
+            finished_at: is_bye.then_some(now),
+        };
+
+        tournament_match.insert_match(conn).await?;
+
+        // A bye counts as a win
+        if is_bye
+            && let Some(participant) = participants.iter_mut().find(|p| p.user_id == player_one_id)
+        {
+            participant.score += 1;
+            participant.clone().modify_participant(conn).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Seeds the registered players by XP and creates the first round.
+pub async fn start_tournament(
+    manager: &RoomsManager,
+    conn: &mut DbConn,
+    tournament_id: Uuid,
+) -> MyResult<Tournament> {
+    let tournament = conn
+        .transaction::<_, MyError, _>(|conn| {
+            async move {
+                let mut tournament = Tournament::get_tournament_for_update(conn, tournament_id)
+                    .await?
+                    .ok_or(MyError::NotFound)?;
+
+                if tournament.status != TournamentStatuses::Registration {
+                    return Err(MyError::Validation("Tournament already started".to_string()));
+                }
+
+                let mut participants =
+                    TournamentParticipant::get_participants_with_xp(conn, tournament_id).await?;
+
+                if participants.len() < 2 {
+                    return Err(MyError::Validation("Not enough participants".to_string()));
+                }
+
+                participants.sort_by(|(a, a_xp), (b, b_xp)| {
+                    b_xp.cmp(a_xp).then(a.registered_at.cmp(&b.registered_at))
+                });
+
+                let mut seeded = vec![];
+                for (seed, (participant, _)) in participants.iter_mut().enumerate() {
+                    participant.seed = Some(seed as i32 + 1);
+                    *participant = participant.clone().modify_participant(conn).await?;
+                    seeded.push(participant.user_id);
+                }
+
+                let mut participants: Vec<_> = participants.into_iter().map(|(p, _)| p).collect();
+
+                let max_rounds = rounds_for(seeded.len());
+                tournament.rounds = match tournament.format {
+                    TournamentFormats::SingleElimination => max_rounds,
+                    TournamentFormats::Swiss if tournament.rounds > 0 => tournament.rounds,
+                    TournamentFormats::Swiss => max_rounds,
+                };
+                tournament.status = TournamentStatuses::Running;
+                tournament.current_round = 1;
+                tournament.started_at = Some(Utc::now().naive_utc());
+
+                let pairings = seeded_pairings(&seeded);
+                insert_round(conn, tournament_id, 1, pairings, &mut participants).await?;
+
+                tournament.modify_tournament(conn).await
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    launch_pending_matches(manager, conn, &tournament).await?;
+
+    Ok(tournament)
+}
+
+/// Records the winner of a match and, once the round is over, pairs the next one.
+/// `winner` is `None` when nobody finished - the match stays open for a moderator.
+pub async fn report_match_result(
+    manager: &RoomsManager,
+    conn: &mut DbConn,
+    match_id: Uuid,
+    winner: Option<Uuid>,
+) -> MyResult<()> {
+    let Some(winner) = winner else {
+        log::warn!("Tournament match {} ended without a winner", match_id);
+        return Ok(());
+    };
+
+    let tournament = conn
+        .transaction::<_, MyError, _>(|conn| {
+            async move {
+                let mut tournament_match = TournamentMatch::get_match_by_id(conn, match_id)
+                    .await?
+                    .ok_or(MyError::NotFound)?;
+
+                let mut tournament =
+                    Tournament::get_tournament_for_update(conn, tournament_match.tournament_id)
+                        .await?
+                        .ok_or(MyError::NotFound)?;
+
+                if tournament_match.status == MatchStatuses::Finished {
+                    return Ok(None);
+                }
+
+                let loser = if tournament_match.player_one_id == winner {
+                    tournament_match.player_two_id
+                } else if tournament_match.player_two_id == Some(winner) {
+                    Some(tournament_match.player_one_id)
+                } else {
+                    return Err(MyError::Validation("Winner is not in the match".to_string()));
+                };
+
+                tournament_match.winner_id = Some(winner);
+                tournament_match.status = MatchStatuses::Finished;
+                tournament_match.finished_at = Some(Utc::now().naive_utc());
+                tournament_match.modify_match(conn).await?;
+
+                let mut participants: Vec<_> =
+                    TournamentParticipant::get_participants(conn, tournament.id)
+                        .await?
+                        .into_iter()
+                        .map(|(p, _)| p)
+                        .collect();
+
+                for participant in participants.iter_mut() {
+                    let is_winner = participant.user_id == winner;
+                    let is_loser = Some(participant.user_id) == loser;
+
+                    if is_winner {
+                        participant.score += 1;
+                    } else if is_loser && tournament.format == TournamentFormats::SingleElimination
+                    {
+                        participant.eliminated = true;
+                    } else {
+                        continue;
+                    }
+
+                    *participant = participant.clone().modify_participant(conn).await?;
+                }
+
+                let matches = TournamentMatch::get_matches(conn, tournament.id).await?;
+                let round: Vec<_> =
+                    matches.iter().filter(|m| m.round == tournament.current_round).collect();
+
+                if round.iter().any(|m| m.status != MatchStatuses::Finished) {
+                    return Ok(None);
+                }
+
+                let next_pairings = match tournament.format {
+                    TournamentFormats::SingleElimination => {
+                        let winners: Vec<_> = round.iter().filter_map(|m| m.winner_id).collect();
+                        if winners.len() > 1 { Some(bracket_pairings(&winners)) } else { None }
+                    },
+                    TournamentFormats::Swiss if tournament.current_round < tournament.rounds => {
+                        let mut standings = participants.clone();
+                        standings.sort_by(|a, b| b.score.cmp(&a.score).then(a.seed.cmp(&b.seed)));
+                        let standings: Vec<_> = standings.iter().map(|p| p.user_id).collect();
+
+                        let played = matches
+                            .iter()
+                            .filter_map(|m| m.player_two_id.map(|two| (m.player_one_id, two)))
+                            .collect();
+                        let had_bye = matches
+                            .iter()
+                            .filter(|m| m.player_two_id.is_none())
+                            .map(|m| m.player_one_id)
+                            .collect();
+
+                        Some(swiss_pairings(&standings, &played, &had_bye))
+                    },
+                    TournamentFormats::Swiss => None,
+                };
+
+                match next_pairings {
+                    Some(pairings) => {
+                        tournament.current_round += 1;
+                        let round = tournament.current_round;
+                        insert_round(conn, tournament.id, round, pairings, &mut participants)
+                            .await?;
+                    },
+                    None => {
+                        // Ties on score go to the better seed
+                        let champion = participants
+                            .iter()
+                            .filter(|p| !p.eliminated)
+                            .max_by(|a, b| a.score.cmp(&b.score).then(b.seed.cmp(&a.seed)))
+                            .map(|p| p.user_id);
+
+                        tournament.status = TournamentStatuses::Finished;
+                        tournament.winner_id = champion;
+                        tournament.ended_at = Some(Utc::now().naive_utc());
+                    },
+                }
+
+                Ok(Some(tournament.modify_tournament(conn).await?))
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    if let Some(tournament) = tournament {
+        launch_pending_matches(manager, conn, &tournament).await?;
+    }
+
+    Ok(())
+}
+
+/// Creates a room for every match of the tournament that doesn't have one yet.
+async fn launch_pending_matches(
+    manager: &RoomsManager,
+    conn: &mut DbConn,
+    tournament: &Tournament,
+) -> MyResult<()> {
+    if tournament.status != TournamentStatuses::Running {
+        return Ok(());
+    }
+
+    let dictionary = Dictionary::get_dictionary_by_id(conn, tournament.dictionary_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    for mut tournament_match in
+        TournamentMatch::get_matches_without_room(conn, tournament.id).await?
+    {
+        let Some(player_two_id) = tournament_match.player_two_id else {
+            continue;
+        };
+
+        let Some(text) = dictionary.get_random_text_in_dictionary(conn).await? else {
+            return Err(MyError::NotFound);
+        };
+
+        let kind = RoomKind::Tournament {
+            tournament_id: tournament.id,
+            match_id: tournament_match.id,
+            players: vec![tournament_match.player_one_id, player_two_id],
+        };
+
+        let room_id = manager.create_room(text, dictionary.clone(), RoomOptions::new(kind)).await?;
+
+        tournament_match.room_id = Some(room_id);
+        tournament_match.status = MatchStatuses::Running;
+        tournament_match.modify_match(conn).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(count: u128) -> Vec<Uuid> {
+        (1..=count).map(Uuid::from_u128).collect()
+    }
+
+    #[test]
+    fn seeded_pairs_best_against_worst() {
+        let p = players(4);
+
+        assert_eq!(seeded_pairings(&p), vec![(p[0], Some(p[3])), (p[1], Some(p[2]))]);
+    }
+
+    #[test]
+    fn seeded_odd_count_gives_the_best_seed_a_bye() {
+        let p = players(5);
+
+        assert_eq!(seeded_pairings(&p), vec![(p[0], None), (p[1], Some(p[4])), (p[2], Some(p[3]))]);
+        assert_eq!(seeded_pairings(&p[..1]), vec![(p[0], None)]);
+        assert!(seeded_pairings(&[]).is_empty());
+    }
+
+    #[test]
+    fn bracket_pairs_neighbouring_winners() {
+        let p = players(4);
+
+        assert_eq!(bracket_pairings(&p), vec![(p[0], Some(p[1])), (p[2], Some(p[3]))]);
+    }
+
+    #[test]
+    fn bracket_odd_winner_out_gets_a_bye() {
+        let p = players(3);
+
+        assert_eq!(bracket_pairings(&p), vec![(p[0], Some(p[1])), (p[2], None)]);
+    }
+
+    #[test]
+    fn swiss_avoids_rematches() {
+        let p = players(4);
+        let played = HashSet::from([(p[0], p[1]), (p[2], p[3])]);
+
+        assert_eq!(
+            swiss_pairings(&p, &played, &HashSet::new()),
+            vec![(p[0], Some(p[2])), (p[1], Some(p[3]))]
+        );
+    }
+
+    #[test]
+    fn swiss_rematches_when_everyone_has_met() {
+        let p = players(2);
+        let played = HashSet::from([(p[1], p[0])]);
+
+        assert_eq!(swiss_pairings(&p, &played, &HashSet::new()), vec![(p[0], Some(p[1]))]);
+    }
+
+    #[test]
+    fn swiss_bye_goes_to_the_lowest_player_without_one() {
+        let p = players(5);
+
+        let pairings = swiss_pairings(&p, &HashSet::new(), &HashSet::from([p[4]]));
+        assert_eq!(pairings[0], (p[3], None));
+        assert_eq!(pairings.len(), 3);
+
+        let everyone = p.iter().copied().collect();
+        let pairings = swiss_pairings(&p, &HashSet::new(), &everyone);
+        assert_eq!(pairings[0], (p[4], None));
+    }
+
+    #[test]
+    fn rounds_cover_every_player() {
+        assert_eq!(rounds_for(0), 1);
+        assert_eq!(rounds_for(2), 1);
+        assert_eq!(rounds_for(5), 3);
+        assert_eq!(rounds_for(8), 3);
+        assert_eq!(rounds_for(9), 4);
+    }
+}
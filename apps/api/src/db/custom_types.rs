@@ -31,3 +31,94 @@ pub enum Leagues {
     Mobile,
     Web,
 }
+
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::RoomKinds")]
+#[serde(rename_all = "lowercase")]
+pub enum RoomKinds {
+    Regular,
+    Daily,
+    Tournament,
+}
+
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::RoomModes")]
+#[serde(rename_all = "lowercase")]
+pub enum RoomModes {
+    Solo,
+    Team,
+    Relay,
+}
+
+/// Lifecycle of a room, the transitions are in `app::room`.
+#[derive(
+    diesel_derive_enum::DbEnum,
+    PartialEq,
+    Eq,
+    Debug,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::RoomStatuses")]
+#[serde(rename_all = "lowercase")]
+pub enum RoomStatuses {
+    Created,
+    Countdown,
+    Running,
+    Finished,
+    Aborted,
+}
+
+/// How a player got out of a room.
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::LeaveReasons")]
+#[serde(rename_all = "lowercase")]
+pub enum LeaveReasons {
+    /// Crossed the line
+    Finished,
+    /// Wasn't racing, left the lobby or stopped watching
+    Left,
+    /// Was racing and disconnected, or the race ended without them
+    Dropped,
+}
+
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::TournamentFormats")]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentFormats {
+    SingleElimination,
+    Swiss,
+}
+
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::TournamentStatuses")]
+#[serde(rename_all = "lowercase")]
+pub enum TournamentStatuses {
+    Registration,
+    Running,
+    Finished,
+}
+
+#[derive(
+    diesel_derive_enum::DbEnum, PartialEq, Debug, Serialize, Deserialize, Clone, utoipa::ToSchema,
+)]
+#[db_enum(existing_type_path = "crate::db::schema::sql_types::MatchStatuses")]
+#[serde(rename_all = "lowercase")]
+pub enum MatchStatuses {
+    Pending,
+    Running,
+    Finished,
+}
@@ -0,0 +1,121 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    deserialize::FromSql,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{Output, ToSql},
+    sql_types::Jsonb,
+};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::schema::{achievements, user_achievements},
+};
+
+/// Condition that unlocks an achievement, stored as JSONB in `achievements.rule`.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, diesel::AsExpression, diesel::FromSqlRow, utoipa::ToSchema,
+)]
+#[diesel[sql_type = diesel::pg::sql_types::Jsonb]]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AchievementRule {
+    /// Total finished races
+    Races { count: i64 },
+    /// Speed reached in a single race
+    MinWpm { wpm: f32 },
+    /// Finished races in the last 24 hours
+    RacesInDay { count: i64 },
+    /// Race without mistakes on a text of at least `min_length` characters
+    PerfectAccuracy { min_length: usize },
+    /// First places in rooms with more than one player
+    MultiplayerWins { count: i64 },
+}
+
+impl FromSql<Jsonb, Pg> for AchievementRule {
+    fn from_sql(bytes: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for AchievementRule {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Serialize, Clone, utoipa::ToSchema)]
+#[diesel(table_name = achievements)]
+pub struct Achievement {
+    pub id: Uuid,
+    pub code: String,
+    pub name: String,
+    pub description: String,
+    pub rule: AchievementRule,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Serialize, Clone, utoipa::ToSchema)]
+#[diesel(table_name = user_achievements)]
+pub struct UserAchievement {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub achievement_id: Uuid,
+    pub result_id: Option<Uuid>,
+    pub unlocked_at: NaiveDateTime,
+}
+
+impl Achievement {
+    pub async fn get_locked_achievements_by_user_id(
+        conn: &mut DbConn,
+        id_user: Uuid,
+    ) -> MyResult<Vec<Achievement>> {
+        use crate::db::schema::achievements::dsl::*;
+
+        let unlocked = user_achievements::table
+            .filter(user_achievements::user_id.eq(id_user))
+            .select(user_achievements::achievement_id);
+
+        let result = achievements
+            .filter(id.ne_all(unlocked))
+            .select(Achievement::as_select())
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+}
+
+impl UserAchievement {
+    pub async fn insert_user_achievement(self, conn: &mut DbConn) -> MyResult<usize> {
+        use crate::db::schema::user_achievements::dsl::*;
+
+        // Unique (user_id, achievement_id) - concurrent evaluation must not unlock it twice
+        Ok(diesel::insert_into(user_achievements)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?)
+    }
+
+    pub async fn get_user_achievements_by_user_id(
+        conn: &mut DbConn,
+        id_user: Uuid,
+    ) -> MyResult<Vec<(UserAchievement, Achievement)>> {
+        use crate::db::schema::user_achievements::dsl::*;
+
+        let result = user_achievements
+            .inner_join(achievements::table)
+            .filter(user_id.eq(id_user))
+            .order(unlocked_at.asc())
+            .select((UserAchievement::as_select(), Achievement::as_select()))
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::schema::{cluster_mailbox, cluster_nodes},
+};
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = cluster_nodes)]
+pub struct ClusterNode {
+    pub id: String,
+    pub heartbeat_at: NaiveDateTime,
+}
+
+impl ClusterNode {
+    pub async fn heartbeat(self, conn: &mut DbConn) -> MyResult<usize> {
+        use crate::db::schema::cluster_nodes::dsl::*;
+
+        Ok(diesel::insert_into(cluster_nodes)
+            .values(&self)
+            .on_conflict(id)
+            .do_update()
+            .set(heartbeat_at.eq(self.heartbeat_at))
+            .execute(conn)
+            .await?)
+    }
+
+    /// Nodes that sent a heartbeat after `since`.
+    pub async fn get_live_node_ids(
+        conn: &mut DbConn,
+        since: NaiveDateTime,
+    ) -> MyResult<Vec<String>> {
+        use crate::db::schema::cluster_nodes::dsl::*;
+        Ok(cluster_nodes.filter(heartbeat_at.gt(since)).select(id).load(conn).await?)
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = cluster_mailbox)]
+pub struct ClusterMail {
+    pub id: Uuid,
+    pub payload: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl ClusterMail {
+    pub async fn insert_mail(self, conn: &mut DbConn) -> MyResult<usize> {
+        use crate::db::schema::cluster_mailbox::dsl::*;
+        Ok(diesel::insert_into(cluster_mailbox).values(self).execute(conn).await?)
+    }
+
+    /// Removes the mail and returns its payload, mail is read exactly once.
+    pub async fn take_mail(conn: &mut DbConn, id_mail: Uuid) -> MyResult<Option<String>> {
+        use crate::db::schema::cluster_mailbox::dsl::*;
+
+        Ok(diesel::delete(cluster_mailbox.filter(id.eq(id_mail)))
+            .returning(payload)
+            .get_result(conn)
+            .await
+            .optional()?)
+    }
+
+    /// Mail nobody picked up, its recipient died before reading it.
+    pub async fn delete_mail_before(conn: &mut DbConn, before: NaiveDateTime) -> MyResult<usize> {
+        use crate::db::schema::cluster_mailbox::dsl::*;
+        Ok(diesel::delete(cluster_mailbox.filter(created_at.lt(before))).execute(conn).await?)
+    }
+}
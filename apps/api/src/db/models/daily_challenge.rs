@@ -0,0 +1,209 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::{
+        models::text::Text,
+        schema::{daily_challenge_attempts, daily_challenges},
+    },
+};
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Clone, utoipa::ToSchema,
+)]
+#[diesel(table_name = daily_challenges)]
+pub struct DailyChallenge {
+    pub id: Uuid,
+    pub challenge_date: NaiveDate,
+    pub text_id: Uuid,
+    pub chosen_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Serialize, Clone, utoipa::ToSchema)]
+#[diesel(table_name = daily_challenge_attempts)]
+pub struct DailyChallengeAttempt {
+    pub id: Uuid,
+    pub challenge_id: Uuid,
+    pub user_id: Uuid,
+    /// Unset while the ranked run is on, and for good if it was abandoned
+    pub result_id: Option<Uuid>,
+    pub ranked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Serialize, utoipa::ToSchema)]
+pub struct DailyTopUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub result_id: Uuid,
+    pub wpm: f32,
+    pub cpm: f32,
+    pub mistakes: i16,
+    pub achieved_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Serialize, utoipa::ToSchema)]
+pub struct DailyArchiveEntry {
+    pub id: Uuid,
+    pub challenge_date: NaiveDate,
+    pub text_id: Uuid,
+    pub title: String,
+    pub participants: i64,
+}
+
+impl DailyChallenge {
+    pub async fn get_challenge_by_date(
+        conn: &mut DbConn,
+        date: NaiveDate,
+    ) -> MyResult<Option<(DailyChallenge, Text)>> {
+        use crate::db::schema::daily_challenges::dsl::*;
+        use crate::db::schema::texts;
+
+        let result = daily_challenges
+            .inner_join(texts::table)
+            .filter(challenge_date.eq(date))
+            .select((DailyChallenge::as_select(), Text::as_select()))
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
+    /// Inserts the challenge unless the date already has one.
+    pub async fn insert_challenge_if_missing(self, conn: &mut DbConn) -> MyResult<usize> {
+        use crate::db::schema::daily_challenges::dsl::*;
+
+        Ok(diesel::insert_into(daily_challenges)
+            .values(self)
+            .on_conflict(challenge_date)
+            .do_nothing()
+            .execute(conn)
+            .await?)
+    }
+
+    /// Inserts the challenge or replaces the text picked for its date.
+    pub async fn upsert_challenge(self, conn: &mut DbConn) -> MyResult<DailyChallenge> {
+        use crate::db::schema::daily_challenges::dsl::*;
+
+        Ok(diesel::insert_into(daily_challenges)
+            .values(&self)
+            .on_conflict(challenge_date)
+            .do_update()
+            .set((text_id.eq(self.text_id), chosen_by.eq(self.chosen_by)))
+            .get_result(conn)
+            .await?)
+    }
+
+    pub async fn get_archive(
+        conn: &mut DbConn,
+        before: NaiveDate,
+        offset: i64,
+        limit: i64,
+    ) -> MyResult<Vec<DailyArchiveEntry>> {
+        use crate::db::schema::daily_challenges::dsl::*;
+        use crate::db::schema::texts;
+
+        let result = daily_challenges
+            .inner_join(texts::table)
+            .filter(challenge_date.lt(before))
+            .select((
+                id,
+                challenge_date,
+                text_id,
+                texts::title,
+                diesel::dsl::sql::<diesel::sql_types::BigInt>(
+                    "(SELECT COUNT(*) FROM daily_challenge_attempts a WHERE a.challenge_id = \
+                     daily_challenges.id AND a.ranked AND a.result_id IS NOT NULL)",
+                ),
+            ))
+            .order(challenge_date.desc())
+            .offset(offset)
+            .limit(limit)
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+}
+
+impl DailyChallengeAttempt {
+    /// Returns `false` if the attempt is ranked and the user already has a ranked one.
+    pub async fn insert_attempt(self, conn: &mut DbConn) -> MyResult<bool> {
+        use crate::db::schema::daily_challenge_attempts::dsl::*;
+
+        let inserted = diesel::insert_into(daily_challenge_attempts)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    /// Attaches the result of the finished run to its reserved attempt.
+    pub async fn set_result(conn: &mut DbConn, id_attempt: Uuid, id_result: Uuid) -> MyResult<()> {
+        use crate::db::schema::daily_challenge_attempts::dsl::*;
+
+        diesel::update(daily_challenge_attempts.find(id_attempt))
+            .set(result_id.eq(Some(id_result)))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn has_ranked_attempt(
+        conn: &mut DbConn,
+        id_challenge: Uuid,
+        id_user: Uuid,
+    ) -> MyResult<bool> {
+        use crate::db::schema::daily_challenge_attempts::dsl::*;
+
+        let result = diesel::select(diesel::dsl::exists(
+            daily_challenge_attempts
+                .filter(challenge_id.eq(id_challenge))
+                .filter(user_id.eq(id_user))
+                .filter(ranked.eq(true)),
+        ))
+        .get_result(conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_leaderboard(
+        conn: &mut DbConn,
+        id_challenge: Uuid,
+    ) -> MyResult<Vec<DailyTopUser>> {
+        use crate::db::schema::daily_challenge_attempts::dsl::*;
+        use crate::db::schema::results;
+        use crate::db::schema::users;
+
+        let result = daily_challenge_attempts
+            .inner_join(results::table)
+            .inner_join(users::table)
+            .filter(challenge_id.eq(id_challenge))
+            .filter(ranked.eq(true))
+            .select((
+                users::id,
+                users::username,
+                results::id,
+                results::wpm,
+                results::cpm,
+                results::mistakes,
+                results::end_time,
+            ))
+            .order((results::wpm.desc(), results::end_time.asc()))
+            .limit(50)
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+}
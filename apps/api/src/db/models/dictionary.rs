@@ -1,7 +1,7 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
@@ -11,7 +11,9 @@ use crate::{
 
 use super::text::Text;
 
-#[derive(Queryable, Selectable, Insertable, Debug, Serialize, utoipa::ToSchema, Clone)]
+#[derive(
+    Queryable, Selectable, Insertable, Debug, Serialize, Deserialize, utoipa::ToSchema, Clone,
+)]
 #[diesel(table_name = dictionaries)]
 pub struct Dictionary {
     pub id: Uuid,
@@ -95,6 +97,32 @@ impl Dictionary {
         Ok(result)
     }
 
+    pub async fn get_text_count_in_dictionary(&self, conn: &mut DbConn) -> MyResult<i64> {
+        use crate::db::schema::texts::dsl::*;
+
+        Ok(texts.filter(dictionary_id.eq(self.id)).count().get_result(conn).await?)
+    }
+
+    /// Stable pick: texts are ordered by id, so the same index gives the same text.
+    pub async fn get_text_by_index_in_dictionary(
+        &self,
+        conn: &mut DbConn,
+        index: i64,
+    ) -> MyResult<Option<Text>> {
+        use crate::db::schema::texts::dsl::*;
+
+        let result = texts
+            .filter(dictionary_id.eq(self.id))
+            .order(id.asc())
+            .offset(index)
+            .select(Text::as_select())
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
     pub async fn get_dictionary_text_count(conn: &mut DbConn) -> MyResult<Vec<(Uuid, i64)>> {
         use crate::db::schema::texts;
         use diesel::dsl::count_star;
@@ -1,8 +1,15 @@
+pub mod achievement;
+pub mod cluster;
+pub mod daily_challenge;
 pub mod dictionary;
+pub mod password_reset;
 pub mod pending_text;
 pub mod result;
 pub mod room;
+pub mod room_snapshot;
 pub mod room_user;
 pub mod session;
 pub mod text;
+pub mod tournament;
 pub mod user;
+pub mod xp_ledger;
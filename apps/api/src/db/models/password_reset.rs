@@ -0,0 +1,68 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::schema::password_resets,
+};
+
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = password_resets)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl PasswordReset {
+    pub async fn insert_reset(self, conn: &mut DbConn) -> MyResult<PasswordReset> {
+        use crate::db::schema::password_resets::dsl::*;
+
+        Ok(diesel::insert_into(password_resets).values(self).get_result(conn).await?)
+    }
+
+    /// Marks the reset used, `None` if the token is unknown, used or expired
+    pub async fn use_reset(
+        conn: &mut DbConn,
+        hash: &str,
+        now: NaiveDateTime,
+    ) -> MyResult<Option<PasswordReset>> {
+        use crate::db::schema::password_resets::dsl::*;
+
+        let result = diesel::update(
+            password_resets
+                .filter(token_hash.eq(hash))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(now)),
+        )
+        .set(used_at.eq(now))
+        .get_result(conn)
+        .await
+        .optional()?;
+
+        Ok(result)
+    }
+
+    /// Older links of the user stop working once one of them was used
+    pub async fn spend_user_resets(
+        conn: &mut DbConn,
+        target_user_id: Uuid,
+        now: NaiveDateTime,
+    ) -> MyResult<()> {
+        use crate::db::schema::password_resets::dsl::*;
+
+        diesel::update(
+            password_resets.filter(user_id.eq(target_user_id)).filter(used_at.is_null()),
+        )
+        .set(used_at.eq(now))
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
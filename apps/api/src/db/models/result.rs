@@ -56,6 +56,7 @@ pub struct Results {
     pub cpm: f32,
     pub stats: ResultStats,
     pub room_user_id: Uuid,
+    pub placement: Option<i16>,
 }
 
 #[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
@@ -91,6 +92,23 @@ pub struct TopUser {
 }
 
 impl Results {
+    /// Share of the typed characters that weren't mistakes, as shown when the race ended.
+    pub fn accuracy(&self) -> f32 {
+        let typed: usize = self
+            .stats
+            .keystrokes
+            .iter()
+            .filter(|k| !k.mistake)
+            .map(|k| k.key.chars().count())
+            .sum();
+
+        if typed == 0 {
+            return 0.0;
+        }
+
+        (100.0 * typed.saturating_sub(self.mistakes.max(0) as usize) as f32 / typed as f32).max(0.0)
+    }
+
     pub async fn get_result_by_id(conn: &mut DbConn, id_result: Uuid) -> MyResult<Option<Results>> {
         use crate::db::schema::results::dsl::*;
         Ok(results.filter(id.eq(id_result)).first(conn).await.optional()?)
@@ -142,6 +160,44 @@ impl Results {
             .await?)
     }
 
+    pub async fn get_results_count_since_by_user_id(
+        conn: &mut DbConn,
+        id_user: Uuid,
+        since: NaiveDateTime,
+    ) -> MyResult<i64> {
+        use crate::db::schema::results::dsl::*;
+        use crate::db::schema::room_users;
+
+        Ok(results
+            .inner_join(room_users::table)
+            .filter(room_users::user_id.eq(id_user))
+            .filter(end_time.gt(since))
+            .count()
+            .first(conn)
+            .await?)
+    }
+
+    pub async fn get_multiplayer_wins_count_by_user_id(
+        conn: &mut DbConn,
+        id_user: Uuid,
+    ) -> MyResult<i64> {
+        use crate::db::schema::results::dsl::*;
+        use crate::db::schema::room_users;
+
+        Ok(results
+            .inner_join(room_users::table)
+            .filter(room_users::user_id.eq(id_user))
+            .filter(placement.eq(1))
+            // Opponents are the others who raced, spectators have no result
+            .filter(diesel::dsl::sql::<diesel::sql_types::Bool>(
+                "(SELECT COUNT(*) FROM room_users others JOIN results raced ON raced.room_user_id \
+                 = others.id WHERE others.room_id = room_users.room_id) > 1",
+            ))
+            .count()
+            .first(conn)
+            .await?)
+    }
+
     pub async fn get_average_wpm_cpm_mistakes_in_dictionary_by_user_id(
         conn: &mut DbConn,
         id_dictionary: Uuid,
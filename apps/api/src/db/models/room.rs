@@ -1,11 +1,16 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
+use serde::Serialize;
 use uuid::Uuid;
 
 use crate::{
     app::types::{DbConn, MyResult},
-    db::schema::rooms,
+    db::{
+        custom_types::{LeaveReasons, RoomKinds, RoomModes, RoomStatuses},
+        models::{result::Results, room_user::RoomUser},
+        schema::rooms,
+    },
 };
 #[derive(Queryable, Selectable, Insertable, AsChangeset, Debug)]
 #[diesel(table_name = rooms)]
@@ -13,8 +18,42 @@ pub struct Room {
     pub id: Uuid,
     pub text_id: Uuid,
     pub created_at: NaiveDateTime,
-    pub started_at: NaiveDateTime,
-    pub ended_at: NaiveDateTime,
+    /// Set once the countdown is over
+    pub started_at: Option<NaiveDateTime>,
+    /// Set once the room is finished or aborted
+    pub ended_at: Option<NaiveDateTime>,
+    pub kind: RoomKinds,
+    pub mode: RoomModes,
+    pub series_id: Uuid,
+    pub previous_room_id: Option<Uuid>,
+    pub node_id: Option<String>,
+    pub status: RoomStatuses,
+}
+
+#[derive(Queryable, Serialize, utoipa::ToSchema)]
+pub struct SeriesResultRow {
+    pub room_id: Uuid,
+    pub username: String,
+    pub wpm: f32,
+    pub cpm: f32,
+    pub mistakes: i16,
+    pub placement: Option<i16>,
+}
+
+/// A race in a user's history, result fields are empty unless they finished.
+#[derive(Queryable, Serialize, utoipa::ToSchema)]
+pub struct UserRoomRow {
+    pub room_id: Uuid,
+    pub status: RoomStatuses,
+    pub kind: RoomKinds,
+    pub mode: RoomModes,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+    pub leave_reason: Option<LeaveReasons>,
+    pub placement: Option<i16>,
+    pub wpm: Option<f32>,
+    pub mistakes: Option<i16>,
 }
 
 impl Room {
@@ -32,4 +71,140 @@ impl Room {
         use crate::db::schema::rooms::dsl::*;
         Ok(diesel::update(rooms.filter(id.eq(self.id))).set(self).get_result(conn).await?)
     }
+
+    /// Moves the room to `new_status` if the lifecycle allows it from the stored status, stamping
+    /// `started_at` or `ended_at` along. `false` if the room was somewhere else.
+    pub async fn set_status(
+        conn: &mut DbConn,
+        id_room: Uuid,
+        new_status: RoomStatuses,
+        at: NaiveDateTime,
+    ) -> MyResult<bool> {
+        use crate::db::schema::rooms::dsl::*;
+
+        let target =
+            rooms.filter(id.eq(id_room)).filter(status.eq_any(new_status.previous().to_vec()));
+
+        let updated = match new_status {
+            RoomStatuses::Running => {
+                diesel::update(target)
+                    .set((status.eq(new_status), started_at.eq(at)))
+                    .execute(conn)
+                    .await?
+            },
+            RoomStatuses::Finished | RoomStatuses::Aborted => {
+                diesel::update(target)
+                    .set((status.eq(new_status), ended_at.eq(at)))
+                    .execute(conn)
+                    .await?
+            },
+            _ => diesel::update(target).set(status.eq(new_status)).execute(conn).await?,
+        };
+
+        Ok(updated == 1)
+    }
+
+    /// Moves the room to `to` unless another node took it over from `from` first.
+    pub async fn claim_room(
+        conn: &mut DbConn,
+        id_room: Uuid,
+        from: Option<&str>,
+        to: &str,
+    ) -> MyResult<bool> {
+        use crate::db::schema::rooms::dsl::*;
+
+        let updated = diesel::update(rooms.filter(id.eq(id_room)))
+            .filter(node_id.is_not_distinct_from(from))
+            .set(node_id.eq(to))
+            .execute(conn)
+            .await?;
+
+        Ok(updated == 1)
+    }
+
+    /// Oldest room first.
+    pub async fn get_series_rooms(conn: &mut DbConn, id_series: Uuid) -> MyResult<Vec<Room>> {
+        use crate::db::schema::rooms::dsl::*;
+        Ok(rooms.filter(series_id.eq(id_series)).order(created_at.asc()).load(conn).await?)
+    }
+
+    pub async fn get_series_results(
+        conn: &mut DbConn,
+        id_series: Uuid,
+    ) -> MyResult<Vec<SeriesResultRow>> {
+        use crate::db::schema::rooms::dsl::*;
+        use crate::db::schema::{results, room_users, users};
+
+        let result = results::table
+            .inner_join(room_users::table.inner_join(rooms).inner_join(users::table))
+            .filter(series_id.eq(id_series))
+            .select((
+                id,
+                users::username,
+                results::wpm,
+                results::cpm,
+                results::mistakes,
+                results::placement,
+            ))
+            .order((created_at.asc(), results::placement.asc()))
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Everyone who joined the room with their username and result, if they finished.
+    pub async fn get_room_participants(
+        conn: &mut DbConn,
+        id_room: Uuid,
+    ) -> MyResult<Vec<(RoomUser, String, Option<Results>)>> {
+        use crate::db::schema::{results, room_users, users};
+
+        let result = room_users::table
+            .inner_join(users::table)
+            .left_join(results::table)
+            .filter(room_users::room_id.eq(id_room))
+            .select((RoomUser::as_select(), users::username, Option::<Results>::as_select()))
+            .order(room_users::joined_at.asc())
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Newest room first.
+    pub async fn get_user_rooms(
+        conn: &mut DbConn,
+        id_user: Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> MyResult<Vec<UserRoomRow>> {
+        use crate::db::schema::rooms::dsl::*;
+        use crate::db::schema::{results, room_users};
+
+        let result = room_users::table
+            .inner_join(rooms)
+            .left_join(results::table)
+            .filter(room_users::user_id.eq(id_user))
+            .select((
+                id,
+                status,
+                kind,
+                mode,
+                created_at,
+                started_at,
+                ended_at,
+                room_users::leave_reason,
+                results::placement.nullable(),
+                results::wpm.nullable(),
+                results::mistakes.nullable(),
+            ))
+            .order(created_at.desc())
+            .offset(offset)
+            .limit(limit)
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
 }
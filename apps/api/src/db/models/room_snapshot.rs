@@ -0,0 +1,52 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::schema::room_snapshots,
+};
+
+/// Checkpoint of a live room. `state` is owned by the rooms manager, the database only keeps it.
+#[derive(Queryable, Selectable, Insertable, Debug)]
+#[diesel(table_name = room_snapshots)]
+pub struct RoomSnapshot {
+    pub room_id: Uuid,
+    pub state: serde_json::Value,
+    pub saved_at: NaiveDateTime,
+}
+
+impl RoomSnapshot {
+    /// Every live room with the node that runs it.
+    pub async fn get_snapshots_with_node(
+        conn: &mut DbConn,
+    ) -> MyResult<Vec<(RoomSnapshot, Option<String>)>> {
+        use crate::db::schema::room_snapshots::dsl::*;
+        use crate::db::schema::rooms;
+
+        Ok(room_snapshots
+            .inner_join(rooms::table)
+            .select((RoomSnapshot::as_select(), rooms::node_id))
+            .load(conn)
+            .await?)
+    }
+
+    /// Inserts the snapshot or replaces the previous one of the room.
+    pub async fn save_snapshot(self, conn: &mut DbConn) -> MyResult<usize> {
+        use crate::db::schema::room_snapshots::dsl::*;
+
+        Ok(diesel::insert_into(room_snapshots)
+            .values(&self)
+            .on_conflict(room_id)
+            .do_update()
+            .set((state.eq(&self.state), saved_at.eq(self.saved_at)))
+            .execute(conn)
+            .await?)
+    }
+
+    pub async fn delete_snapshot(conn: &mut DbConn, id_room: Uuid) -> MyResult<usize> {
+        use crate::db::schema::room_snapshots::dsl::*;
+        Ok(diesel::delete(room_snapshots.filter(room_id.eq(id_room))).execute(conn).await?)
+    }
+}
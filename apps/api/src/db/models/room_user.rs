@@ -5,7 +5,10 @@ use uuid::Uuid;
 
 use crate::{
     app::types::{DbConn, MyResult},
-    db::{custom_types::Leagues, schema::room_users},
+    db::{
+        custom_types::{Leagues, LeaveReasons},
+        schema::room_users,
+    },
 };
 #[derive(Queryable, Selectable, Insertable, Debug, AsChangeset)]
 #[diesel(table_name = room_users)]
@@ -14,8 +17,12 @@ pub struct RoomUser {
     pub room_id: Uuid,
     pub user_id: Uuid,
     pub joined_at: NaiveDateTime,
-    pub left_at: NaiveDateTime,
+    /// Set with `leave_reason`, cleared when the player comes back
+    pub left_at: Option<NaiveDateTime>,
     pub league: Leagues,
+    pub team: Option<i16>,
+    pub leg: Option<i16>,
+    pub leave_reason: Option<LeaveReasons>,
 }
 
 impl RoomUser {
@@ -36,4 +43,48 @@ impl RoomUser {
         use crate::db::schema::room_users::dsl::*;
         Ok(diesel::update(room_users.filter(id.eq(self.id))).set(self).get_result(conn).await?)
     }
+
+    /// Final team makeup, written when the race starts and idle players became spectators.
+    pub async fn set_team_and_leg(
+        conn: &mut DbConn,
+        id_room_user: Uuid,
+        new_team: Option<i16>,
+        new_leg: Option<i16>,
+    ) -> MyResult<RoomUser> {
+        use crate::db::schema::room_users::dsl::*;
+        Ok(diesel::update(room_users.filter(id.eq(id_room_user)))
+            .set((team.eq(new_team), leg.eq(new_leg)))
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Records why the player is out, `None` for both when they rejoined.
+    pub async fn set_left(
+        conn: &mut DbConn,
+        id_room_user: Uuid,
+        at: Option<NaiveDateTime>,
+        reason: Option<LeaveReasons>,
+    ) -> MyResult<()> {
+        use crate::db::schema::room_users::dsl::*;
+        diesel::update(room_users.filter(id.eq(id_room_user)))
+            .set((left_at.eq(at), leave_reason.eq(reason)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Gives everyone still in the room the same way out, for rooms closed without them.
+    pub async fn close_remaining(
+        conn: &mut DbConn,
+        id_room: Uuid,
+        at: NaiveDateTime,
+        reason: LeaveReasons,
+    ) -> MyResult<()> {
+        use crate::db::schema::room_users::dsl::*;
+        diesel::update(room_users.filter(room_id.eq(id_room)).filter(leave_reason.is_null()))
+            .set((left_at.eq(at), leave_reason.eq(reason)))
+            .execute(conn)
+            .await?;
+        Ok(())
+    }
 }
@@ -1,11 +1,14 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncConnection, RunQueryDsl, scoped_futures::ScopedFutureExt};
 use ipnetwork::IpNetwork;
 use uuid::Uuid;
 
 use crate::{
-    app::types::{DbConn, MyResult},
+    app::{
+        error::MyError,
+        types::{DbConn, MyResult},
+    },
     db::schema::sessions,
 };
 #[derive(Queryable, Selectable, Insertable, Debug)]
@@ -13,7 +16,7 @@ use crate::{
 pub struct Session {
     pub id: Uuid,
     pub user_id: Uuid,
-    pub token: String,
+    pub refresh_token_hash: String,
     pub expires_at: NaiveDateTime,
     pub created_at: NaiveDateTime,
     pub user_agent: String,
@@ -34,6 +37,169 @@ impl Session {
         Ok(result)
     }
 
+    /// Not expired yet, newest first
+    pub async fn get_active_sessions(
+        conn: &mut DbConn,
+        target_user_id: Uuid,
+        now: NaiveDateTime,
+    ) -> MyResult<Vec<Session>> {
+        use crate::db::schema::sessions::dsl::*;
+
+        let result = sessions
+            .filter(user_id.eq(target_user_id))
+            .filter(expires_at.gt(now))
+            .order(created_at.desc())
+            .select(Session::as_select())
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Only deletes a session of the given user, false if there was none
+    pub async fn delete_session(
+        conn: &mut DbConn,
+        target_user_id: Uuid,
+        sid: Uuid,
+    ) -> MyResult<bool> {
+        use crate::db::schema::sessions::dsl::*;
+
+        let deleted =
+            diesel::delete(sessions.filter(id.eq(sid)).filter(user_id.eq(target_user_id)))
+                .execute(conn)
+                .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Swaps in the next refresh token and remembers the old one along with `new_token`, for
+    /// concurrent refreshes. False if `old_hash` is not the current one.
+    pub async fn rotate_refresh_token(
+        conn: &mut DbConn,
+        sid: Uuid,
+        old_hash: &str,
+        new_token: &str,
+        new_hash: &str,
+        new_expires_at: NaiveDateTime,
+    ) -> MyResult<bool> {
+        use crate::db::schema::rotated_refresh_tokens;
+        use crate::db::schema::sessions::dsl::*;
+
+        conn.transaction::<_, MyError, _>(|conn| {
+            async move {
+                let updated = diesel::update(
+                    sessions.filter(id.eq(sid)).filter(refresh_token_hash.eq(old_hash)),
+                )
+                .set((refresh_token_hash.eq(new_hash), expires_at.eq(new_expires_at)))
+                .execute(conn)
+                .await?;
+
+                if updated == 0 {
+                    return Ok(false);
+                }
+
+                // Only the token just rotated away from may still be refreshing
+                diesel::update(
+                    rotated_refresh_tokens::table
+                        .filter(rotated_refresh_tokens::session_id.eq(sid))
+                        .filter(rotated_refresh_tokens::replaced_by.is_not_null()),
+                )
+                .set(rotated_refresh_tokens::replaced_by.eq(None::<String>))
+                .execute(conn)
+                .await?;
+
+                diesel::insert_into(rotated_refresh_tokens::table)
+                    .values((
+                        rotated_refresh_tokens::token_hash.eq(old_hash),
+                        rotated_refresh_tokens::session_id.eq(sid),
+                        rotated_refresh_tokens::rotated_at.eq(Utc::now().naive_utc()),
+                        rotated_refresh_tokens::replaced_by.eq(new_token),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// The session issued this refresh token before and rotated away from it
+    pub async fn is_rotated_refresh_token(
+        conn: &mut DbConn,
+        sid: Uuid,
+        hash: &str,
+    ) -> MyResult<bool> {
+        use crate::db::schema::rotated_refresh_tokens::dsl::*;
+
+        let rotated = diesel::select(diesel::dsl::exists(
+            rotated_refresh_tokens.filter(session_id.eq(sid)).filter(token_hash.eq(hash)),
+        ))
+        .get_result(conn)
+        .await?;
+
+        Ok(rotated)
+    }
+
+    /// Token that replaced `hash` if the rotation happened after `since`
+    pub async fn get_refresh_token_successor(
+        conn: &mut DbConn,
+        sid: Uuid,
+        hash: &str,
+        since: NaiveDateTime,
+    ) -> MyResult<Option<String>> {
+        use crate::db::schema::rotated_refresh_tokens::dsl::*;
+
+        let successor = rotated_refresh_tokens
+            .filter(session_id.eq(sid))
+            .filter(token_hash.eq(hash))
+            .filter(rotated_at.gt(since))
+            .select(replaced_by)
+            .first::<Option<String>>(conn)
+            .await
+            .optional()?;
+
+        Ok(successor.flatten())
+    }
+
+    /// Clears the successors of tokens rotated before `before`, returns how many
+    pub async fn forget_refresh_token_successors(
+        conn: &mut DbConn,
+        before: NaiveDateTime,
+    ) -> MyResult<usize> {
+        use crate::db::schema::rotated_refresh_tokens::dsl::*;
+
+        Ok(diesel::update(rotated_refresh_tokens.filter(rotated_at.le(before)))
+            .filter(replaced_by.is_not_null())
+            .set(replaced_by.eq(None::<String>))
+            .execute(conn)
+            .await?)
+    }
+
+    /// Every session of the user except `keep`, returns how many were deleted
+    pub async fn delete_user_sessions(
+        conn: &mut DbConn,
+        target_user_id: Uuid,
+        keep: Option<Uuid>,
+    ) -> MyResult<usize> {
+        use crate::db::schema::sessions::dsl::*;
+
+        let query = diesel::delete(sessions).filter(user_id.eq(target_user_id)).into_boxed();
+        let query = match keep {
+            Some(sid) => query.filter(id.ne(sid)),
+            None => query,
+        };
+
+        Ok(query.execute(conn).await?)
+    }
+
+    pub async fn delete_expired_sessions(conn: &mut DbConn, now: NaiveDateTime) -> MyResult<usize> {
+        use crate::db::schema::sessions::dsl::*;
+
+        Ok(diesel::delete(sessions.filter(expires_at.le(now))).execute(conn).await?)
+    }
+
     pub async fn insert_session(self, conn: &mut DbConn) -> MyResult<Session> {
         use crate::db::schema::sessions::dsl::*;
 
@@ -0,0 +1,250 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::RunQueryDsl;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::{
+        custom_types::{MatchStatuses, TournamentFormats, TournamentStatuses},
+        schema::{tournament_matches, tournament_participants, tournaments},
+    },
+};
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Clone, utoipa::ToSchema,
+)]
+#[diesel(table_name = tournaments)]
+#[diesel(treat_none_as_null = true)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    pub format: TournamentFormats,
+    pub status: TournamentStatuses,
+    pub dictionary_id: Uuid,
+    pub rounds: i32,
+    pub current_round: i32,
+    pub created_by: Uuid,
+    pub winner_id: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Clone, utoipa::ToSchema,
+)]
+#[diesel(table_name = tournament_participants)]
+pub struct TournamentParticipant {
+    pub id: Uuid,
+    pub tournament_id: Uuid,
+    pub user_id: Uuid,
+    pub seed: Option<i32>,
+    pub score: i32,
+    pub eliminated: bool,
+    pub registered_at: NaiveDateTime,
+}
+
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Debug, Serialize, Clone, utoipa::ToSchema,
+)]
+#[diesel(table_name = tournament_matches)]
+#[diesel(treat_none_as_null = true)]
+pub struct TournamentMatch {
+    pub id: Uuid,
+    pub tournament_id: Uuid,
+    pub round: i32,
+    pub position: i32,
+    pub player_one_id: Uuid,
+    pub player_two_id: Option<Uuid>,
+    pub room_id: Option<Uuid>,
+    pub winner_id: Option<Uuid>,
+    pub status: MatchStatuses,
+    pub created_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+impl Tournament {
+    pub async fn insert_tournament(self, conn: &mut DbConn) -> MyResult<Tournament> {
+        use crate::db::schema::tournaments::dsl::*;
+
+        Ok(diesel::insert_into(tournaments).values(self).get_result(conn).await?)
+    }
+
+    pub async fn get_tournaments(conn: &mut DbConn) -> MyResult<Vec<Tournament>> {
+        use crate::db::schema::tournaments::dsl::*;
+
+        let result =
+            tournaments.order(created_at.desc()).select(Tournament::as_select()).load(conn).await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_tournament_by_id(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Option<Tournament>> {
+        use crate::db::schema::tournaments::dsl::*;
+
+        let result = tournaments
+            .filter(id.eq(id_tournament))
+            .select(Tournament::as_select())
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
+    /// Locks the tournament until the end of the transaction, so rounds advance only once.
+    pub async fn get_tournament_for_update(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Option<Tournament>> {
+        use crate::db::schema::tournaments::dsl::*;
+
+        let result = tournaments
+            .filter(id.eq(id_tournament))
+            .select(Tournament::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
+    pub async fn modify_tournament(self, conn: &mut DbConn) -> MyResult<Tournament> {
+        use crate::db::schema::tournaments::dsl::*;
+
+        Ok(diesel::update(tournaments.filter(id.eq(self.id))).set(self).get_result(conn).await?)
+    }
+}
+
+impl TournamentParticipant {
+    /// Returns `false` if the user is already registered.
+    pub async fn insert_participant(self, conn: &mut DbConn) -> MyResult<bool> {
+        use crate::db::schema::tournament_participants::dsl::*;
+
+        let inserted = diesel::insert_into(tournament_participants)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(inserted > 0)
+    }
+
+    pub async fn get_participants(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Vec<(TournamentParticipant, String)>> {
+        use crate::db::schema::tournament_participants::dsl::*;
+        use crate::db::schema::users;
+
+        let result = tournament_participants
+            .inner_join(users::table)
+            .filter(tournament_id.eq(id_tournament))
+            .order((seed.asc(), registered_at.asc()))
+            .select((TournamentParticipant::as_select(), users::username))
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_participants_with_xp(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Vec<(TournamentParticipant, i64)>> {
+        use crate::db::schema::tournament_participants::dsl::*;
+        use crate::db::schema::users;
+
+        let result = tournament_participants
+            .inner_join(users::table)
+            .filter(tournament_id.eq(id_tournament))
+            .select((TournamentParticipant::as_select(), users::xp))
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn modify_participant(self, conn: &mut DbConn) -> MyResult<TournamentParticipant> {
+        use crate::db::schema::tournament_participants::dsl::*;
+
+        Ok(diesel::update(tournament_participants.filter(id.eq(self.id)))
+            .set(self)
+            .get_result(conn)
+            .await?)
+    }
+}
+
+impl TournamentMatch {
+    pub async fn insert_match(self, conn: &mut DbConn) -> MyResult<TournamentMatch> {
+        use crate::db::schema::tournament_matches::dsl::*;
+
+        Ok(diesel::insert_into(tournament_matches).values(self).get_result(conn).await?)
+    }
+
+    pub async fn get_match_by_id(
+        conn: &mut DbConn,
+        id_match: Uuid,
+    ) -> MyResult<Option<TournamentMatch>> {
+        use crate::db::schema::tournament_matches::dsl::*;
+
+        let result = tournament_matches
+            .filter(id.eq(id_match))
+            .select(TournamentMatch::as_select())
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
+    pub async fn get_matches(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Vec<TournamentMatch>> {
+        use crate::db::schema::tournament_matches::dsl::*;
+
+        let result = tournament_matches
+            .filter(tournament_id.eq(id_tournament))
+            .order((round.asc(), position.asc()))
+            .select(TournamentMatch::as_select())
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    /// Matches that still wait for a room to be created.
+    pub async fn get_matches_without_room(
+        conn: &mut DbConn,
+        id_tournament: Uuid,
+    ) -> MyResult<Vec<TournamentMatch>> {
+        use crate::db::schema::tournament_matches::dsl::*;
+
+        let result = tournament_matches
+            .filter(tournament_id.eq(id_tournament))
+            .filter(status.eq(MatchStatuses::Pending))
+            .filter(room_id.is_null())
+            .order((round.asc(), position.asc()))
+            .select(TournamentMatch::as_select())
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+
+    pub async fn modify_match(self, conn: &mut DbConn) -> MyResult<TournamentMatch> {
+        use crate::db::schema::tournament_matches::dsl::*;
+
+        Ok(diesel::update(tournament_matches.filter(id.eq(self.id)))
+            .set(self)
+            .get_result(conn)
+            .await?)
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
 use diesel_async::RunQueryDsl;
 use uuid::Uuid;
@@ -17,6 +17,12 @@ pub struct User {
     pub password_hash: String,
     pub created_at: NaiveDateTime,
     pub role: UserRoles,
+    pub xp: i64,
+    pub level: i32,
+    pub streak_days: i32,
+    pub best_streak_days: i32,
+    pub last_active_on: Option<NaiveDate>,
+    pub chat_muted_until: Option<NaiveDateTime>,
 }
 
 impl User {
@@ -29,6 +35,21 @@ impl User {
         Ok(result)
     }
 
+    /// Locks the row until the end of the transaction, for read-modify-write of counters.
+    pub async fn get_user_for_update(conn: &mut DbConn, id_user: Uuid) -> MyResult<Option<User>> {
+        use crate::db::schema::users::dsl::*;
+
+        let result = users
+            .filter(id.eq(id_user))
+            .select(User::as_select())
+            .for_update()
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(result)
+    }
+
     pub async fn get_user_by_email(conn: &mut DbConn, user_email: &str) -> MyResult<Option<User>> {
         use crate::db::schema::users::dsl::*;
 
@@ -64,4 +85,58 @@ impl User {
 
         Ok(diesel::update(users.filter(id.eq(self.id))).set(self).get_result(conn).await?)
     }
+
+    pub async fn set_role(conn: &mut DbConn, id_user: Uuid, new_role: UserRoles) -> MyResult<User> {
+        use crate::db::schema::users::dsl::*;
+
+        Ok(diesel::update(users.filter(id.eq(id_user)))
+            .set(role.eq(new_role))
+            .get_result(conn)
+            .await?)
+    }
+
+    /// Only the progression columns, `None` clears `last_active_on` unlike in `update_user`.
+    pub async fn set_progression(self, conn: &mut DbConn) -> MyResult<User> {
+        use crate::db::schema::users::dsl::*;
+
+        Ok(diesel::update(users.filter(id.eq(self.id)))
+            .set((
+                xp.eq(self.xp),
+                level.eq(self.level),
+                streak_days.eq(self.streak_days),
+                best_streak_days.eq(self.best_streak_days),
+                last_active_on.eq(self.last_active_on),
+            ))
+            .get_result(conn)
+            .await?)
+    }
+
+    pub async fn set_password_hash(
+        conn: &mut DbConn,
+        id_user: Uuid,
+        new_password_hash: String,
+    ) -> MyResult<()> {
+        use crate::db::schema::users::dsl::*;
+
+        diesel::update(users.filter(id.eq(id_user)))
+            .set(password_hash.eq(new_password_hash))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// `None` lifts the mute, the changeset of `update_user` would skip it.
+    pub async fn set_chat_muted_until(
+        conn: &mut DbConn,
+        id_user: Uuid,
+        until: Option<NaiveDateTime>,
+    ) -> MyResult<User> {
+        use crate::db::schema::users::dsl::*;
+
+        Ok(diesel::update(users.filter(id.eq(id_user)))
+            .set(chat_muted_until.eq(until))
+            .get_result(conn)
+            .await?)
+    }
 }
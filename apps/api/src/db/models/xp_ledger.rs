@@ -0,0 +1,92 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    deserialize::FromSql,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{Output, ToSql},
+    sql_types::Jsonb,
+};
+use diesel_async::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    app::types::{DbConn, MyResult},
+    db::schema::xp_ledger,
+};
+
+/// Inputs the XP amount was computed from, kept to audit or recompute the ledger.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, diesel::AsExpression, diesel::FromSqlRow, utoipa::ToSchema,
+)]
+#[diesel[sql_type = diesel::pg::sql_types::Jsonb]]
+pub struct XpBreakdown {
+    pub text_length: usize,
+    pub accuracy: f32,
+    pub placement: i16,
+    pub players: usize,
+    pub length_xp: i32,
+    pub accuracy_multiplier: f32,
+    pub placement_bonus: i32,
+}
+
+impl FromSql<Jsonb, Pg> for XpBreakdown {
+    fn from_sql(bytes: PgValue<'_>) -> diesel::deserialize::Result<Self> {
+        let value = <serde_json::Value as FromSql<Jsonb, Pg>>::from_sql(bytes)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+impl ToSql<Jsonb, Pg> for XpBreakdown {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> diesel::serialize::Result {
+        let value = serde_json::to_value(self)?;
+        <serde_json::Value as ToSql<Jsonb, Pg>>::to_sql(&value, &mut out.reborrow())
+    }
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Serialize, Clone, utoipa::ToSchema)]
+#[diesel(table_name = xp_ledger)]
+pub struct XpLedgerEntry {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub result_id: Option<Uuid>,
+    pub amount: i32,
+    pub breakdown: XpBreakdown,
+    pub created_at: NaiveDateTime,
+}
+
+impl XpLedgerEntry {
+    pub async fn insert_entry(self, conn: &mut DbConn) -> MyResult<XpLedgerEntry> {
+        use crate::db::schema::xp_ledger::dsl::*;
+
+        Ok(diesel::insert_into(xp_ledger).values(self).get_result(conn).await?)
+    }
+
+    pub async fn get_total_xp_by_user_id(conn: &mut DbConn, id_user: Uuid) -> MyResult<i64> {
+        use crate::db::schema::xp_ledger::dsl::*;
+
+        let result = xp_ledger
+            .filter(user_id.eq(id_user))
+            .select(diesel::dsl::sum(amount))
+            .first::<Option<i64>>(conn)
+            .await?;
+
+        Ok(result.unwrap_or(0))
+    }
+
+    pub async fn get_entry_dates_by_user_id(
+        conn: &mut DbConn,
+        id_user: Uuid,
+    ) -> MyResult<Vec<NaiveDateTime>> {
+        use crate::db::schema::xp_ledger::dsl::*;
+
+        let result = xp_ledger
+            .filter(user_id.eq(id_user))
+            .order(created_at.asc())
+            .select(created_at)
+            .load(conn)
+            .await?;
+
+        Ok(result)
+    }
+}
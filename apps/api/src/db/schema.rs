@@ -5,15 +5,92 @@ pub mod sql_types {
     #[diesel(postgres_type(name = "leagues"))]
     pub struct Leagues;
 
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "leave_reasons"))]
+    pub struct LeaveReasons;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "match_statuses"))]
+    pub struct MatchStatuses;
+
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "review_text_status"))]
     pub struct ReviewTextStatus;
 
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "room_kinds"))]
+    pub struct RoomKinds;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "room_modes"))]
+    pub struct RoomModes;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "room_statuses"))]
+    pub struct RoomStatuses;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tournament_formats"))]
+    pub struct TournamentFormats;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tournament_statuses"))]
+    pub struct TournamentStatuses;
+
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "user_roles"))]
     pub struct UserRoles;
 }
 
+diesel::table! {
+    achievements (id) {
+        id -> Uuid,
+        #[max_length = 64]
+        code -> Varchar,
+        name -> Varchar,
+        description -> Text,
+        rule -> Jsonb,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    cluster_mailbox (id) {
+        id -> Uuid,
+        payload -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    cluster_nodes (id) {
+        #[max_length = 64]
+        id -> Varchar,
+        heartbeat_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    daily_challenge_attempts (id) {
+        id -> Uuid,
+        challenge_id -> Uuid,
+        user_id -> Uuid,
+        result_id -> Nullable<Uuid>,
+        ranked -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    daily_challenges (id) {
+        id -> Uuid,
+        challenge_date -> Date,
+        text_id -> Uuid,
+        chosen_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     dictionaries (id) {
         id -> Uuid,
@@ -23,6 +100,17 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    password_resets (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::ReviewTextStatus;
@@ -51,30 +139,64 @@ diesel::table! {
         cpm -> Float4,
         stats -> Jsonb,
         room_user_id -> Uuid,
+        placement -> Nullable<Int2>,
+    }
+}
+
+diesel::table! {
+    room_snapshots (room_id) {
+        room_id -> Uuid,
+        state -> Jsonb,
+        saved_at -> Timestamp,
     }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::Leagues;
+    use super::sql_types::LeaveReasons;
 
     room_users (id) {
         id -> Uuid,
         room_id -> Uuid,
         user_id -> Uuid,
         joined_at -> Timestamp,
-        left_at -> Timestamp,
+        left_at -> Nullable<Timestamp>,
         league -> Leagues,
+        team -> Nullable<Int2>,
+        leg -> Nullable<Int2>,
+        leave_reason -> Nullable<LeaveReasons>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::RoomKinds;
+    use super::sql_types::RoomModes;
+    use super::sql_types::RoomStatuses;
+
     rooms (id) {
         id -> Uuid,
         text_id -> Uuid,
         created_at -> Timestamp,
-        started_at -> Timestamp,
-        ended_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        ended_at -> Nullable<Timestamp>,
+        kind -> RoomKinds,
+        mode -> RoomModes,
+        series_id -> Uuid,
+        previous_room_id -> Nullable<Uuid>,
+        #[max_length = 64]
+        node_id -> Nullable<Varchar>,
+        status -> RoomStatuses,
+    }
+}
+
+diesel::table! {
+    rotated_refresh_tokens (token_hash) {
+        token_hash -> Varchar,
+        session_id -> Uuid,
+        rotated_at -> Timestamp,
+        replaced_by -> Nullable<Varchar>,
     }
 }
 
@@ -82,7 +204,7 @@ diesel::table! {
     sessions (id) {
         id -> Uuid,
         user_id -> Uuid,
-        token -> Varchar,
+        refresh_token_hash -> Varchar,
         expires_at -> Timestamp,
         created_at -> Timestamp,
         user_agent -> Text,
@@ -101,6 +223,68 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MatchStatuses;
+
+    tournament_matches (id) {
+        id -> Uuid,
+        tournament_id -> Uuid,
+        round -> Int4,
+        position -> Int4,
+        player_one_id -> Uuid,
+        player_two_id -> Nullable<Uuid>,
+        room_id -> Nullable<Uuid>,
+        winner_id -> Nullable<Uuid>,
+        status -> MatchStatuses,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    tournament_participants (id) {
+        id -> Uuid,
+        tournament_id -> Uuid,
+        user_id -> Uuid,
+        seed -> Nullable<Int4>,
+        score -> Int4,
+        eliminated -> Bool,
+        registered_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TournamentFormats;
+    use super::sql_types::TournamentStatuses;
+
+    tournaments (id) {
+        id -> Uuid,
+        name -> Varchar,
+        format -> TournamentFormats,
+        status -> TournamentStatuses,
+        dictionary_id -> Uuid,
+        rounds -> Int4,
+        current_round -> Int4,
+        created_by -> Uuid,
+        winner_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        ended_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_achievements (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        achievement_id -> Uuid,
+        result_id -> Nullable<Uuid>,
+        unlocked_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::UserRoles;
@@ -114,27 +298,75 @@ diesel::table! {
         password_hash -> Varchar,
         created_at -> Timestamp,
         role -> UserRoles,
+        xp -> Int8,
+        level -> Int4,
+        streak_days -> Int4,
+        best_streak_days -> Int4,
+        last_active_on -> Nullable<Date>,
+        chat_muted_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    xp_ledger (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        result_id -> Nullable<Uuid>,
+        amount -> Int4,
+        breakdown -> Jsonb,
+        created_at -> Timestamp,
     }
 }
 
+diesel::joinable!(daily_challenge_attempts -> daily_challenges (challenge_id));
+diesel::joinable!(daily_challenge_attempts -> results (result_id));
+diesel::joinable!(daily_challenge_attempts -> users (user_id));
+diesel::joinable!(daily_challenges -> texts (text_id));
+diesel::joinable!(daily_challenges -> users (chosen_by));
 diesel::joinable!(dictionaries -> users (user_id));
+diesel::joinable!(password_resets -> users (user_id));
 diesel::joinable!(pending_texts -> dictionaries (dictionary_id));
 diesel::joinable!(results -> room_users (room_user_id));
+diesel::joinable!(room_snapshots -> rooms (room_id));
 diesel::joinable!(room_users -> rooms (room_id));
 diesel::joinable!(room_users -> users (user_id));
 diesel::joinable!(rooms -> texts (text_id));
+diesel::joinable!(rotated_refresh_tokens -> sessions (session_id));
 diesel::joinable!(sessions -> users (user_id));
 diesel::joinable!(texts -> dictionaries (dictionary_id));
 diesel::joinable!(texts -> users (author_id));
+diesel::joinable!(tournament_matches -> rooms (room_id));
+diesel::joinable!(tournament_matches -> tournaments (tournament_id));
+diesel::joinable!(tournament_participants -> tournaments (tournament_id));
+diesel::joinable!(tournament_participants -> users (user_id));
+diesel::joinable!(tournaments -> dictionaries (dictionary_id));
+diesel::joinable!(user_achievements -> achievements (achievement_id));
+diesel::joinable!(user_achievements -> results (result_id));
+diesel::joinable!(user_achievements -> users (user_id));
+diesel::joinable!(xp_ledger -> results (result_id));
+diesel::joinable!(xp_ledger -> users (user_id));
 diesel::joinable!(pending_texts -> users (author_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    achievements,
+    cluster_mailbox,
+    cluster_nodes,
+    daily_challenge_attempts,
+    daily_challenges,
     dictionaries,
+    password_resets,
     pending_texts,
     results,
+    room_snapshots,
     room_users,
     rooms,
+    rotated_refresh_tokens,
     sessions,
     texts,
+    tournament_matches,
+    tournament_participants,
+    tournaments,
+    user_achievements,
     users,
+    xp_ledger,
 );
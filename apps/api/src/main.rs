@@ -1,3 +1,6 @@
+// `diesel::dsl` exports `avg` through two globs, newer compilers flag importing it
+#![allow(ambiguous_glob_imports)]
+
 mod app;
 mod db;
 mod routes;
@@ -13,15 +16,34 @@ async fn main() {
     pretty_env_logger::init_timed();
 
     let config = app::config::load_config();
+    // Fail on a bad key at startup rather than on the first login
+    std::sync::LazyLock::force(&app::auth::KEYS);
 
     let pool = db::init::init_pool(config.database_url.clone());
 
-    let rooms_manager = app::room::RoomsManager::new(pool.clone());
-
-    let state = AppState { pool, rooms_manager, config };
+    let chat_filter = app::chat::WordListFilter::new(&config.chat_banned_words);
+    let cluster = app::cluster::Cluster::new(
+        pool.clone(),
+        config.database_url.clone(),
+        config.node_id.clone(),
+    );
+    let rooms_manager =
+        app::room::RoomsManager::new(pool.clone(), cluster).with_chat_filter(chat_filter);
+    rooms_manager.start().await;
+    app::auth::spawn_session_cleanup(pool.clone());
+
+    let port = config.port;
+    let mailer = app::mailer::from_config(&config);
+
+    let state = AppState {
+        pool,
+        rooms_manager,
+        config,
+        ws_limits: app::rate_limit::WsRateLimits::default(),
+        mailer,
+    };
 
     let ip = [127, 0, 0, 1];
-    let port = 9999;
     let addr = SocketAddr::from((ip, port));
 
     let router = app::router::build_router(state.clone());
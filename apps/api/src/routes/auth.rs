@@ -0,0 +1,15 @@
+use axum::Json;
+use jsonwebtoken::jwk::JwkSet;
+
+use crate::app::auth::KEYS;
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "Public keys verifying the access tokens, matched by `kid`", body = Object)
+    )
+)]
+pub async fn jwks() -> Json<JwkSet> {
+    Json(KEYS.jwks.clone())
+}
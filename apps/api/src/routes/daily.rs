@@ -0,0 +1,263 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+};
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    app::{
+        auth::Claims,
+        error::MyError,
+        room::{RoomKind, RoomOptions},
+        types::{DbConn, MyResult},
+    },
+    db::{
+        custom_types::UserRoles,
+        models::{
+            daily_challenge::{
+                DailyArchiveEntry, DailyChallenge, DailyChallengeAttempt, DailyTopUser,
+            },
+            dictionary::Dictionary,
+            text::Text,
+            user::User,
+        },
+    },
+};
+
+/// Returns the challenge of `date`, picking a text from the default dictionary if no moderator
+/// did it beforehand. The pick depends only on the date, so every instance agrees on it.
+async fn get_or_pick_challenge(
+    conn: &mut DbConn,
+    date: NaiveDate,
+    default_dictionary_id: Uuid,
+) -> MyResult<(DailyChallenge, Text)> {
+    if let Some(challenge) = DailyChallenge::get_challenge_by_date(conn, date).await? {
+        return Ok(challenge);
+    }
+
+    let dictionary = Dictionary::get_dictionary_by_id(conn, default_dictionary_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    let count = dictionary.get_text_count_in_dictionary(conn).await?;
+    if count == 0 {
+        return Err(MyError::NotFound);
+    }
+
+    let index = (date - NaiveDate::default()).num_days().rem_euclid(count);
+    let text =
+        dictionary.get_text_by_index_in_dictionary(conn, index).await?.ok_or(MyError::NotFound)?;
+
+    let challenge = DailyChallenge {
+        id: Uuid::new_v4(),
+        challenge_date: date,
+        text_id: text.id,
+        chosen_by: None,
+        created_at: Utc::now().naive_utc(),
+    };
+
+    // Another request may have picked it in the meantime, the stored one wins
+    challenge.insert_challenge_if_missing(conn).await?;
+
+    DailyChallenge::get_challenge_by_date(conn, date).await?.ok_or(MyError::InternalError)
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DailyChallengeResponse {
+    challenge: DailyChallenge,
+    text: Text,
+    /// Next attempt will be an unranked retry
+    ranked_attempt_used: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/daily",
+    responses(
+        (status = 200, description = "Today's challenge", body = DailyChallengeResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn get_daily_challenge(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> MyResult<Json<DailyChallengeResponse>> {
+    let mut conn = state.db().await?;
+    let today = Utc::now().date_naive();
+
+    let (challenge, text) =
+        get_or_pick_challenge(&mut conn, today, state.config.default_dictionary_id).await?;
+    let ranked_attempt_used =
+        DailyChallengeAttempt::has_ranked_attempt(&mut conn, challenge.id, claims.sub).await?;
+
+    Ok(Json(DailyChallengeResponse { challenge, text, ranked_attempt_used }))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct PlayDailyChallengeResponse {
+    room_id: Uuid,
+    ranked: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/daily/play",
+    responses(
+        (status = 200, description = "Room with today's text created", body = PlayDailyChallengeResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn play_daily_challenge(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> MyResult<Json<PlayDailyChallengeResponse>> {
+    let mut conn = state.db().await?;
+    let today = Utc::now().date_naive();
+
+    let (challenge, text) =
+        get_or_pick_challenge(&mut conn, today, state.config.default_dictionary_id).await?;
+
+    let dictionary = Dictionary::get_dictionary_by_id(&mut conn, text.dictionary_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    // The ranked slot is spent by starting the run, abandoning it doesn't give it back
+    let attempt = DailyChallengeAttempt {
+        id: Uuid::new_v4(),
+        challenge_id: challenge.id,
+        user_id: claims.sub,
+        result_id: None,
+        ranked: true,
+        created_at: Utc::now().naive_utc(),
+    };
+    let attempt_id = attempt.id;
+    let ranked = attempt.insert_attempt(&mut conn).await?;
+
+    let kind = RoomKind::Daily {
+        challenge_id: challenge.id,
+        user_id: claims.sub,
+        ranked_attempt: ranked.then_some(attempt_id),
+    };
+    let room_id = state.rooms_manager.create_room(text, dictionary, RoomOptions::new(kind)).await?;
+
+    Ok(Json(PlayDailyChallengeResponse { room_id, ranked }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct SetDailyChallengeRequest {
+    text_id: Uuid,
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/daily/{date}",
+    request_body = SetDailyChallengeRequest,
+    responses(
+        (status = 200, description = "Success", body = DailyChallenge),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn set_daily_challenge(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(date): Path<NaiveDate>,
+    Json(input): Json<SetDailyChallengeRequest>,
+) -> MyResult<Json<DailyChallenge>> {
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    if user.role != UserRoles::Creator && user.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    // Ranked attempts of past and current days were made on the stored text
+    if date <= Utc::now().date_naive() {
+        return Err(MyError::Validation("Only future challenges can be changed".to_string()));
+    }
+
+    let text = Text::get_text_by_id(&mut conn, input.text_id).await?.ok_or(MyError::NotFound)?;
+
+    let challenge = DailyChallenge {
+        id: Uuid::new_v4(),
+        challenge_date: date,
+        text_id: text.id,
+        chosen_by: Some(user.id),
+        created_at: Utc::now().naive_utc(),
+    };
+
+    let challenge = challenge.upsert_challenge(&mut conn).await?;
+
+    Ok(Json(challenge))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DailyLeaderboardResponse {
+    challenge: DailyChallenge,
+    users: Vec<DailyTopUser>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/daily/{date}/leaderboard",
+    responses(
+        (status = 200, description = "Ranked attempts of the day", body = DailyLeaderboardResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn get_daily_leaderboard(
+    _: Claims,
+    State(state): State<AppState>,
+    Path(date): Path<NaiveDate>,
+) -> MyResult<Json<DailyLeaderboardResponse>> {
+    let mut conn = state.db().await?;
+
+    let (challenge, _) =
+        DailyChallenge::get_challenge_by_date(&mut conn, date).await?.ok_or(MyError::NotFound)?;
+    let users = DailyChallengeAttempt::get_leaderboard(&mut conn, challenge.id).await?;
+
+    Ok(Json(DailyLeaderboardResponse { challenge, users }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct ArchiveQuery {
+    #[serde(default)]
+    offset: i64,
+    // Defaults to a month of challenges
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DailyArchiveResponse {
+    list: Vec<DailyArchiveEntry>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/daily/archive",
+    params(ArchiveQuery),
+    responses(
+        (status = 200, description = "Past challenges", body = DailyArchiveResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn get_daily_archive(
+    _: Claims,
+    State(state): State<AppState>,
+    Query(params): Query<ArchiveQuery>,
+) -> MyResult<Json<DailyArchiveResponse>> {
+    let mut conn = state.db().await?;
+
+    let today = Utc::now().date_naive();
+    let limit = params.limit.unwrap_or(30).clamp(1, 100);
+    let offset = params.offset.max(0);
+
+    let list = DailyChallenge::get_archive(&mut conn, today, offset, limit).await?;
+
+    Ok(Json(DailyArchiveResponse { list }))
+}
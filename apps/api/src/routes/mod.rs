@@ -1,6 +1,9 @@
+pub mod auth;
+pub mod daily;
 pub mod dictionaries;
 pub mod leaderboard;
 pub mod rooms;
 pub mod texts;
+pub mod tournaments;
 pub mod user;
 pub mod ws;
@@ -1,35 +1,130 @@
-use std::sync::Arc;
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
 
 use axum::{
     Json,
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
 };
+use futures::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    app::{auth::Claims, error::MyError, room::RoomStats, types::MyResult},
-    db::models::dictionary::Dictionary,
+    app::{
+        auth::Claims,
+        error::MyError,
+        room::{DEFAULT_READY_QUORUM, RoomKind, RoomMode, RoomOptions, RoomStats},
+        room_directory::{LobbyEntry, LobbyEvent},
+        types::MyResult,
+    },
+    db::{
+        custom_types::{Leagues, LeaveReasons, RoomKinds, RoomModes, RoomStatuses},
+        models::{
+            dictionary::Dictionary,
+            result::{Keystroke, Results},
+            room::{Room as RoomModel, SeriesResultRow},
+            room_user::RoomUser,
+            text::Text,
+        },
+    },
 };
 
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct RoomsQuery {
+    dictionary_id: Option<Uuid>,
+    /// Only rooms still in the lobby with a free seat
+    #[serde(default)]
+    joinable: bool,
+    league: Option<Leagues>,
+    mode: Option<RoomModes>,
+    #[serde(default)]
+    offset: i64,
+    // Defaults to 20 rooms
+    limit: Option<i64>,
+}
+
 #[derive(Serialize, utoipa::ToSchema)]
 pub struct GetRoomsResponse {
     rooms: Vec<RoomStats>,
+    /// Rooms matching the filters, across all pages
+    total: usize,
 }
 
 #[utoipa::path(
     get,
     path = "/api/v1/rooms",
+    params(RoomsQuery),
     responses(
-        (status = 200, description = "List of rooms", body=GetRoomsResponse)
+        (status = 200, description = "List of rooms, newest first", body=GetRoomsResponse)
     )
 )]
-pub async fn get_rooms(_: Claims, state: State<AppState>) -> MyResult<Json<GetRoomsResponse>> {
-    let stats = state.rooms_manager.get_stats().await;
+pub async fn get_rooms(
+    _: Claims,
+    Query(params): Query<RoomsQuery>,
+    state: State<AppState>,
+) -> MyResult<Json<GetRoomsResponse>> {
+    let mut rooms: Vec<_> = state
+        .rooms_manager
+        .directory
+        .list()
+        .into_iter()
+        .filter(|room| params.dictionary_id.is_none_or(|id| room.dictionary.id == id))
+        .filter(|room| !params.joinable || room.joinable)
+        .filter(|room| params.league.as_ref().is_none_or(|league| &room.league == league))
+        .filter(|room| params.mode.as_ref().is_none_or(|mode| &room.mode.db_mode() == mode))
+        .collect();
 
-    let res = GetRoomsResponse { rooms: stats };
-    Ok(Json(res))
+    rooms.sort_by_key(|room| Reverse(room.created_at));
+
+    let total = rooms.len();
+    let limit = params.limit.unwrap_or(20).clamp(1, 100) as usize;
+    let offset = params.offset.max(0) as usize;
+
+    let rooms = rooms.into_iter().skip(offset).take(limit).collect();
+
+    Ok(Json(GetRoomsResponse { rooms, total }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/events",
+    responses(
+        (status = 200, description = "Server-Sent Events of room list changes. Resumes after `Last-Event-ID`, otherwise starts with a snapshot", content_type = "text/event-stream", body = LobbyEvent)
+    )
+)]
+pub async fn room_events(
+    _: Claims,
+    headers: HeaderMap,
+    state: State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let manager = state.rooms_manager.clone();
+    let last_event_id = headers.get("last-event-id").and_then(|value| value.to_str().ok());
+
+    let subscription = manager.directory.subscribe(last_event_id);
+
+    let backlog = match subscription.missed {
+        Some(missed) => missed,
+        None => {
+            let rooms = manager.directory.list();
+            let event = Arc::new(LobbyEvent::Snapshot { rooms });
+            vec![LobbyEntry { seq: subscription.seq, event }]
+        },
+    };
+
+    let live = stream::unfold(subscription.events, |mut events| async move {
+        // Lagging behind ends the stream, the client reconnects and resumes from its last event
+        let entry = events.recv().await.ok()?;
+        Some((entry, events))
+    });
+
+    let directory = manager.directory.clone();
+    let stream = stream::iter(backlog).chain(live).map(move |entry| {
+        Event::default().id(directory.event_id(entry.seq)).json_data(&*entry.event)
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
 }
 
 #[derive(Serialize, utoipa::ToSchema)]
@@ -41,28 +136,16 @@ pub struct StartRoomResponse {
     post,
     path = "/api/v1/rooms/{room_id}/start",
     responses(
-        (status = 200, description = "Countdown started", body = StartRoomResponse)
+        (status = 200, description = "Countdown started, everyone connected races", body = StartRoomResponse),
+        (status = 401, description = "Only the host may start the room"),
     )
 )]
 pub async fn start_room(
-    _: Claims,
+    claims: Claims,
     Path(room_id): Path<Uuid>,
     State(state): State<AppState>,
 ) -> MyResult<Json<StartRoomResponse>> {
-    let rooms = state.rooms_manager.rooms.read().await;
-    let room = rooms.get(&room_id).cloned().ok_or(MyError::NotFound)?;
-
-    if room.read().await.started {
-        return Err(MyError::NotFound);
-    }
-
-    drop(rooms);
-    drop(room);
-
-    let state = Arc::new(state);
-    tokio::spawn(async move {
-        state.rooms_manager.start_countdown(room_id).await;
-    });
+    state.rooms_manager.start_countdown(room_id, claims.sub).await?;
 
     Ok(Json(StartRoomResponse { message: "Countdown started".to_string() }))
 }
@@ -72,6 +155,12 @@ pub async fn start_room(
 pub struct CreateRoomRequest {
     /// If dictionary_id is not provided, the default dictionary will be used
     dictionary_id: Option<Uuid>,
+    /// Solo if not provided
+    mode: Option<RoomMode>,
+    /// Percent of players that must be ready to start the countdown, all of them if not provided
+    ready_quorum: Option<u8>,
+    /// Web if not provided
+    league: Option<Leagues>,
 }
 
 #[derive(Serialize, utoipa::ToSchema)]
@@ -92,6 +181,16 @@ pub async fn create_room(
     state: State<AppState>,
     Json(input): Json<CreateRoomRequest>,
 ) -> MyResult<Json<CreateRoomResponse>> {
+    let mode = input.mode.unwrap_or(RoomMode::Solo);
+    if mode != RoomMode::Solo && !(2..=8).contains(&mode.teams()) {
+        return Err(MyError::Validation("Teams count must be between 2 and 8".to_string()));
+    }
+
+    let ready_quorum = input.ready_quorum.unwrap_or(DEFAULT_READY_QUORUM);
+    if !(1..=100).contains(&ready_quorum) {
+        return Err(MyError::Validation("Ready quorum must be between 1 and 100".to_string()));
+    }
+
     let mut conn = state.db().await?;
 
     let dict_id = input.dictionary_id.unwrap_or(state.config.default_dictionary_id);
@@ -104,9 +203,240 @@ pub async fn create_room(
         return Err(MyError::NotFound);
     };
 
-    let room_id = state.rooms_manager.create_room(text, dictionary).await;
+    let options = RoomOptions {
+        kind: RoomKind::Regular,
+        mode,
+        ready_quorum,
+        league: input.league.unwrap_or(Leagues::Web),
+        series_id: None,
+        previous_room_id: None,
+    };
+    let room_id = state.rooms_manager.create_room(text, dictionary, options).await?;
 
     let res = CreateRoomResponse { room_id };
 
     Ok(Json(res))
 }
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SeriesRoom {
+    room_id: Uuid,
+    text_id: Uuid,
+    status: RoomStatuses,
+    started_at: Option<chrono::NaiveDateTime>,
+    results: Vec<SeriesResultRow>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SeriesStanding {
+    username: String,
+    races: usize,
+    wins: usize,
+    average_wpm: f32,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RoomSeriesResponse {
+    series_id: Uuid,
+    rooms: Vec<SeriesRoom>,
+    /// Most wins first, average speed breaks ties
+    standings: Vec<SeriesStanding>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{room_id}/series",
+    responses(
+        (status = 200, description = "Rooms chained by rematches and their results", body = RoomSeriesResponse),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn get_room_series(
+    _: Claims,
+    Path(room_id): Path<Uuid>,
+    state: State<AppState>,
+) -> MyResult<Json<RoomSeriesResponse>> {
+    let mut conn = state.db().await?;
+
+    let room = RoomModel::get_room_by_id(&mut conn, room_id).await?.ok_or(MyError::NotFound)?;
+    let series_id = room.series_id;
+
+    let series_rooms = RoomModel::get_series_rooms(&mut conn, series_id).await?;
+    let mut results = RoomModel::get_series_results(&mut conn, series_id).await?;
+
+    let mut standings: HashMap<String, SeriesStanding> = HashMap::new();
+    for row in &results {
+        let standing = standings.entry(row.username.clone()).or_insert(SeriesStanding {
+            username: row.username.clone(),
+            races: 0,
+            wins: 0,
+            average_wpm: 0.0,
+        });
+
+        standing.average_wpm += row.wpm;
+        standing.races += 1;
+        if row.placement == Some(1) {
+            standing.wins += 1;
+        }
+    }
+
+    let mut standings: Vec<_> = standings
+        .into_values()
+        .map(|s| SeriesStanding { average_wpm: s.average_wpm / s.races as f32, ..s })
+        .collect();
+    standings.sort_by(|a, b| b.wins.cmp(&a.wins).then(b.average_wpm.total_cmp(&a.average_wpm)));
+
+    let rooms = series_rooms
+        .into_iter()
+        .map(|room| {
+            let (room_results, rest) = results.drain(..).partition(|r| r.room_id == room.id);
+            results = rest;
+
+            SeriesRoom {
+                room_id: room.id,
+                text_id: room.text_id,
+                status: room.status,
+                started_at: room.started_at,
+                results: room_results,
+            }
+        })
+        .collect();
+
+    Ok(Json(RoomSeriesResponse { series_id, rooms, standings }))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RoomParticipant {
+    user_id: Uuid,
+    username: String,
+    team: Option<i16>,
+    joined_at: chrono::NaiveDateTime,
+    left_at: Option<chrono::NaiveDateTime>,
+    leave_reason: Option<LeaveReasons>,
+    placement: Option<i16>,
+    wpm: Option<f32>,
+    cpm: Option<f32>,
+    accuracy: Option<f32>,
+    mistakes: Option<i16>,
+    /// Keystrokes of the race, only for players who finished
+    replay_url: Option<String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RoomDetailResponse {
+    room_id: Uuid,
+    status: RoomStatuses,
+    kind: RoomKinds,
+    mode: RoomModes,
+    series_id: Uuid,
+    previous_room_id: Option<Uuid>,
+    created_at: chrono::NaiveDateTime,
+    started_at: Option<chrono::NaiveDateTime>,
+    ended_at: Option<chrono::NaiveDateTime>,
+    text: Text,
+    dictionary: Dictionary,
+    /// By placement, players who didn't finish last
+    participants: Vec<RoomParticipant>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{room_id}",
+    responses(
+        (status = 200, description = "Room with its participants and results", body = RoomDetailResponse),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn get_room(
+    _: Claims,
+    Path(room_id): Path<Uuid>,
+    state: State<AppState>,
+) -> MyResult<Json<RoomDetailResponse>> {
+    let mut conn = state.db().await?;
+
+    let room = RoomModel::get_room_by_id(&mut conn, room_id).await?.ok_or(MyError::NotFound)?;
+    let text = Text::get_text_by_id(&mut conn, room.text_id).await?.ok_or(MyError::NotFound)?;
+    let dictionary = Dictionary::get_dictionary_by_id(&mut conn, text.dictionary_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    let mut participants: Vec<_> = RoomModel::get_room_participants(&mut conn, room_id)
+        .await?
+        .into_iter()
+        .map(|(room_user, username, result)| RoomParticipant {
+            user_id: room_user.user_id,
+            username,
+            team: room_user.team,
+            joined_at: room_user.joined_at,
+            left_at: room_user.left_at,
+            leave_reason: room_user.leave_reason,
+            placement: result.as_ref().and_then(|r| r.placement),
+            wpm: result.as_ref().map(|r| r.wpm),
+            cpm: result.as_ref().map(|r| r.cpm),
+            accuracy: result.as_ref().map(Results::accuracy),
+            mistakes: result.as_ref().map(|r| r.mistakes),
+            replay_url: result
+                .as_ref()
+                .map(|r| format!("/api/v1/rooms/{}/replays/{}", room_id, r.id)),
+        })
+        .collect();
+    participants.sort_by_key(|p| (p.placement.is_none(), p.placement));
+
+    let res = RoomDetailResponse {
+        room_id: room.id,
+        status: room.status,
+        kind: room.kind,
+        mode: room.mode,
+        series_id: room.series_id,
+        previous_room_id: room.previous_room_id,
+        created_at: room.created_at,
+        started_at: room.started_at,
+        ended_at: room.ended_at,
+        text,
+        dictionary,
+        participants,
+    };
+
+    Ok(Json(res))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ReplayResponse {
+    result_id: Uuid,
+    user_id: Uuid,
+    start_time: chrono::NaiveDateTime,
+    end_time: chrono::NaiveDateTime,
+    keystrokes: Vec<Keystroke>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/rooms/{room_id}/replays/{result_id}",
+    responses(
+        (status = 200, description = "Keystrokes of one player's race", body = ReplayResponse),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn get_room_replay(
+    _: Claims,
+    Path((room_id, result_id)): Path<(Uuid, Uuid)>,
+    state: State<AppState>,
+) -> MyResult<Json<ReplayResponse>> {
+    let mut conn = state.db().await?;
+
+    let result = Results::get_result_by_id(&mut conn, result_id).await?.ok_or(MyError::NotFound)?;
+    let room_user = RoomUser::get_room_user_by_id(&mut conn, result.room_user_id)
+        .await?
+        .filter(|room_user| room_user.room_id == room_id)
+        .ok_or(MyError::NotFound)?;
+
+    let res = ReplayResponse {
+        result_id: result.id,
+        user_id: room_user.user_id,
+        start_time: result.start_time,
+        end_time: result.end_time,
+        keystrokes: result.stats.keystrokes,
+    };
+
+    Ok(Json(res))
+}
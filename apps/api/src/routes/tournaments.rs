@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    app::{auth::Claims, error::MyError, tournament, types::MyResult},
+    db::{
+        custom_types::{MatchStatuses, TournamentFormats, TournamentStatuses, UserRoles},
+        models::{
+            dictionary::Dictionary,
+            tournament::{Tournament, TournamentMatch, TournamentParticipant},
+            user::User,
+        },
+    },
+};
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct GetTournamentsResponse {
+    list: Vec<Tournament>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/tournaments",
+    responses(
+        (status = 200, description = "Success", body = GetTournamentsResponse),
+    )
+)]
+pub async fn get_tournaments(state: State<AppState>) -> MyResult<Json<GetTournamentsResponse>> {
+    let mut conn = state.db().await?;
+    let list = Tournament::get_tournaments(&mut conn).await?;
+
+    Ok(Json(GetTournamentsResponse { list }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct CreateTournamentRequest {
+    name: String,
+    format: TournamentFormats,
+    /// If dictionary_id is not provided, the default dictionary will be used
+    dictionary_id: Option<Uuid>,
+    /// Swiss only, defaults to enough rounds to find a single winner
+    rounds: Option<i32>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tournaments",
+    request_body = CreateTournamentRequest,
+    responses(
+        (status = 200, description = "Success", body = Tournament),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn create_tournament(
+    claims: Claims,
+    state: State<AppState>,
+    Json(input): Json<CreateTournamentRequest>,
+) -> MyResult<Json<Tournament>> {
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    if user.role != UserRoles::Creator && user.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    let dict_id = input.dictionary_id.unwrap_or(state.config.default_dictionary_id);
+    let dictionary =
+        Dictionary::get_dictionary_by_id(&mut conn, dict_id).await?.ok_or(MyError::NotFound)?;
+
+    let tournament = Tournament {
+        id: Uuid::new_v4(),
+        name: input.name,
+        format: input.format,
+        status: TournamentStatuses::Registration,
+        dictionary_id: dictionary.id,
+        // Known only after seeding for single elimination
+        rounds: input.rounds.unwrap_or(0).max(0),
+        current_round: 0,
+        created_by: user.id,
+        winner_id: None,
+        created_at: chrono::Utc::now().naive_utc(),
+        started_at: None,
+        ended_at: None,
+    };
+
+    let tournament = tournament.insert_tournament(&mut conn).await?;
+
+    Ok(Json(tournament))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ParticipantInfo {
+    username: String,
+    seed: Option<i32>,
+    score: i32,
+    eliminated: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MatchInfo {
+    id: Uuid,
+    position: i32,
+    player_one: String,
+    /// Empty for a bye
+    player_two: Option<String>,
+    winner: Option<String>,
+    room_id: Option<Uuid>,
+    status: MatchStatuses,
+    finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RoundInfo {
+    round: i32,
+    matches: Vec<MatchInfo>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct TournamentBracketResponse {
+    tournament: Tournament,
+    winner: Option<String>,
+    participants: Vec<ParticipantInfo>,
+    rounds: Vec<RoundInfo>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/tournaments/{tournament_id}",
+    responses(
+        (status = 200, description = "Public bracket", body = TournamentBracketResponse),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn get_tournament_bracket(
+    state: State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+) -> MyResult<Json<TournamentBracketResponse>> {
+    let mut conn = state.db().await?;
+
+    let tournament = Tournament::get_tournament_by_id(&mut conn, tournament_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+    let participants = TournamentParticipant::get_participants(&mut conn, tournament_id).await?;
+    let matches = TournamentMatch::get_matches(&mut conn, tournament_id).await?;
+
+    let usernames: HashMap<Uuid, String> =
+        participants.iter().map(|(p, username)| (p.user_id, username.clone())).collect();
+    let username = |id: Uuid| usernames.get(&id).cloned().unwrap_or_default();
+
+    let mut rounds: Vec<RoundInfo> = vec![];
+    for m in matches {
+        let info = MatchInfo {
+            id: m.id,
+            position: m.position,
+            player_one: username(m.player_one_id),
+            player_two: m.player_two_id.map(username),
+            winner: m.winner_id.map(username),
+            room_id: m.room_id,
+            status: m.status,
+            finished_at: m.finished_at,
+        };
+
+        match rounds.last_mut() {
+            Some(round) if round.round == m.round => round.matches.push(info),
+            _ => rounds.push(RoundInfo { round: m.round, matches: vec![info] }),
+        }
+    }
+
+    let winner = tournament.winner_id.map(username);
+    let participants = participants
+        .into_iter()
+        .map(|(p, username)| ParticipantInfo {
+            username,
+            seed: p.seed,
+            score: p.score,
+            eliminated: p.eliminated,
+        })
+        .collect();
+
+    Ok(Json(TournamentBracketResponse { tournament, winner, participants, rounds }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tournaments/{tournament_id}/register",
+    responses(
+        (status = 200, description = "Registered", body = TournamentParticipant),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn register_in_tournament(
+    claims: Claims,
+    state: State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+) -> MyResult<Json<TournamentParticipant>> {
+    let mut conn = state.db().await?;
+
+    let tournament = Tournament::get_tournament_by_id(&mut conn, tournament_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    if tournament.status != TournamentStatuses::Registration {
+        return Err(MyError::Validation("Registration is closed".to_string()));
+    }
+
+    let participant = TournamentParticipant {
+        id: Uuid::new_v4(),
+        tournament_id,
+        user_id: claims.sub,
+        seed: None,
+        score: 0,
+        eliminated: false,
+        registered_at: chrono::Utc::now().naive_utc(),
+    };
+
+    if !participant.clone().insert_participant(&mut conn).await? {
+        return Err(MyError::Validation("Already registered".to_string()));
+    }
+
+    Ok(Json(participant))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tournaments/{tournament_id}/start",
+    responses(
+        (status = 200, description = "Bracket seeded, first round rooms created", body = Tournament),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn start_tournament(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(tournament_id): Path<Uuid>,
+) -> MyResult<Json<Tournament>> {
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    if user.role != UserRoles::Creator && user.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    let tournament =
+        tournament::start_tournament(&state.rooms_manager, &mut conn, tournament_id).await?;
+
+    Ok(Json(tournament))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct SetMatchWinnerRequest {
+    username: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tournaments/{tournament_id}/matches/{match_id}/winner",
+    request_body = SetMatchWinnerRequest,
+    responses(
+        (status = 200, description = "Success", body = Tournament),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn set_match_winner(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((tournament_id, match_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<SetMatchWinnerRequest>,
+) -> MyResult<Json<Tournament>> {
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    // For matches whose room was abandoned before anyone finished
+    if user.role != UserRoles::Creator && user.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    let tournament_match =
+        TournamentMatch::get_match_by_id(&mut conn, match_id).await?.ok_or(MyError::NotFound)?;
+
+    if tournament_match.tournament_id != tournament_id {
+        return Err(MyError::NotFound);
+    }
+
+    let winner =
+        User::get_user_by_username(&mut conn, &input.username).await?.ok_or(MyError::NotFound)?;
+
+    tournament::report_match_result(&state.rooms_manager, &mut conn, match_id, Some(winner.id))
+        .await?;
+
+    let tournament = Tournament::get_tournament_by_id(&mut conn, tournament_id)
+        .await?
+        .ok_or(MyError::NotFound)?;
+
+    Ok(Json(tournament))
+}
@@ -2,7 +2,7 @@ use std::net::SocketAddr;
 
 use axum::{
     Json,
-    extract::{ConnectInfo, Path, State},
+    extract::{ConnectInfo, Path, Query, State},
 };
 use axum_extra::{
     TypedHeader,
@@ -13,7 +13,7 @@ use axum_extra::{
     headers::UserAgent,
 };
 use chrono::NaiveDateTime;
-use jsonwebtoken::{Header, encode};
+use diesel_async::{AsyncConnection, scoped_futures::ScopedFutureExt};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
@@ -21,13 +21,25 @@ use uuid::Uuid;
 use crate::{
     AppState,
     app::{
-        auth::{COMPANY_NAME, COOKIE_NAME, Claims, KEYS},
+        auth::{
+            ACCESS_TOKEN_TTL, COOKIE_NAME, Claims, REFRESH_COOKIE_NAME, REFRESH_COOKIE_PATH,
+            REFRESH_GRACE, RefreshToken, SESSION_MAX_AGE, SESSION_TTL,
+        },
         error::{AuthError, MyError},
-        types::MyResult,
+        mailer::Mail,
+        progression::{ProgressionInfo, recompute_progression},
+        types::{DbConn, MyResult},
     },
     db::{
         custom_types::UserRoles,
-        models::{result::Results, session::Session, user::User},
+        models::{
+            achievement::UserAchievement,
+            password_reset::PasswordReset,
+            result::Results,
+            room::{Room as RoomModel, UserRoomRow},
+            session::Session,
+            user::User,
+        },
     },
     utils,
 };
@@ -42,6 +54,83 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub access_token: String,
     pub token_type: String,
+    /// Seconds until the access token expires
+    pub expires_in: i64,
+    /// Single use, `/api/v1/user/refresh` returns the next one
+    pub refresh_token: String,
+}
+
+struct SessionTokens {
+    access_token: String,
+    refresh_token: String,
+    issued_at: NaiveDateTime,
+    /// Of the session, the refresh token is useless after it
+    expires_at: NaiveDateTime,
+}
+
+/// Shared by login and signup
+async fn start_session(
+    conn: &mut DbConn,
+    user_id: Uuid,
+    user_agent: &UserAgent,
+    addr: SocketAddr,
+) -> MyResult<SessionTokens> {
+    let created_at = chrono::Utc::now().naive_utc();
+    let expires_at = created_at + SESSION_TTL;
+    let session_id = Uuid::new_v4();
+
+    let access_token = Claims::access_token(user_id, session_id, created_at)?;
+    let (refresh_token, refresh_token_hash) = RefreshToken::generate(session_id);
+
+    let new_session = Session {
+        id: session_id,
+        user_id,
+        refresh_token_hash,
+        expires_at,
+        created_at,
+        user_agent: user_agent.to_string(),
+        ip: addr.ip().into(),
+    };
+
+    new_session.insert_session(conn).await?;
+
+    Ok(SessionTokens { access_token, refresh_token, issued_at: created_at, expires_at })
+}
+
+/// Web clients get both tokens as cookies, the others use the body
+fn session_response(jar: CookieJar, tokens: SessionTokens) -> (CookieJar, Json<LoginResponse>) {
+    let access_expires_at = tokens.issued_at + ACCESS_TOKEN_TTL;
+
+    let access_cookie = Cookie::build((COOKIE_NAME, tokens.access_token.clone()))
+        .path("/")
+        .http_only(true)
+        // TODO: use after live https server setup
+        // .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(ACCESS_TOKEN_TTL.num_seconds()))
+        .expires(
+            OffsetDateTime::from_unix_timestamp(access_expires_at.and_utc().timestamp()).unwrap(),
+        );
+
+    let refresh_cookie = Cookie::build((REFRESH_COOKIE_NAME, tokens.refresh_token.clone()))
+        .path(REFRESH_COOKIE_PATH)
+        .http_only(true)
+        // TODO: use after live https server setup
+        // .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(Duration::seconds((tokens.expires_at - tokens.issued_at).num_seconds()))
+        .expires(
+            OffsetDateTime::from_unix_timestamp(tokens.expires_at.and_utc().timestamp()).unwrap(),
+        );
+
+    let res = LoginResponse {
+        access_token: tokens.access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+        refresh_token: tokens.refresh_token,
+    };
+
+    (jar.add(access_cookie).add(refresh_cookie), Json(res))
 }
 
 #[utoipa::path(
@@ -78,43 +167,138 @@ pub async fn login(
         return Err(MyError::Auth(AuthError::InvalidToken));
     }
 
-    let created_at = chrono::Utc::now().naive_utc();
-    let expires_at = created_at + chrono::Duration::days(7);
-    let session_id = Uuid::new_v4();
+    let tokens = start_session(&mut conn, user.id, &user_agent, addr).await?;
+
+    Ok(session_response(jar, tokens))
+}
 
-    let claims = Claims {
-        sub: user.id,
-        company: COMPANY_NAME.to_string(),
-        exp: expires_at.and_utc().timestamp() as usize,
-        sid: session_id,
+#[derive(Default, Deserialize, utoipa::ToSchema)]
+pub struct RefreshRequest {
+    /// Web clients send the refresh cookie instead
+    refresh_token: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/refresh",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, description = "New access token and the next refresh token", body = LoginResponse),
+        (status = 401, description = "Refresh token invalid, expired or reused"),
+    )
+)]
+pub async fn refresh(
+    jar: CookieJar,
+    state: State<AppState>,
+    input: Option<Json<RefreshRequest>>,
+) -> MyResult<(CookieJar, Json<LoginResponse>)> {
+    let Json(input) = input.unwrap_or_default();
+
+    let token = input
+        .refresh_token
+        .or_else(|| jar.get(REFRESH_COOKIE_NAME).map(|cookie| cookie.value().to_string()))
+        .ok_or(AuthError::InvalidToken)?;
+    let token = RefreshToken::parse(&token).ok_or(AuthError::InvalidToken)?;
+
+    let mut conn = state.db().await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let Some(session) = Session::get_session(&mut conn, token.session_id)
+        .await?
+        .filter(|session| session.expires_at > now)
+    else {
+        return Err(MyError::Auth(AuthError::InvalidToken));
     };
 
-    let access_token = encode(&Header::default(), &claims, &KEYS.encoding)?;
+    let expires_at = if state.config.session_sliding {
+        (now + SESSION_TTL).min(session.created_at + SESSION_MAX_AGE)
+    } else {
+        session.expires_at
+    };
 
-    let new_session = Session {
-        id: session_id,
-        user_id: user.id,
-        token: access_token.clone(),
+    let (refresh_token, refresh_token_hash) = RefreshToken::generate(session.id);
+
+    let rotated = Session::rotate_refresh_token(
+        &mut conn,
+        session.id,
+        &token.secret_hash,
+        &refresh_token,
+        &refresh_token_hash,
         expires_at,
-        created_at,
-        user_agent: user_agent.to_string(),
-        ip: addr.ip().into(),
-    };
+    )
+    .await?;
 
-    new_session.insert_session(&mut conn).await?;
+    if !rotated {
+        // Lost the race to a refresh with the same token, both get the same next token
+        let successor = Session::get_refresh_token_successor(
+            &mut conn,
+            session.id,
+            &token.secret_hash,
+            now - REFRESH_GRACE,
+        )
+        .await?;
+        let current = Session::get_session(&mut conn, session.id).await?;
+
+        if let (Some(successor), Some(current)) = (successor, current)
+            && RefreshToken::parse(&successor)
+                .is_some_and(|next| next.secret_hash == current.refresh_token_hash)
+        {
+            let access_token = Claims::access_token(current.user_id, current.id, now)?;
+            let tokens = SessionTokens {
+                access_token,
+                refresh_token: successor,
+                issued_at: now,
+                expires_at: current.expires_at,
+            };
+
+            return Ok(session_response(jar, tokens));
+        }
+
+        // An already rotated token came back, whoever holds the session now may have stolen
+        // it. Unknown secrets are only rejected, the session id alone is no secret.
+        if Session::is_rotated_refresh_token(&mut conn, session.id, &token.secret_hash).await? {
+            log::warn!("Refresh token of session {} reused, revoking the session", session.id);
+            Session::delete_session(&mut conn, session.user_id, session.id).await?;
+        }
 
-    let cookie = Cookie::build((COOKIE_NAME, access_token.clone()))
-        .path("/")
-        .http_only(true)
-        // TODO: use after live https server setup
-        // .secure(true)
-        .same_site(SameSite::Lax)
-        .max_age(Duration::days(7))
-        .expires(OffsetDateTime::from_unix_timestamp(expires_at.and_utc().timestamp()).unwrap());
+        return Err(MyError::Auth(AuthError::InvalidToken));
+    }
 
-    let res = LoginResponse { access_token, token_type: "Bearer".to_string() };
+    let access_token = Claims::access_token(session.user_id, session.id, now)?;
+    let tokens = SessionTokens { access_token, refresh_token, issued_at: now, expires_at };
 
-    Ok((jar.add(cookie), Json(res)))
+    Ok(session_response(jar, tokens))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MessageResponse {
+    message: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/logout",
+    responses(
+        (status = 200, description = "Session deleted and cookie cleared", body = MessageResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn logout(
+    claims: Claims,
+    jar: CookieJar,
+    state: State<AppState>,
+) -> MyResult<(CookieJar, Json<MessageResponse>)> {
+    let mut conn = state.db().await?;
+
+    Session::delete_session(&mut conn, claims.sub, claims.sid).await?;
+
+    let res = MessageResponse { message: "Logged out".to_string() };
+
+    let jar = jar
+        .remove(Cookie::build(COOKIE_NAME).path("/"))
+        .remove(Cookie::build(REFRESH_COOKIE_NAME).path(REFRESH_COOKIE_PATH));
+
+    Ok((jar, Json(res)))
 }
 
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
@@ -124,6 +308,7 @@ pub struct ProfileResponse {
     email: String,
     created_at: NaiveDateTime,
     role: UserRoles,
+    progression: ProgressionInfo,
 }
 
 #[utoipa::path(
@@ -141,6 +326,7 @@ pub async fn profile(claims: Claims, state: State<AppState>) -> MyResult<Json<Pr
     let Some(user) = maybe_user else { return Err(MyError::InternalError) };
 
     let res = ProfileResponse {
+        progression: ProgressionInfo::from_user(&user),
         username: user.username,
         email: user.email,
         created_at: user.created_at,
@@ -192,52 +378,25 @@ pub async fn signup(
         password_hash,
         created_at: chrono::Utc::now().naive_utc(),
         role: UserRoles::User,
+        xp: 0,
+        level: 1,
+        streak_days: 0,
+        best_streak_days: 0,
+        last_active_on: None,
+        chat_muted_until: None,
     };
 
     let user = new_user.insert_user(&mut conn).await?;
 
-    let created_at = chrono::Utc::now().naive_utc();
-    let expires_at = created_at + chrono::Duration::days(7);
-    let session_id = Uuid::new_v4();
-
-    let claims = Claims {
-        sub: user.id,
-        company: COMPANY_NAME.to_string(),
-        exp: expires_at.and_utc().timestamp() as usize,
-        sid: session_id,
-    };
-
-    let access_token = encode(&Header::default(), &claims, &KEYS.encoding)?;
-
-    let new_session = Session {
-        id: session_id,
-        user_id: user.id,
-        token: access_token.clone(),
-        expires_at,
-        created_at,
-        user_agent: user_agent.to_string(),
-        ip: addr.ip().into(),
-    };
-
-    new_session.insert_session(&mut conn).await?;
+    let tokens = start_session(&mut conn, user.id, &user_agent, addr).await?;
 
-    let cookie = Cookie::build((COOKIE_NAME, access_token.clone()))
-        .path("/")
-        .http_only(true)
-        // TODO: use after live https server setup
-        // .secure(true)
-        .same_site(SameSite::Lax)
-        .max_age(Duration::days(7))
-        .expires(OffsetDateTime::from_unix_timestamp(expires_at.and_utc().timestamp()).unwrap());
-
-    let res = LoginResponse { access_token, token_type: "Bearer".to_string() };
-
-    Ok((jar.add(cookie), Json(res)))
+    Ok(session_response(jar, tokens))
 }
 
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 pub struct UserMeStats {
     results_count: i64,
+    progression: ProgressionInfo,
     last_result: Option<Results>,
     average_wpm: f64,
     average_cpm: f64,
@@ -272,6 +431,7 @@ pub async fn me_stats(claims: Claims, state: State<AppState>) -> MyResult<Json<U
 
     let res = UserMeStats {
         results_count,
+        progression: ProgressionInfo::from_user(&user),
         last_result: last_result.map(|r| r.0),
         average_wpm,
         average_cpm,
@@ -284,6 +444,7 @@ pub async fn me_stats(claims: Claims, state: State<AppState>) -> MyResult<Json<U
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 pub struct UserStats {
     results_count: i64,
+    progression: ProgressionInfo,
     last_result: Option<Results>,
     average_wpm: f64,
     average_cpm: f64,
@@ -322,6 +483,7 @@ pub async fn user_stats(
 
     let res = UserStats {
         results_count,
+        progression: ProgressionInfo::from_user(&user),
         last_result: last_result.map(|r| r.0),
         average_wpm,
         average_cpm,
@@ -331,11 +493,39 @@ pub async fn user_stats(
     Ok(Json(res))
 }
 
+#[derive(Serialize, Deserialize, utoipa::ToSchema)]
+pub struct UserAchievementInfo {
+    code: String,
+    name: String,
+    description: String,
+    unlocked_at: NaiveDateTime,
+}
+
 #[derive(Serialize, Deserialize, utoipa::ToSchema)]
 pub struct UserProfileResponse {
     username: String,
     created_at: NaiveDateTime,
     role: UserRoles,
+    achievements: Vec<UserAchievementInfo>,
+    progression: ProgressionInfo,
+}
+
+async fn get_achievements_info(
+    conn: &mut DbConn,
+    user_id: Uuid,
+) -> MyResult<Vec<UserAchievementInfo>> {
+    let achievements = UserAchievement::get_user_achievements_by_user_id(conn, user_id)
+        .await?
+        .into_iter()
+        .map(|(unlocked, achievement)| UserAchievementInfo {
+            code: achievement.code,
+            name: achievement.name,
+            description: achievement.description,
+            unlocked_at: unlocked.unlocked_at,
+        })
+        .collect();
+
+    Ok(achievements)
 }
 
 #[utoipa::path(
@@ -356,15 +546,60 @@ pub async fn user_profile(
     let maybe_user = User::get_user_by_username(&mut conn, &username).await?;
     let Some(user) = maybe_user else { return Err(MyError::NotFound) };
 
+    let achievements = get_achievements_info(&mut conn, user.id).await?;
+
     let res = UserProfileResponse {
+        progression: ProgressionInfo::from_user(&user),
         username: user.username,
         created_at: user.created_at,
         role: user.role,
+        achievements,
     };
 
     Ok(Json(res))
 }
 
+#[derive(Deserialize, utoipa::ToSchema, utoipa::IntoParams)]
+pub struct UserRoomsQuery {
+    #[serde(default)]
+    offset: i64,
+    // Defaults to 20 races
+    limit: Option<i64>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct UserRoomsResponse {
+    list: Vec<UserRoomRow>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/{username}/rooms",
+    params(UserRoomsQuery),
+    responses(
+        (status = 200, description = "Rooms the user took part in, newest first", body = UserRoomsResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn user_rooms(
+    _: Claims,
+    Path(username): Path<String>,
+    Query(params): Query<UserRoomsQuery>,
+    state: State<AppState>,
+) -> MyResult<Json<UserRoomsResponse>> {
+    let mut conn = state.db().await?;
+
+    let user = User::get_user_by_username(&mut conn, &username).await?.ok_or(MyError::NotFound)?;
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 100);
+    let offset = params.offset.max(0);
+
+    let list = RoomModel::get_user_rooms(&mut conn, user.id, offset, limit).await?;
+
+    Ok(Json(UserRoomsResponse { list }))
+}
+
 #[derive(Deserialize, utoipa::ToSchema)]
 pub struct PatchUser {
     role: UserRoles,
@@ -388,16 +623,388 @@ pub async fn patch_user(
     let mut conn = state.db().await?;
 
     let maybe_user = User::get_user_by_username(&mut conn, &username).await?;
-    let Some(mut user) = maybe_user else { return Err(MyError::NotFound) };
+    let Some(user) = maybe_user else { return Err(MyError::NotFound) };
 
-    user.role = input.role;
-    let user = user.update_user(&mut conn).await?;
+    // Only the role, a race finishing meanwhile writes XP and streaks
+    let user = User::set_role(&mut conn, user.id, input.role).await?;
+    let achievements = get_achievements_info(&mut conn, user.id).await?;
 
     let res = UserProfileResponse {
+        progression: ProgressionInfo::from_user(&user),
         username: user.username,
         created_at: user.created_at,
         role: user.role,
+        achievements,
+    };
+
+    Ok(Json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/{username}/progression/recompute",
+    responses(
+        (status = 200, description = "Success", body = ProgressionInfo),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn recompute_user_progression(
+    claims: Claims,
+    Path(username): Path<String>,
+    state: State<AppState>,
+) -> MyResult<Json<ProgressionInfo>> {
+    let mut conn = state.db().await?;
+
+    let Some(moderator) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
     };
 
+    if moderator.role != UserRoles::Creator && moderator.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    let maybe_user = User::get_user_by_username(&mut conn, &username).await?;
+    let Some(user) = maybe_user else { return Err(MyError::NotFound) };
+
+    let user = recompute_progression(&mut conn, user.id).await?;
+
+    Ok(Json(ProgressionInfo::from_user(&user)))
+}
+
+/// A year, longer mutes are a ban in disguise
+const MAX_MUTE_MINUTES: i64 = 525_600;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct MuteUserRequest {
+    /// Capped to 1..=525600
+    minutes: i64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MuteUserResponse {
+    chat_muted_until: Option<NaiveDateTime>,
+}
+
+/// Shared by mute and unmute, only moderators may change it. `None` minutes lifts the mute.
+async fn set_user_chat_mute(
+    claims: Claims,
+    username: &str,
+    state: &AppState,
+    minutes: Option<i64>,
+) -> MyResult<MuteUserResponse> {
+    let mut conn = state.db().await?;
+
+    let Some(moderator) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    if moderator.role != UserRoles::Creator && moderator.role != UserRoles::Moderator {
+        return Err(MyError::Unauthorized);
+    };
+
+    let until = match minutes {
+        Some(minutes) => {
+            let duration = chrono::Duration::minutes(minutes.clamp(1, MAX_MUTE_MINUTES));
+            let until = chrono::Utc::now().naive_utc().checked_add_signed(duration);
+
+            Some(until.ok_or(MyError::Validation("Mute ends too far away".to_string()))?)
+        },
+        None => None,
+    };
+
+    let maybe_user = User::get_user_by_username(&mut conn, username).await?;
+    let Some(user) = maybe_user else { return Err(MyError::NotFound) };
+
+    let user = User::set_chat_muted_until(&mut conn, user.id, until).await?;
+    state.rooms_manager.set_chat_mute(user.id, user.chat_muted_until).await;
+
+    Ok(MuteUserResponse { chat_muted_until: user.chat_muted_until })
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/user/{username}/mute",
+    request_body = MuteUserRequest,
+    responses(
+        (status = 200, description = "User muted in every room", body = MuteUserResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn mute_user(
+    claims: Claims,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    Json(input): Json<MuteUserRequest>,
+) -> MyResult<Json<MuteUserResponse>> {
+    Ok(Json(set_user_chat_mute(claims, &username, &state, Some(input.minutes)).await?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/{username}/mute",
+    responses(
+        (status = 200, description = "Mute lifted", body = MuteUserResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn unmute_user(
+    claims: Claims,
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+) -> MyResult<Json<MuteUserResponse>> {
+    Ok(Json(set_user_chat_mute(claims, &username, &state, None).await?))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SessionInfo {
+    id: Uuid,
+    user_agent: String,
+    ip: String,
+    created_at: NaiveDateTime,
+    expires_at: NaiveDateTime,
+    /// The session making this request
+    current: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SessionsResponse {
+    list: Vec<SessionInfo>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/me/sessions",
+    responses(
+        (status = 200, description = "Active sessions, newest first", body = SessionsResponse),
+        (status = 401, description = "Unauthorized"),
+    )
+)]
+pub async fn me_sessions(
+    claims: Claims,
+    state: State<AppState>,
+) -> MyResult<Json<SessionsResponse>> {
+    let mut conn = state.db().await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let sessions = Session::get_active_sessions(&mut conn, claims.sub, now).await?;
+
+    let list = sessions
+        .into_iter()
+        .map(|session| SessionInfo {
+            current: session.id == claims.sid,
+            id: session.id,
+            user_agent: session.user_agent,
+            ip: session.ip.ip().to_string(),
+            created_at: session.created_at,
+            expires_at: session.expires_at,
+        })
+        .collect();
+
+    Ok(Json(SessionsResponse { list }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/me/sessions/{session_id}",
+    responses(
+        (status = 200, description = "Session revoked", body = MessageResponse),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found"),
+    )
+)]
+pub async fn revoke_session(
+    claims: Claims,
+    Path(session_id): Path<Uuid>,
+    state: State<AppState>,
+) -> MyResult<Json<MessageResponse>> {
+    let mut conn = state.db().await?;
+
+    if !Session::delete_session(&mut conn, claims.sub, session_id).await? {
+        return Err(MyError::NotFound);
+    }
+
+    Ok(Json(MessageResponse { message: "Session revoked".to_string() }))
+}
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Reset links stop working after this long
+const PASSWORD_RESET_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+fn validate_new_password(password: &str) -> MyResult<()> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(MyError::Validation(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/me/password",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed, other sessions revoked", body = MessageResponse),
+        (status = 401, description = "Wrong current password"),
+    )
+)]
+pub async fn change_password(
+    claims: Claims,
+    state: State<AppState>,
+    Json(input): Json<ChangePasswordRequest>,
+) -> MyResult<Json<MessageResponse>> {
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user(&mut conn, claims.sub).await? else {
+        return Err(MyError::Unauthorized);
+    };
+
+    if utils::verify_password(input.current_password, user.password_hash).is_err() {
+        return Err(MyError::Auth(AuthError::WrongCredentials));
+    }
+
+    validate_new_password(&input.new_password)?;
+
+    let Ok(password_hash) = utils::hash_password(&input.new_password) else {
+        return Err(MyError::InternalError);
+    };
+
+    conn.transaction::<_, MyError, _>(|conn| {
+        async move {
+            User::set_password_hash(conn, user.id, password_hash).await?;
+            Session::delete_user_sessions(conn, user.id, Some(claims.sid)).await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(Json(MessageResponse { message: "Password changed".to_string() }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password/reset",
+    request_body = PasswordResetRequest,
+    responses(
+        (status = 200, description = "Reset link mailed if the email belongs to a user", body = MessageResponse),
+    )
+)]
+pub async fn request_password_reset(
+    state: State<AppState>,
+    Json(input): Json<PasswordResetRequest>,
+) -> MyResult<Json<MessageResponse>> {
+    // Same answer either way, the endpoint must not tell which emails are registered
+    let res = MessageResponse {
+        message: "If the email belongs to an account, a reset link is on its way".to_string(),
+    };
+
+    let mut conn = state.db().await?;
+
+    let Some(user) = User::get_user_by_email(&mut conn, &input.email).await? else {
+        return Ok(Json(res));
+    };
+
+    let created_at = chrono::Utc::now().naive_utc();
+    let token = utils::generate_token();
+
+    let reset = PasswordReset {
+        id: Uuid::new_v4(),
+        user_id: user.id,
+        token_hash: utils::hash_token(&token),
+        expires_at: created_at + PASSWORD_RESET_TTL,
+        used_at: None,
+        created_at,
+    };
+
+    reset.insert_reset(&mut conn).await?;
+
+    let link =
+        format!("{}/reset-password?token={}", state.config.web_url.trim_end_matches('/'), token);
+
+    let mail = Mail {
+        to: user.email,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nFollow this link within {} minutes to choose a new password:\n{}\n\n\
+             If you didn't ask for it, ignore this mail.",
+            user.username,
+            PASSWORD_RESET_TTL.num_minutes(),
+            link
+        ),
+    };
+
+    // Sent in the background, a slow answer would give registered emails away too
+    let mailer = state.mailer.clone();
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(mail).await {
+            log::error!("Password reset mail not sent: {:?}", err);
+        }
+    });
+
     Ok(Json(res))
 }
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct ConfirmPasswordResetRequest {
+    token: String,
+    new_password: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/password/reset/confirm",
+    request_body = ConfirmPasswordResetRequest,
+    responses(
+        (status = 200, description = "Password changed, every session revoked", body = MessageResponse),
+        (status = 401, description = "Reset token invalid, used or expired"),
+    )
+)]
+pub async fn confirm_password_reset(
+    state: State<AppState>,
+    Json(input): Json<ConfirmPasswordResetRequest>,
+) -> MyResult<Json<MessageResponse>> {
+    validate_new_password(&input.new_password)?;
+
+    let Ok(password_hash) = utils::hash_password(&input.new_password) else {
+        return Err(MyError::InternalError);
+    };
+
+    let mut conn = state.db().await?;
+
+    let token_hash = utils::hash_token(&input.token);
+    let now = chrono::Utc::now().naive_utc();
+
+    conn.transaction::<_, MyError, _>(|conn| {
+        async move {
+            let Some(reset) = PasswordReset::use_reset(conn, &token_hash, now).await? else {
+                return Err(MyError::Auth(AuthError::InvalidToken));
+            };
+
+            PasswordReset::spend_user_resets(conn, reset.user_id, now).await?;
+            User::set_password_hash(conn, reset.user_id, password_hash).await?;
+            // Whoever made the reset necessary may be logged in somewhere
+            Session::delete_user_sessions(conn, reset.user_id, None).await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(Json(MessageResponse { message: "Password changed".to_string() }))
+}
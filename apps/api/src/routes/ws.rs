@@ -2,20 +2,42 @@ use axum::{
     extract::{
         Path, State,
         connect_info::ConnectInfo,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
     },
     response::IntoResponse,
 };
 use axum_extra::{TypedHeader, headers};
+use chrono::Utc;
 use futures::{SinkExt, StreamExt};
-use std::net::SocketAddr;
-use tokio::sync::mpsc;
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
 use uuid::Uuid;
 
 use crate::app::state::AppState;
-use crate::app::{error::MyError, room::WsMessage};
+use crate::app::{
+    error::MyError,
+    outbound::PlayerSender,
+    protocol::{
+        ClientMessage, Encoding, ErrorCode, LEGACY_PROTOCOL_VERSION, Protocol, ServerMessage,
+        UNSUPPORTED_VERSION_CLOSE_CODE,
+    },
+    rate_limit::{MessageClass, Verdict},
+};
 use crate::{app::auth::Claims, db::models::user::User};
 
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// Silence after which a half-open connection is dropped, two missed pings
+const PONG_TIMEOUT: Duration = Duration::from_secs(35);
+/// How long a client gets to open with `Hello` before it's treated as legacy
+const HELLO_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[utoipa::path(
     get,
     path = "/api/v1/ws/room/{room_id}",
@@ -34,7 +56,7 @@ pub async fn ws_handler(
 ) -> impl IntoResponse {
     let user_agent = user_agent.as_ref().map(|ua| ua.as_str()).unwrap_or("Unknown agent");
 
-    if state.rooms_manager.rooms.read().await.get(&room_id).is_none() {
+    if !state.rooms_manager.has_room(room_id).await {
         return Err(MyError::NotFound);
     };
 
@@ -50,7 +72,7 @@ async fn handle_socket(
     state: State<AppState>,
     room_id: Uuid,
 ) {
-    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (tx, mut rx, overflow) = PlayerSender::channel();
 
     let mut conn = state.db().await.unwrap();
 
@@ -60,12 +82,75 @@ async fn handle_socket(
     };
 
     drop(conn);
-    let _ = state.rooms_manager.join_room(room_id, user, tx).await;
 
     let (mut ws_sender, mut ws_receiver) = ws.split();
 
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
+    // Handshake, clients that open with anything but `Hello` predate it
+    let min_version = state.config.ws_min_protocol_version;
+    let legacy = || (LEGACY_PROTOCOL_VERSION >= min_version).then(Protocol::legacy);
+    let mut pending = None;
+
+    let protocol = match tokio::time::timeout(HELLO_TIMEOUT, ws_receiver.next()).await {
+        Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => return,
+        Ok(Some(Ok(frame))) => match ClientMessage::decode(&frame) {
+            Some(Ok(ClientMessage::Hello { version, capabilities, encoding })) => {
+                Protocol::negotiate(version, &capabilities, encoding, min_version)
+            },
+            parsed => {
+                pending = parsed;
+                legacy()
+            },
+        },
+        Err(_) => legacy(),
+    };
+
+    let Some(protocol) = protocol else {
+        log::debug!("User {} rejected, protocol older than v{}", claims.sub, min_version);
+
+        let reason = format!("Protocol version {} or newer is required", min_version);
+        let message = ServerMessage::error(ErrorCode::UnsupportedVersion, reason.clone());
+        let close = CloseFrame { code: UNSUPPORTED_VERSION_CLOSE_CODE, reason: reason.into() };
+
+        let _ = ws_sender.send(message.to_frame(Encoding::Json)).await;
+        let _ = ws_sender.send(Message::Close(Some(close))).await;
+        return;
+    };
+
+    if protocol.version > LEGACY_PROTOCOL_VERSION {
+        let welcome = ServerMessage::Welcome {
+            version: protocol.version,
+            capabilities: protocol.capabilities.clone(),
+            encoding: protocol.encoding,
+        };
+        // Already in the negotiated encoding, like everything after it
+        if ws_sender.send(welcome.to_frame(protocol.encoding)).await.is_err() {
+            return;
+        }
+    }
+
+    // Follows the player into rematch rooms
+    let (room_binding, _) = watch::channel(room_id);
+
+    let pinger = tx.clone();
+    if let Err(e) =
+        state.rooms_manager.join_room(room_id, user, tx, room_binding.clone(), protocol).await
+    {
+        log::debug!("Failed to join room {}: {:?}", room_id, e);
+        return;
+    }
+
+    // A legacy client's first message arrived before it joined
+    match pending {
+        Some(Ok(msg)) => state.rooms_manager.handle_message(room_id, claims.sub, msg).await,
+        Some(Err(e)) => state.rooms_manager.handle_error(room_id, claims.sub, e).await,
+        None => {},
+    }
+
+    // Any frame from the client proves it is alive, browsers answer pings on their own
+    let last_seen = Arc::new(AtomicI64::new(Utc::now().timestamp_millis()));
+
+    let mut send_task = tokio::spawn(async move {
+        while let Some((msg, _)) = rx.recv().await {
             if ws_sender.send(msg).await.is_err() {
                 log::error!("Failed to send message to client");
                 break;
@@ -73,27 +158,83 @@ async fn handle_socket(
         }
     });
 
-    // Wait to start!
-    state.rooms_manager.wait_to_start_typing_session(room_id).await;
-    // Wait to start!
+    let seen = last_seen.clone();
+    let mut heartbeat_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let silent_ms = Utc::now().timestamp_millis() - seen.load(Ordering::Relaxed);
+            if silent_ms > PONG_TIMEOUT.as_millis() as i64 {
+                log::debug!("No pong for {} ms, dropping connection", silent_ms);
+                break;
+            }
+
+            pinger.send(Message::Ping(Default::default()), false);
+        }
+    });
+
+    let manager = state.rooms_manager.clone();
+    let binding = room_binding.clone();
+    let mut limiter = state.ws_limits.connect(claims.sub);
 
-    let recv_task = tokio::spawn(async move {
+    // Lobby messages are handled too, keystrokes are rejected until the race starts
+    let mut recv_task = tokio::spawn(async move {
         while let Some(Ok(msg)) = ws_receiver.next().await {
-            if let Message::Text(text) = msg {
-                match serde_json::from_str::<WsMessage>(&text) {
-                    Ok(msg) => state.rooms_manager.handle_message(room_id, claims.sub, msg).await,
-                    Err(e) => state.rooms_manager.handle_error(room_id, claims.sub, e).await,
+            last_seen.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+            let room_id = *binding.borrow();
+
+            if let Message::Close(_) = msg {
+                break;
+            }
+
+            if let Some(parsed) = ClientMessage::decode(&msg) {
+                let (class, cost) = match &parsed {
+                    Ok(ClientMessage::Keystroke { .. }) => (MessageClass::Keystroke, 1),
+                    Ok(ClientMessage::KeystrokeBatch { keys, .. }) => {
+                        (MessageClass::Keystroke, keys.len().max(1))
+                    },
+                    _ => (MessageClass::Control, 1),
+                };
+
+                // Checked before the room lock is taken or a parse error is answered
+                match limiter.check(class, cost) {
+                    Verdict::Allow => {},
+                    Verdict::Throttle { notify } => {
+                        if notify {
+                            let message =
+                                ServerMessage::error(ErrorCode::RateLimited, "Rate limit exceeded");
+                            manager
+                                ._send_message_to_user_in_room(room_id, claims.sub, message)
+                                .await;
+                        }
+                        continue;
+                    },
+                    Verdict::Disconnect => break,
+                }
+
+                match parsed {
+                    Ok(msg) => manager.handle_message(room_id, claims.sub, msg).await,
+                    Err(e) => manager.handle_error(room_id, claims.sub, e).await,
                 }
-            } else if let Message::Close(_) = msg {
-                state.rooms_manager.leave_room(room_id, claims.sub).await;
             }
         }
     });
 
     tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {}
+        _ = &mut send_task => {},
+        _ = &mut recv_task => {},
+        _ = &mut heartbeat_task => {},
+        _ = overflow.notified() => {},
     }
 
+    send_task.abort();
+    recv_task.abort();
+    heartbeat_task.abort();
+
+    let room_id = *room_binding.borrow();
+    state.rooms_manager.leave_room(room_id, claims.sub).await;
+
     log::debug!("Closed.");
 }
@@ -1,5 +1,9 @@
-use argon2::password_hash::{SaltString, rand_core::OsRng};
+use argon2::password_hash::{
+    SaltString,
+    rand_core::{OsRng, RngCore},
+};
 use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version};
+use sha2::{Digest, Sha256};
 
 pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
     let argon2_settings = get_argon2_settings();
@@ -22,3 +26,20 @@ pub fn verify_password(password: String, hash: String) -> Result<(), argon2::pas
 fn get_argon2_settings() -> Argon2<'static> {
     Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::new(1024 * 32, 1, 1, None).unwrap())
 }
+
+/// 256 random bits, hex encoded
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    to_hex(&bytes)
+}
+
+/// Generated tokens are too random to brute force, a fast hash is enough unlike for passwords
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}